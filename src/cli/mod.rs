@@ -0,0 +1,546 @@
+use std::{collections::HashMap, io::{self, IsTerminal, Write}, path::PathBuf};
+
+use ssh2::Session;
+
+use crate::{
+    connection::{build_targets, run_parallel_cancelable, CancelToken, ConnectionError, CANCELLED_EXIT_CODE, OutputStream, ParallelEvent, ServerStatus, ServerTarget, StepEvent, SSH},
+    parser::ConfigYaml,
+    secrets::{read_masked, PromptAnswers, SecretString},
+};
+
+mod vault;
+pub use vault::{decrypt_file, edit_file, encrypt_file, vault_passphrase};
+
+pub const USAGE: &str = "Uso: server_automation [opções] [comando]
+
+Comandos:
+  validate                Valida os arquivos de configuração e lista todos os problemas
+  list-servers            Lista os servidores configurados (ou os selecionados)
+  list-commands           Lista os comandos de todos os servidores (ou dos selecionados)
+  run                     Executa --command nos servidores selecionados, sem abrir a interface
+  encrypt <arquivo>       Cifra o arquivo de configuração (saída padrão: <arquivo>.enc)
+  decrypt <arquivo>       Decifra o arquivo para stdout (ou para --output)
+  edit <arquivo>          Abre o arquivo cifrado decifrado no $EDITOR e o cifra de novo ao salvar
+
+Opções:
+  -c, --config <arquivo>  Arquivo ou diretório de configuração (pode ser repetido para mesclar)
+  -s, --server <nome>     Seleciona um servidor (pode ser repetido)
+  -t, --tag <tag>         Seleciona os servidores com a tag (pode ser repetido)
+  -g, --group <grupo>     Seleciona os servidores do grupo (pode ser repetido)
+  -x, --command <nome>    Comando executado por run
+  --var <nome>=<valor>    Valor de um placeholder do comando (pode ser repetido)
+  -p, --parallel <n>      Máximo de servidores executando ao mesmo tempo (padrão: parallelism do arquivo)
+  -o, --output <arquivo>  Arquivo gerado por encrypt e decrypt
+  -h, --help              Mostra esta ajuda
+
+Sem --config, usa a variável SERVER_MANAGER_CONFIG (lista separada como no PATH) ou o primeiro
+arquivo existente entre ./config.yaml e $XDG_CONFIG_HOME/server_manager/config.yaml.
+Arquivos cifrados (.yaml.enc) usam a senha de SERVER_MANAGER_VAULT_PASSWORD ou pedem a senha
+no terminal.
+
+Em run, o código de saída é o da etapa que falhou (do primeiro servidor com falha), 2 para erros
+de uso e 255 se a conexão falhar.";
+
+/// Código de saída para argumentos ou nomes inválidos.
+pub const EXIT_USAGE: i32 = 2;
+/// Código de saída quando não foi possível conectar, como no `ssh`.
+pub const EXIT_CONNECTION: i32 = 255;
+
+/// Servidores escolhidos por nome, tag ou grupo.
+#[derive(Debug,PartialEq, Eq,Clone,Default)]
+pub struct ServerSelection {
+    pub servers: Vec<String>,
+    pub tags: Vec<String>,
+    pub groups: Vec<String>,
+}
+
+impl ServerSelection {
+    pub fn is_empty(&self) -> bool {
+        self.servers.is_empty() && self.tags.is_empty() && self.groups.is_empty()
+    }
+
+    /// Nomes dos servidores selecionados, sem repetição: primeiro os informados por nome,
+    /// depois os de cada tag e grupo na ordem do arquivo.
+    pub fn resolve(&self, config: &ConfigYaml) -> Result<Vec<String>,String> {
+        let mut names: Vec<String> = vec![];
+
+        for name in &self.servers {
+            if config.get_info_server(name).is_none() {
+                return Err(format!("Servidor não encontrado: {}",name));
+            }
+            names.push(name.clone());
+        }
+
+        for tag in &self.tags {
+            let servers = config.servers_with_tag(tag);
+            if servers.is_empty() {
+                return Err(format!("Nenhum servidor com a tag: {}",tag));
+            }
+            names.extend(servers.iter().map(|server| server.name.clone()));
+        }
+
+        for group in &self.groups {
+            let servers = config.servers_in_group(group);
+            if servers.is_empty() {
+                return Err(format!("Nenhum servidor no grupo: {}",group));
+            }
+            names.extend(servers.iter().map(|server| server.name.clone()));
+        }
+
+        let mut unique: Vec<String> = vec![];
+        for name in names {
+            if !unique.contains(&name) {
+                unique.push(name);
+            }
+        }
+        Ok(unique)
+    }
+}
+
+/// Subcomandos executados sem abrir a interface.
+#[derive(Debug,PartialEq, Eq,Clone)]
+pub enum Command {
+    Validate,
+    ListServers { selection: ServerSelection },
+    ListCommands { selection: ServerSelection },
+    Run { selection: ServerSelection, command: String, vars: Vec<(String,String)>, parallel: Option<usize> },
+    Encrypt { file: PathBuf, output: Option<PathBuf> },
+    Decrypt { file: PathBuf, output: Option<PathBuf> },
+    Edit { file: PathBuf },
+}
+
+/// Argumentos de linha de comando do binário.
+#[derive(Debug,PartialEq, Eq,Clone,Default)]
+pub struct Args {
+    pub config_paths: Vec<PathBuf>,
+    pub help: bool,
+    pub command: Option<Command>,
+}
+
+impl Args {
+    pub fn parse<I>(args: I) -> Result<Args,String>
+    where
+        I: IntoIterator<Item = String>,
+    {
+        let mut parsed = Args::default();
+        let mut args = args.into_iter();
+        let mut subcommand: Option<String> = None;
+        let mut selection = ServerSelection::default();
+        let mut command: Option<String> = None;
+        let mut vars: Vec<(String,String)> = vec![];
+        let mut parallel: Option<usize> = None;
+        let mut file: Option<PathBuf> = None;
+        let mut output: Option<PathBuf> = None;
+
+        while let Some(arg) = args.next() {
+            let (flag, inline_value) = match arg.split_once('=') {
+                Some((flag, value)) if flag.starts_with("--") => (flag.to_string(), Some(value.to_string())),
+                _ => (arg.clone(), None)
+            };
+
+            let mut value = |name: &str| match inline_value.clone().or_else(|| args.next()) {
+                Some(value) => Ok(value),
+                None => Err(format!("A opção {} exige {}",flag,name))
+            };
+
+            match flag.as_str() {
+                "-h" | "--help" => parsed.help = true,
+                "-c" | "--config" => parsed.config_paths.push(PathBuf::from(value("um arquivo")?)),
+                "-s" | "--server" => selection.servers.push(value("o nome do servidor")?),
+                "-t" | "--tag" => selection.tags.push(value("uma tag")?),
+                "-g" | "--group" => selection.groups.push(value("o nome do grupo")?),
+                "-x" | "--command" => command = Some(value("o nome do comando")?),
+                "--var" => {
+                    let var = value("nome=valor")?;
+                    match var.split_once('=') {
+                        Some((name, value)) if !name.is_empty() => vars.push((name.to_string(), value.to_string())),
+                        _ => return Err(format!("Variável inválida: {} (use nome=valor)",var))
+                    }
+                },
+                "-p" | "--parallel" => {
+                    let limit = value("um número")?;
+                    match limit.parse::<usize>() {
+                        Ok(limit) if limit > 0 => parallel = Some(limit),
+                        _ => return Err(format!("Valor inválido para {}: {}",flag,limit))
+                    }
+                },
+                "-o" | "--output" => output = Some(PathBuf::from(value("um arquivo")?)),
+                "validate" | "list-servers" | "list-commands" | "run" | "encrypt" | "decrypt" | "edit" if subcommand.is_none() => {
+                    subcommand = Some(arg)
+                },
+                _ if matches!(subcommand.as_deref(), Some("encrypt" | "decrypt" | "edit")) && file.is_none() && !arg.starts_with('-') => {
+                    file = Some(PathBuf::from(arg))
+                },
+                _ => return Err(format!("Argumento desconhecido: {}",arg))
+            }
+        }
+
+        parsed.command = match subcommand.as_deref() {
+            Some("run") if selection.is_empty() => return Err(String::from("run exige --server, --tag ou --group")),
+            Some("run") => Some(Command::Run {
+                selection: std::mem::take(&mut selection),
+                command: command.take().ok_or("run exige --command")?,
+                vars: std::mem::take(&mut vars),
+                parallel: parallel.take(),
+            }),
+            Some("list-commands") => Some(Command::ListCommands { selection: std::mem::take(&mut selection) }),
+            Some("list-servers") => Some(Command::ListServers { selection: std::mem::take(&mut selection) }),
+            Some("validate") => Some(Command::Validate),
+            Some(name @ ("encrypt" | "decrypt" | "edit")) => {
+                let file = file.take().ok_or_else(|| format!("{} exige o arquivo", name))?;
+                Some(match name {
+                    "encrypt" => Command::Encrypt { file, output: output.take() },
+                    "decrypt" => Command::Decrypt { file, output: output.take() },
+                    _ => Command::Edit { file },
+                })
+            },
+            _ => None
+        };
+
+        if output.is_some() {
+            return Err(String::from("--output só pode ser usado com encrypt ou decrypt"));
+        }
+
+        if !selection.is_empty() {
+            return Err(String::from("--server, --tag e --group só podem ser usados com run, list-servers ou list-commands"));
+        }
+
+        if command.is_some() || !vars.is_empty() || parallel.is_some() {
+            return Err(String::from("--command, --var e --parallel só podem ser usados com run"));
+        }
+
+        Ok(parsed)
+    }
+}
+
+/// Servidores da seleção, ou todos quando ela está vazia.
+fn selected_servers(config: &ConfigYaml, selection: &ServerSelection) -> Result<Vec<String>,String> {
+    if selection.is_empty() {
+        Ok(config.list_servers().iter().map(|server| server.name.clone()).collect())
+    } else {
+        selection.resolve(config)
+    }
+}
+
+pub fn list_servers(config: &ConfigYaml, selection: &ServerSelection) -> i32 {
+    let names = match selected_servers(config, selection) {
+        Ok(names) => names,
+        Err(e) => {
+            eprintln!("{}",e);
+            return EXIT_USAGE;
+        }
+    };
+
+    for server in config.list_servers().iter().filter(|item| names.contains(&item.name)) {
+        println!(
+            "{}\t{}\t{:?}\t{}\t{}",
+            server.name,
+            server.connect().ip_address(),
+            server.connect().type_connection(),
+            server.tags().join(","),
+            config.groups_of(&server.name).join(",")
+            );
+    }
+    0
+}
+
+pub fn list_commands(config: &ConfigYaml, selection: &ServerSelection) -> i32 {
+    let names = match selected_servers(config, selection) {
+        Ok(names) => names,
+        Err(e) => {
+            eprintln!("{}",e);
+            return EXIT_USAGE;
+        }
+    };
+
+    for server in config.list_servers().iter().filter(|item| names.contains(&item.name)) {
+        for command in server.effective_commands(config.commands()) {
+            let placeholders = command.placeholders()
+                                      .iter()
+                                      .map(|name| format!("{{{}}}",name))
+                                      .collect::<Vec<_>>()
+                                      .join(" ");
+            println!("{}\t{}\t{}",server.name,command.name(),placeholders);
+        }
+    }
+    0
+}
+
+/// Executa um comando sem interface. Com um servidor a saída remota é repassada para
+/// stdout/stderr enquanto chega; com vários, os servidores rodam em paralelo e a saída de cada
+/// um é impressa ao final. Retorna o código de saída do processo.
+pub fn run_command(config: &ConfigYaml, selection: &ServerSelection, command: &str, vars: &[(String,String)], parallel: Option<usize>) -> i32 {
+    let values: HashMap<String,String> = vars.iter().cloned().collect();
+    let targets = selection.resolve(config)
+                           .and_then(|servers| build_targets(config, &servers, command, &values));
+
+    let mut targets = match targets {
+        Ok(targets) => targets,
+        Err(e) => {
+            eprintln!("{}",e);
+            return EXIT_USAGE;
+        }
+    };
+
+    let labels = targets.iter()
+                        .flat_map(|target| target.connect.prompt_labels())
+                        .fold(vec![], |mut labels: Vec<String>, label| {
+                            if !labels.contains(&label) {
+                                labels.push(label);
+                            }
+                            labels
+                        });
+
+    let answers = match ask_prompt_secrets(&labels) {
+        Ok(answers) => answers,
+        Err(e) => {
+            eprintln!("{}",e);
+            return EXIT_USAGE;
+        }
+    };
+    for target in targets.iter_mut() {
+        target.prompt_answers = answers.clone();
+    }
+
+    let cancel = cancel_on_ctrl_c();
+
+    if targets.len() == 1 {
+        return run_single(&targets.remove(0), &cancel);
+    }
+
+    let runs = run_parallel_cancelable(targets, parallel.unwrap_or(config.parallelism()), &cancel, |event| {
+        if let ParallelEvent::Finished { run, .. } = event {
+            eprintln!("{}: {}",run.server(),status_label(run.status()));
+        }
+    });
+
+    for run in &runs {
+        println!("=== {} ({}, {:.1}s) ===",run.server(),status_label(run.status()),run.duration().as_secs_f64());
+        for (stream, text) in run.output() {
+            match stream {
+                OutputStream::Stdout => print!("{}",text),
+                OutputStream::Stderr => eprint!("{}",text),
+            }
+        }
+    }
+
+    runs.iter()
+        .map(|run| run_exit_code(run.status()))
+        .find(|code| *code != 0)
+        .unwrap_or(0)
+}
+
+/// Ctrl-C cancela a execução como o `c` da interface: a etapa em andamento é interrompida e as
+/// seguintes não começam. Um segundo Ctrl-C encerra o processo sem esperar.
+fn cancel_on_ctrl_c() -> CancelToken {
+    let cancel = CancelToken::default();
+
+    if let Ok(runtime) = tokio::runtime::Handle::try_current() {
+        let token = cancel.clone();
+        runtime.spawn(async move {
+            if tokio::signal::ctrl_c().await.is_ok() {
+                eprintln!("\nCancelando; pressione Ctrl-C de novo para sair imediatamente.");
+                token.cancel();
+            }
+            if tokio::signal::ctrl_c().await.is_ok() {
+                std::process::exit(CANCELLED_EXIT_CODE);
+            }
+        });
+    }
+    cancel
+}
+
+/// Pede no terminal, sem eco, as senhas configuradas como `prompt`.
+fn ask_prompt_secrets(labels: &[String]) -> Result<PromptAnswers,String> {
+    let mut answers = PromptAnswers::default();
+
+    if labels.is_empty() {
+        return Ok(answers);
+    }
+
+    if !io::stdin().is_terminal() {
+        return Err(format!(
+            "As senhas \"{}\" precisam ser digitadas; execute em um terminal ou use env:, file: ou cmd: em password",
+            labels.join("\", \"")
+            ));
+    }
+
+    for label in labels {
+        let value = read_masked(label).map_err(|e| format!("{}: {}",label,e))?;
+        answers.insert(label, SecretString::new(&value));
+    }
+    Ok(answers)
+}
+
+/// Abre a sessão perguntando no terminal se uma chave de host desconhecida (do servidor ou de um
+/// bastião) deve ser registrada. Sem terminal (scripts, CI) a chave desconhecida é recusada.
+/// Cada chave é perguntada uma vez: se ela continuar desconhecida depois de registrada, o erro é devolvido.
+fn open_interactive(ssh: &SSH) -> Result<Session,ConnectionError> {
+    let mut trusted: Vec<String> = vec![];
+
+    loop {
+        let error = match ssh.open_session() {
+            Ok(session) => return Ok(session),
+            Err(e) => e
+        };
+
+        let Some(key) = error.unknown_host_key()
+                             .filter(|key| io::stdin().is_terminal() && !trusted.contains(&key.fingerprint().to_string()))
+                             .cloned() else {
+            return Err(error);
+        };
+
+        eprint!(
+            "O servidor {}:{} não está no known_hosts.\n{} {}\nConfiar nesta chave? [s/N] ",
+            key.host(), key.port(), key.key_type(), key.fingerprint()
+            );
+
+        let mut answer = String::new();
+        let _ = io::stdin().read_line(&mut answer);
+        if !answer.trim().eq_ignore_ascii_case("s") {
+            return Err(error);
+        }
+
+        ssh.trust_host_key(&key)?;
+        trusted.push(key.fingerprint().to_string());
+    }
+}
+
+fn run_single(target: &ServerTarget, cancel: &CancelToken) -> i32 {
+    let ssh = SSH::new(&target.connect).with_prompt_answers(&target.prompt_answers);
+    let session = match open_interactive(&ssh) {
+        Ok(session) => session,
+        Err(e) => {
+            eprintln!("Não foi possivel conectar-se ao servidor: {}",e);
+            return EXIT_CONNECTION;
+        }
+    };
+
+    let result = ssh.execute_steps_cancelable(&target.command, session, cancel, |event| {
+        match event {
+            StepEvent::Started { command, .. } => eprintln!("$ {}",command),
+            StepEvent::Output { stream: OutputStream::Stdout, text, .. } => {
+                print!("{}",text);
+                let _ = std::io::stdout().flush();
+            },
+            StepEvent::Output { stream: OutputStream::Stderr, text, .. } => eprint!("{}",text),
+            StepEvent::Progress { .. } | StepEvent::Finished { .. } => {}
+        }
+    });
+
+    match result {
+        Ok(report) if report.is_cancelled() => CANCELLED_EXIT_CODE,
+        Ok(report) => match report.failed_step() {
+            None => 0,
+            Some(index) => run_exit_code(&ServerStatus::Failed(report.steps()[index].exit_code()))
+        },
+        Err(e) => {
+            eprintln!("Erro ao executar os comandos: {}",e);
+            EXIT_CONNECTION
+        }
+    }
+}
+
+pub fn status_label(status: &ServerStatus) -> String {
+    match status {
+        ServerStatus::Pending => String::from("aguardando"),
+        ServerStatus::Running => String::from("executando"),
+        ServerStatus::Succeeded => String::from("sucesso"),
+        ServerStatus::Failed(code) => format!("falhou com código {}",code),
+        ServerStatus::Error(message) => format!("erro: {}",message),
+        ServerStatus::Cancelled => String::from("cancelado"),
+    }
+}
+
+/// Código de saída do processo para o resultado de um servidor.
+fn run_exit_code(status: &ServerStatus) -> i32 {
+    match status {
+        ServerStatus::Succeeded => 0,
+        ServerStatus::Failed(code) if (1..=255).contains(code) => *code,
+        ServerStatus::Error(_) => EXIT_CONNECTION,
+        ServerStatus::Cancelled => CANCELLED_EXIT_CODE,
+        _ => 1
+    }
+}
+
+#[test]
+fn test_parse_config_args() {
+    let args = Args::parse(vec![
+        String::from("-c"),
+        String::from("config.yaml"),
+        String::from("--config=times/")
+    ]).unwrap();
+
+    assert_eq!(args.config_paths,vec![PathBuf::from("config.yaml"),PathBuf::from("times/")]);
+    assert!(!args.help);
+    assert_eq!(args.command, None);
+
+    let args = Args::parse(vec![String::from("validate"), String::from("-c"), String::from("a.yaml")]).unwrap();
+    assert_eq!(args.command, Some(Command::Validate));
+    assert_eq!(args.config_paths, vec![PathBuf::from("a.yaml")]);
+
+    assert!(Args::parse(vec![String::from("--config")]).is_err());
+    assert!(Args::parse(vec![String::from("--desconhecido")]).is_err());
+}
+
+#[test]
+fn test_parse_run_args() {
+    let args = Args::parse(vec![
+        String::from("run"),
+        String::from("--server"),
+        String::from("Servidor 1"),
+        String::from("--command=Atualizar Servidor"),
+        String::from("--var"),
+        String::from("url=https://example.com/repo.git?a=b"),
+    ]).unwrap();
+
+    assert_eq!(args.command, Some(Command::Run {
+        selection: ServerSelection { servers: vec![String::from("Servidor 1")], ..Default::default() },
+        command: String::from("Atualizar Servidor"),
+        vars: vec![(String::from("url"), String::from("https://example.com/repo.git?a=b"))],
+        parallel: None,
+    }));
+
+    let args = Args::parse(vec![
+        String::from("run"), String::from("-s"), String::from("Servidor 1"), String::from("-s"), String::from("Servidor 2"),
+        String::from("-x"), String::from("Deploy"), String::from("--parallel=8"),
+    ]).unwrap();
+    assert!(matches!(args.command, Some(Command::Run { ref selection, parallel: Some(8), .. }) if selection.servers.len() == 2));
+    assert!(Args::parse(vec![String::from("run"), String::from("-p"), String::from("0")]).is_err());
+
+    assert!(Args::parse(vec![String::from("run"), String::from("-s"), String::from("Servidor 1")]).is_err());
+    assert!(Args::parse(vec![String::from("validate"), String::from("-s"), String::from("Servidor 1")]).is_err());
+    assert!(Args::parse(vec![String::from("list-servers"), String::from("-x"), String::from("Deploy")]).is_err());
+    assert!(Args::parse(vec![String::from("run"), String::from("--var"), String::from("sem_valor")]).is_err());
+}
+
+#[test]
+fn test_parse_vault_args() {
+    let args = Args::parse(vec![String::from("encrypt"), String::from("config.yaml"), String::from("-o"), String::from("prod.yaml.enc")]).unwrap();
+    assert_eq!(args.command, Some(Command::Encrypt {
+        file: PathBuf::from("config.yaml"),
+        output: Some(PathBuf::from("prod.yaml.enc")),
+    }));
+
+    let args = Args::parse(vec![String::from("edit"), String::from("config.yaml.enc")]).unwrap();
+    assert_eq!(args.command, Some(Command::Edit { file: PathBuf::from("config.yaml.enc") }));
+
+    assert!(Args::parse(vec![String::from("decrypt")]).is_err());
+    assert!(Args::parse(vec![String::from("edit"), String::from("a.enc"), String::from("-o"), String::from("b")]).is_err());
+    assert!(Args::parse(vec![String::from("validate"), String::from("config.yaml")]).is_err());
+}
+
+#[test]
+fn test_selection_resolves_tags_and_groups() {
+    let config = ConfigYaml::new("config.yaml").unwrap();
+
+    let selection = ServerSelection {
+        servers: vec![String::from("Servidor 2")],
+        tags: vec![String::from("web")],
+        groups: vec![String::from("Produção")],
+    };
+    assert_eq!(selection.resolve(&config).unwrap(), vec!["Servidor 2", "Servidor 1"]);
+
+    let selection = ServerSelection { tags: vec![String::from("inexistente")], ..Default::default() };
+    assert!(selection.resolve(&config).is_err());
+}
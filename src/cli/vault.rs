@@ -0,0 +1,279 @@
+use std::{
+    env,
+    fs::{self, DirBuilder, OpenOptions},
+    io::{self, IsTerminal, Write},
+    os::unix::fs::{DirBuilderExt, OpenOptionsExt},
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use openssl::rand::rand_bytes;
+
+use crate::{
+    parser::{decrypt, encrypt, is_vault, passphrase_from_env, validate_str, ConfigYaml, VAULT_EXTENSION, VAULT_PASSWORD_ENV},
+    secrets::{read_masked, SecretString},
+};
+
+/// Senha do cofre: `SERVER_MANAGER_VAULT_PASSWORD` ou digitada no terminal. Com `confirm` a senha
+/// digitada é pedida duas vezes, para cifrar.
+pub fn vault_passphrase(confirm: bool) -> Result<SecretString,String> {
+    if let Some(passphrase) = passphrase_from_env() {
+        return Ok(passphrase);
+    }
+
+    if !io::stdin().is_terminal() {
+        return Err(format!("Defina {} ou execute em um terminal para digitar a senha do arquivo cifrado", VAULT_PASSWORD_ENV));
+    }
+
+    let passphrase = read_masked("Senha do arquivo cifrado").map_err(|e| e.to_string())?;
+    if passphrase.is_empty() {
+        return Err(String::from("A senha não pode ser vazia"));
+    }
+
+    if confirm && read_masked("Confirme a senha").map_err(|e| e.to_string())? != passphrase {
+        return Err(String::from("As senhas não conferem"));
+    }
+
+    Ok(SecretString::new(&passphrase))
+}
+
+/// Cifra um arquivo de configuração. Sem `output`, grava em `<arquivo>.enc`.
+pub fn encrypt_file(file: &Path, output: Option<&Path>) -> i32 {
+    let content = match fs::read_to_string(file) {
+        Ok(content) if is_vault(&content) => {
+            eprintln!("{} já está cifrado", file.display());
+            return 1;
+        },
+        Ok(content) => content,
+        Err(e) => {
+            eprintln!("Erro ao ler o arquivo {}: {}", file.display(), e);
+            return 1;
+        }
+    };
+
+    if !check_config(file, &content) {
+        return 1;
+    }
+
+    let output = output.map(Path::to_path_buf)
+                       .unwrap_or_else(|| PathBuf::from(format!("{}.{}", file.display(), VAULT_EXTENSION)));
+
+    let result = vault_passphrase(true).and_then(|passphrase| encrypt(&content, &passphrase).map_err(|e| e.to_string()))
+                                       .and_then(|encrypted| write_private(&output, &encrypted).map_err(|e| e.to_string()));
+
+    match result {
+        Ok(()) => {
+            eprintln!("Arquivo cifrado gravado em {}", output.display());
+            0
+        },
+        Err(e) => {
+            eprintln!("{}", e);
+            1
+        }
+    }
+}
+
+/// Decifra para stdout ou, com `output`, para um arquivo legível só pelo usuário.
+pub fn decrypt_file(file: &Path, output: Option<&Path>) -> i32 {
+    let result = read_vault(file).and_then(|(content, _)| match output {
+        Some(output) => write_private(output, &content).map_err(|e| e.to_string()),
+        None => {
+            print!("{}", content);
+            io::stdout().flush().map_err(|e| e.to_string())
+        }
+    });
+
+    match result {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("{}", e);
+            1
+        }
+    }
+}
+
+/// Edita o conteúdo decifrado em um arquivo temporário com `$VISUAL`/`$EDITOR` e cifra de novo
+/// com a mesma senha. YAML que não corresponde à configuração não é gravado; o usuário pode voltar ao editor.
+pub fn edit_file(file: &Path) -> i32 {
+    let (content, passphrase) = match read_vault(file) {
+        Ok(vault) => vault,
+        Err(e) => {
+            eprintln!("{}", e);
+            return 1;
+        }
+    };
+
+    // O diretório é removido quando `dir` sai de escopo, em qualquer caminho de saída.
+    let dir = match PrivateDir::create() {
+        Ok(dir) => dir,
+        Err(e) => {
+            eprintln!("Erro ao criar o diretório temporário: {}", e);
+            return 1;
+        }
+    };
+
+    let name = file.file_name()
+                   .map(|name| name.to_string_lossy().trim_end_matches(&format!(".{}", VAULT_EXTENSION)).to_string())
+                   .unwrap_or_else(|| String::from("config.yaml"));
+    edit_in(&dir.path().join(name), file, &content, &passphrase)
+}
+
+/// Diretório com nome aleatório e permissão 0700 para o conteúdo decifrado. A criação falha se
+/// o caminho já existir, então outro usuário não consegue preparar um link simbólico nele.
+struct PrivateDir(PathBuf);
+
+impl PrivateDir {
+    fn create() -> io::Result<PrivateDir> {
+        let path = env::temp_dir().join(format!("server_manager_{}", random_suffix()?));
+        DirBuilder::new().mode(0o700).create(&path)?;
+        Ok(PrivateDir(path))
+    }
+
+    fn path(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for PrivateDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+fn random_suffix() -> io::Result<String> {
+    let mut bytes = [0u8; 12];
+    rand_bytes(&mut bytes).map_err(io::Error::other)?;
+    Ok(bytes.iter().map(|byte| format!("{:02x}", byte)).collect())
+}
+
+fn edit_in(temp: &Path, file: &Path, content: &str, passphrase: &SecretString) -> i32 {
+    if let Err(e) = write_private(temp, content) {
+        eprintln!("Erro ao criar o arquivo temporário: {}", e);
+        return 1;
+    }
+
+    let edited = loop {
+        if let Err(e) = run_editor(temp) {
+            eprintln!("{}", e);
+            return 1;
+        }
+
+        let edited = match fs::read_to_string(temp) {
+            Ok(edited) => edited,
+            Err(e) => {
+                eprintln!("Erro ao ler o arquivo temporário: {}", e);
+                return 1;
+            }
+        };
+
+        if check_config(file, &edited) {
+            break edited;
+        }
+
+        eprint!("Editar novamente? [S/n] ");
+        let mut answer = String::new();
+        let _ = io::stdin().read_line(&mut answer);
+        if answer.trim().eq_ignore_ascii_case("n") {
+            eprintln!("Nenhuma alteração gravada");
+            return 1;
+        }
+    };
+
+    if edited == content {
+        eprintln!("Nenhuma alteração");
+        return 0;
+    }
+
+    match encrypt(&edited, passphrase).map_err(|e| e.to_string())
+                                       .and_then(|encrypted| write_private(file, &encrypted).map_err(|e| e.to_string())) {
+        Ok(()) => {
+            eprintln!("{} atualizado", file.display());
+            0
+        },
+        Err(e) => {
+            eprintln!("{}", e);
+            1
+        }
+    }
+}
+
+/// Conteúdo decifrado e a senha usada.
+fn read_vault(file: &Path) -> Result<(String,SecretString),String> {
+    let content = fs::read_to_string(file).map_err(|e| format!("Erro ao ler o arquivo {}: {}", file.display(), e))?;
+    if !is_vault(&content) {
+        return Err(format!("{} não está cifrado", file.display()));
+    }
+
+    let passphrase = vault_passphrase(false)?;
+    let content = decrypt(&content, &passphrase).map_err(|e| e.to_string())?;
+    Ok((content, passphrase))
+}
+
+/// Exige que o conteúdo seja lido pelo mesmo modelo usado ao carregar e imprime os problemas
+/// encontrados pela validação, que não impedem a gravação.
+fn check_config(file: &Path, content: &str) -> bool {
+    let diagnostics = validate_str(file, content);
+    for diagnostic in &diagnostics {
+        eprintln!("{}", diagnostic);
+    }
+    serde_yaml_ng::from_str::<ConfigYaml>(content).is_ok()
+}
+
+fn run_editor(path: &Path) -> Result<(),String> {
+    let editor = env::var("VISUAL").or_else(|_| env::var("EDITOR"))
+                                   .unwrap_or_else(|_| String::from("vi"));
+
+    let status = Command::new("sh").arg("-c")
+                                   .arg(format!("{} \"$1\"", editor))
+                                   .arg("sh")
+                                   .arg(path)
+                                   .status()
+                                   .map_err(|e| format!("Não foi possivel abrir o editor {}: {}", editor, e))?;
+
+    if status.success() {
+        Ok(())
+    } else {
+        Err(format!("O editor {} terminou com {}", editor, status))
+    }
+}
+
+/// Grava com permissão 0600 em um arquivo novo ao lado do destino e o renomeia, para que uma
+/// falha no meio não deixe o arquivo original truncado. O arquivo parcial tem nome aleatório e é
+/// criado com `create_new`: um link simbólico ou arquivo já existente no caminho faz a gravação falhar.
+fn write_private(path: &Path, content: &str) -> io::Result<()> {
+    let partial = PathBuf::from(format!("{}.{}.tmp", path.display(), random_suffix()?));
+
+    let result = OpenOptions::new().write(true)
+                                   .create_new(true)
+                                   .mode(0o600)
+                                   .open(&partial)
+                                   .and_then(|mut file| {
+                                       file.write_all(content.as_bytes())?;
+                                       file.sync_all()
+                                   })
+                                   .and_then(|_| fs::rename(&partial, path));
+
+    if result.is_err() {
+        let _ = fs::remove_file(&partial);
+    }
+    result
+}
+
+#[test]
+fn test_private_files() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = PrivateDir::create().unwrap();
+    let path = dir.path().to_path_buf();
+    assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o700);
+
+    let file = path.join("config.yaml");
+    write_private(&file, "segredo").unwrap();
+    write_private(&file, "novo segredo").unwrap();
+    assert_eq!(fs::read_to_string(&file).unwrap(), "novo segredo");
+    assert_eq!(fs::metadata(&file).unwrap().permissions().mode() & 0o777, 0o600);
+    assert_eq!(fs::read_dir(&path).unwrap().count(), 1);
+
+    drop(dir);
+    assert!(!path.exists());
+}
@@ -0,0 +1,229 @@
+use std::{
+    io::{self, Read, Seek, SeekFrom},
+    path::Path,
+};
+
+use ssh2::Session;
+
+/// Quantidade máxima lida de um arquivo para a pré-visualização ou para o `tail`.
+pub const PREVIEW_BYTES: u64 = 64 * 1024;
+
+/// Item de um diretório remoto listado pelo SFTP.
+#[derive(Debug,PartialEq, Eq,Clone)]
+pub struct RemoteEntry {
+    name: String,
+    path: String,
+    is_dir: bool,
+    size: Option<u64>,
+    perm: Option<u32>,
+}
+
+impl RemoteEntry {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Caminho absoluto no servidor.
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Diretório, ou link simbólico que aponta para um.
+    pub fn is_dir(&self) -> bool {
+        self.is_dir
+    }
+
+    pub fn size(&self) -> Option<u64> {
+        self.size
+    }
+
+    pub fn perm(&self) -> Option<u32> {
+        self.perm
+    }
+}
+
+/// Trecho de um arquivo remoto lido para exibição: o começo, o final no `tail` ou, ao acompanhar
+/// o arquivo, só o que foi acrescentado desde a última leitura.
+#[derive(Debug,PartialEq, Eq,Clone)]
+pub struct FilePreview {
+    path: String,
+    text: String,
+    size: Option<u64>,
+    tail: bool,
+    truncated: bool,
+    binary: bool,
+    appended: bool,
+    end: u64,
+}
+
+impl FilePreview {
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Conteúdo lido; vazio quando o arquivo é binário.
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    pub fn size(&self) -> Option<u64> {
+        self.size
+    }
+
+    pub fn is_tail(&self) -> bool {
+        self.tail
+    }
+
+    /// O arquivo é maior que `PREVIEW_BYTES` e só parte dele foi lida.
+    pub fn is_truncated(&self) -> bool {
+        self.truncated
+    }
+
+    pub fn is_binary(&self) -> bool {
+        self.binary
+    }
+
+    /// O texto continua o que já foi exibido, lido por `read_appended`.
+    pub fn is_appended(&self) -> bool {
+        self.appended
+    }
+
+    /// Posição no arquivo logo depois da última linha completa lida; a próxima leitura começa aqui.
+    pub fn end(&self) -> u64 {
+        self.end
+    }
+}
+
+/// Lista o diretório `path` (aceita `.` e `..`) e devolve o caminho absoluto junto dos itens,
+/// diretórios primeiro e cada grupo em ordem alfabética.
+pub(crate) fn list_dir(session: &Session, path: &str) -> io::Result<(String,Vec<RemoteEntry>)> {
+    let sftp = session.sftp()?;
+    let directory = sftp.realpath(Path::new(path)).map_err(|e| with_path(e.into(), path))?;
+
+    let mut entries: Vec<RemoteEntry> = sftp.readdir(&directory)
+                                            .map_err(|e| with_path(e.into(), path))?
+                                            .into_iter()
+                                            .filter_map(|(entry_path, stat)| {
+                                                let name = entry_path.file_name()?.to_string_lossy().to_string();
+                                                // O readdir não segue links; o destino decide se é diretório.
+                                                let is_dir = if stat.file_type().is_symlink() {
+                                                    sftp.stat(&entry_path).map(|target| target.is_dir()).unwrap_or(false)
+                                                } else {
+                                                    stat.is_dir()
+                                                };
+
+                                                Some(RemoteEntry {
+                                                    name,
+                                                    path: entry_path.to_string_lossy().to_string(),
+                                                    is_dir,
+                                                    size: stat.size,
+                                                    perm: stat.perm,
+                                                })
+                                            })
+                                            .collect();
+
+    entries.sort_by(|a, b| b.is_dir.cmp(&a.is_dir).then_with(|| a.name.cmp(&b.name)));
+    Ok((directory.to_string_lossy().to_string(), entries))
+}
+
+/// Lê até `PREVIEW_BYTES` do começo do arquivo ou, com `tail`, do final.
+pub(crate) fn read_preview(session: &Session, path: &str, tail: bool) -> io::Result<FilePreview> {
+    let sftp = session.sftp()?;
+    let mut file = sftp.open(Path::new(path)).map_err(|e| with_path(e.into(), path))?;
+    let size = file.stat().ok().and_then(|stat| stat.size);
+
+    let truncated = size.is_some_and(|size| size > PREVIEW_BYTES);
+    let start = match tail && truncated {
+        true => file.seek(SeekFrom::End(-(PREVIEW_BYTES as i64)))?,
+        false => 0
+    };
+
+    let mut bytes = vec![];
+    file.take(PREVIEW_BYTES).read_to_end(&mut bytes).map_err(|e| with_path(e, path))?;
+
+    // No `tail` a linha final incompleta fica para a próxima leitura de `read_appended`.
+    if tail {
+        bytes.truncate(complete_lines(&bytes));
+    }
+
+    let (text, binary) = preview_text(&bytes, tail && truncated);
+    Ok(FilePreview {
+        path: path.to_string(),
+        text,
+        size,
+        tail,
+        truncated,
+        binary,
+        appended: false,
+        end: start + bytes.len() as u64,
+    })
+}
+
+/// Lê as linhas completas acrescentadas ao arquivo a partir de `offset`. Se o arquivo diminuiu
+/// (rotacionado ou truncado), faz uma leitura do `tail` do zero.
+pub(crate) fn read_appended(session: &Session, path: &str, offset: u64) -> io::Result<FilePreview> {
+    let sftp = session.sftp()?;
+    let mut file = sftp.open(Path::new(path)).map_err(|e| with_path(e.into(), path))?;
+    let size = file.stat().map_err(|e| with_path(e.into(), path))?.size.unwrap_or_default();
+
+    if size < offset {
+        return read_preview(session, path, true);
+    }
+
+    // Com mais de `PREVIEW_BYTES` novos, só o final é exibido, como no `tail` inicial.
+    let truncated = size - offset > PREVIEW_BYTES;
+    let start = if truncated { size - PREVIEW_BYTES } else { offset };
+    file.seek(SeekFrom::Start(start))?;
+
+    let mut bytes = vec![];
+    file.take(size - start).read_to_end(&mut bytes).map_err(|e| with_path(e, path))?;
+    bytes.truncate(complete_lines(&bytes));
+
+    let (text, binary) = preview_text(&bytes, truncated);
+    Ok(FilePreview {
+        path: path.to_string(),
+        text,
+        size: Some(size),
+        tail: true,
+        truncated,
+        binary,
+        appended: true,
+        end: start + bytes.len() as u64,
+    })
+}
+
+/// Tamanho do trecho até a última quebra de linha, para não cortar uma linha (ou um caractere
+/// UTF-8) que ainda está sendo escrita.
+fn complete_lines(bytes: &[u8]) -> usize {
+    bytes.iter().rposition(|byte| *byte == b'\n').map_or(0, |position| position + 1)
+}
+
+/// Converte o trecho lido em texto. Um byte nulo indica arquivo binário; no `tail` cortado no meio
+/// de um arquivo, a primeira linha incompleta é descartada.
+fn preview_text(bytes: &[u8], starts_mid_file: bool) -> (String,bool) {
+    if bytes.contains(&0) {
+        return (String::new(), true);
+    }
+
+    let text = String::from_utf8_lossy(bytes);
+    let text = match text.find('\n') {
+        Some(position) if starts_mid_file => &text[position + 1..],
+        _ => &text[..]
+    };
+    (text.to_string(), false)
+}
+
+fn with_path(error: io::Error, path: &str) -> io::Error {
+    io::Error::new(error.kind(), format!("{}: {}", path, error))
+}
+
+#[test]
+fn test_preview_text() {
+    assert_eq!(preview_text(b"linha 1\nlinha 2\n", false), (String::from("linha 1\nlinha 2\n"), false));
+    assert_eq!(preview_text(b"ha 1\nlinha 2\n", true), (String::from("linha 2\n"), false));
+    assert_eq!(preview_text(b"\x7fELF\x00\x01", false), (String::new(), true));
+
+    assert_eq!(complete_lines(b"linha 1\nlinha 2\nlin"), 16);
+    assert_eq!(complete_lines("ação\n".as_bytes()), 7);
+    assert_eq!(complete_lines(b"sem quebra"), 0);
+}
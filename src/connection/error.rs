@@ -0,0 +1,181 @@
+use std::{fmt, io, path::PathBuf};
+
+use crate::{parser::HostKeyPolicy, secrets::SecretError};
+
+use super::HostKey;
+
+/// Código do libssh2 para operações que excederam o tempo limite da sessão.
+const LIBSSH2_ERROR_TIMEOUT: i32 = -9;
+
+/// Falhas ao conectar ou conversar com o servidor, na ordem em que podem acontecer.
+#[derive(Debug)]
+pub enum ConnectionError {
+    /// O host não pôde ser resolvido.
+    Dns { host: String, source: io::Error },
+    /// Nenhum dos endereços do host aceitou a conexão TCP.
+    Tcp { address: String, source: io::Error },
+    Handshake(ssh2::Error),
+    /// Chave do servidor ausente do `known_hosts`; com `HostKeyPolicy::Ask` o usuário pode confiar nela.
+    UnknownHostKey { key: HostKey, policy: HostKeyPolicy },
+    /// Chave diferente da registrada no `known_hosts` ou da `host_key_fingerprint` esperada.
+    HostKeyMismatch { key: HostKey, expected: Option<String> },
+    KnownHosts { path: PathBuf, source: io::Error },
+    Auth { user: String, source: ssh2::Error },
+    /// Senha que não pôde ser obtida da origem indicada em `password`.
+    Secret(SecretError),
+    /// `location` ausente ou apontando para um arquivo que não existe.
+    KeyNotFound { path: Option<PathBuf> },
+    /// `operation` descreve o que estava em andamento.
+    Timeout { operation: String },
+    /// Falha ao conectar no bastião `name` usado como `jump_host`.
+    JumpHost { name: String, source: Box<ConnectionError> },
+    /// O bastião não abriu o encaminhamento até o servidor (ou a cadeia de `jump_host` é inválida).
+    Tunnel { jump_host: String, target: String, source: io::Error },
+    /// Erro ao abrir o canal ou ao trocar dados depois de conectado.
+    Channel(io::Error),
+}
+
+impl ConnectionError {
+    /// Indica se tentar de novo pode resolver (rede instável, servidor reiniciando).
+    pub fn is_retryable(&self) -> bool {
+        if let ConnectionError::JumpHost { source, .. } = self {
+            return source.is_retryable();
+        }
+
+        !matches!(
+            self,
+            ConnectionError::KeyNotFound { .. }
+                | ConnectionError::Auth { .. }
+                | ConnectionError::Secret(_)
+                | ConnectionError::UnknownHostKey { .. }
+                | ConnectionError::HostKeyMismatch { .. }
+                | ConnectionError::KnownHosts { .. }
+        )
+    }
+
+    /// Chave desconhecida que o usuário pode aceitar (`host_key_check: ask`), inclusive a de um bastião.
+    pub fn unknown_host_key(&self) -> Option<&HostKey> {
+        match self {
+            ConnectionError::UnknownHostKey { key, policy: HostKeyPolicy::Ask } => Some(key),
+            ConnectionError::JumpHost { source, .. } => source.unknown_host_key(),
+            _ => None
+        }
+    }
+
+    /// Usuário ou senha recusados, pelo servidor ou por um bastião da cadeia.
+    pub fn is_auth_failure(&self) -> bool {
+        match self {
+            ConnectionError::Auth { .. } => true,
+            ConnectionError::JumpHost { source, .. } => source.is_auth_failure(),
+            _ => false
+        }
+    }
+
+    pub(crate) fn handshake(error: ssh2::Error) -> Self {
+        if is_timeout(&error) {
+            ConnectionError::Timeout { operation: String::from("handshake SSH") }
+        } else {
+            ConnectionError::Handshake(error)
+        }
+    }
+
+    pub(crate) fn auth(user: &str, error: ssh2::Error) -> Self {
+        if is_timeout(&error) {
+            ConnectionError::Timeout { operation: String::from("autenticação") }
+        } else {
+            ConnectionError::Auth { user: user.to_string(), source: error }
+        }
+    }
+}
+
+fn is_timeout(error: &ssh2::Error) -> bool {
+    error.code() == ssh2::ErrorCode::Session(LIBSSH2_ERROR_TIMEOUT)
+}
+
+impl fmt::Display for ConnectionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConnectionError::Dns { host, source } => write!(f, "Não foi possivel resolver o host {}: {}", host, source),
+            ConnectionError::Tcp { address, source } => write!(f, "Não foi possivel conectar em {}: {}", address, source),
+            ConnectionError::Handshake(source) => write!(f, "Falha no handshake SSH: {}", source),
+            ConnectionError::UnknownHostKey { key, policy: HostKeyPolicy::Strict } => write!(
+                f, "Chave do servidor {}:{} não está no known_hosts ({} {}); recusada por host_key_check: strict",
+                key.host(), key.port(), key.key_type(), key.fingerprint()
+                ),
+            ConnectionError::UnknownHostKey { key, .. } => write!(
+                f, "Servidor {}:{} desconhecido, chave {} {}",
+                key.host(), key.port(), key.key_type(), key.fingerprint()
+                ),
+            ConnectionError::HostKeyMismatch { key, expected: Some(expected) } => write!(
+                f, "Chave do servidor {}:{} ({}) diferente da host_key_fingerprint {}; conexão recusada",
+                key.host(), key.port(), key.fingerprint(), expected
+                ),
+            ConnectionError::HostKeyMismatch { key, expected: None } => write!(
+                f, "A chave do servidor {}:{} mudou ({}) e não confere com o known_hosts; conexão recusada",
+                key.host(), key.port(), key.fingerprint()
+                ),
+            ConnectionError::JumpHost { name, source } => write!(f, "Falha no jump host \"{}\": {}", name, source),
+            ConnectionError::Tunnel { jump_host, target, source } => write!(
+                f, "Não foi possivel abrir o túnel até {} via \"{}\": {}", target, jump_host, source
+                ),
+            ConnectionError::Secret(source) => write!(f, "Senha indisponível: {}", source),
+            ConnectionError::KnownHosts { path, source } => write!(f, "Erro no known_hosts {}: {}", path.display(), source),
+            ConnectionError::Auth { user, source } => write!(f, "Falha na autenticação do usuário \"{}\": {}", user, source),
+            ConnectionError::KeyNotFound { path: Some(path) } => write!(f, "Chave privada não encontrada: {}", path.display()),
+            ConnectionError::KeyNotFound { path: None } => write!(f, "Chave privada não informada em location"),
+            ConnectionError::Timeout { operation } => write!(f, "Tempo limite excedido: {}", operation),
+            ConnectionError::Channel(source) => write!(f, "Erro no canal SSH: {}", source),
+        }
+    }
+}
+
+impl std::error::Error for ConnectionError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ConnectionError::Dns { source, .. } | ConnectionError::Tcp { source, .. } => Some(source),
+            ConnectionError::Handshake(source) | ConnectionError::Auth { source, .. } => Some(source),
+            ConnectionError::Channel(source)
+                | ConnectionError::KnownHosts { source, .. }
+                | ConnectionError::Tunnel { source, .. } => Some(source),
+            ConnectionError::JumpHost { source, .. } => Some(source.as_ref()),
+            ConnectionError::Secret(source) => Some(source),
+            ConnectionError::KeyNotFound { .. }
+                | ConnectionError::Timeout { .. }
+                | ConnectionError::UnknownHostKey { .. }
+                | ConnectionError::HostKeyMismatch { .. } => None,
+        }
+    }
+}
+
+impl From<io::Error> for ConnectionError {
+    fn from(error: io::Error) -> Self {
+        match error.kind() {
+            io::ErrorKind::TimedOut => ConnectionError::Timeout { operation: error.to_string() },
+            _ => ConnectionError::Channel(error)
+        }
+    }
+}
+
+impl From<ssh2::Error> for ConnectionError {
+    fn from(error: ssh2::Error) -> Self {
+        if is_timeout(&error) {
+            ConnectionError::Timeout { operation: String::from("operação no canal SSH") }
+        } else {
+            ConnectionError::Channel(error.into())
+        }
+    }
+}
+
+#[test]
+fn test_connection_error_messages() {
+    let error = ConnectionError::KeyNotFound { path: Some(PathBuf::from("/home/deploy/.ssh/id_ed25519")) };
+    assert_eq!(error.to_string(), "Chave privada não encontrada: /home/deploy/.ssh/id_ed25519");
+    assert!(!error.is_retryable());
+
+    let error = ConnectionError::from(io::Error::new(io::ErrorKind::TimedOut, "leitura da saída"));
+    assert!(matches!(error, ConnectionError::Timeout { .. }));
+    assert!(error.is_retryable());
+
+    let error = ConnectionError::handshake(ssh2::Error::new(ssh2::ErrorCode::Session(LIBSSH2_ERROR_TIMEOUT), "timeout"));
+    assert_eq!(error.to_string(), "Tempo limite excedido: handshake SSH");
+}
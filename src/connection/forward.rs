@@ -0,0 +1,396 @@
+use std::{
+    io,
+    net::{TcpListener, TcpStream, ToSocketAddrs},
+    sync::mpsc::{self, Receiver, Sender, TryRecvError},
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use ssh2::{Listener, Session};
+
+use crate::parser::{ForwardDirection, PortForward};
+
+use super::{tunnel::Pipe, SessionPool, SSH};
+
+/// Pausa do loop logo depois de repassar dados; dobra a cada volta sem dados até `IDLE_SLEEP_MAX`.
+const IDLE_SLEEP: Duration = Duration::from_millis(2);
+const IDLE_SLEEP_MAX: Duration = Duration::from_millis(50);
+/// Intervalo dos keepalives enviados nas sessões com túneis ativos.
+const KEEPALIVE_EVERY: Duration = Duration::from_secs(5);
+
+enum Control {
+    Start { server: String, ssh: Box<SSH>, forward: PortForward },
+    Stop { server: String, name: String },
+}
+
+/// Mudanças nos túneis, enviadas pela thread de encaminhamento.
+#[derive(Debug,PartialEq, Eq)]
+pub enum ForwardEvent {
+    /// A porta está aberta e aceitando conexões.
+    Started { server: String, name: String },
+    /// Quantidade de conexões repassadas no momento.
+    Connections { server: String, name: String, open: usize },
+    /// Uma conexão recebida não pôde ser encaminhada; o túnel continua ativo.
+    ConnectionFailed { server: String, name: String, error: String },
+    /// O túnel foi parado a pedido (`error` vazio) ou não pôde ser aberto.
+    Stopped { server: String, name: String, error: Option<String> },
+}
+
+/// Thread que mantém os túneis de `forwards:` abertos em segundo plano. Os túneis de um mesmo
+/// servidor compartilham uma sessão do seu próprio `SessionPool`, usada em modo não bloqueante
+/// para repassar todas as conexões em um só loop; por isso não é a mesma do `Worker`.
+pub struct Forwarder {
+    control: Option<Sender<Control>>,
+    events: Receiver<ForwardEvent>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl Forwarder {
+    pub fn spawn() -> Forwarder {
+        let (control, control_receiver) = mpsc::channel();
+        let (event_sender, events) = mpsc::channel();
+
+        let handle = thread::spawn(move || run(control_receiver, event_sender));
+
+        Forwarder {
+            control: Some(control),
+            events,
+            handle: Some(handle),
+        }
+    }
+
+    /// Abre o túnel; o resultado chega como `Started` ou `Stopped` com o erro.
+    pub fn start(&self, server: &str, ssh: SSH, forward: PortForward) {
+        self.send(Control::Start { server: server.to_string(), ssh: Box::new(ssh), forward });
+    }
+
+    /// Fecha o túnel e as conexões que passam por ele.
+    pub fn stop(&self, server: &str, name: &str) {
+        self.send(Control::Stop { server: server.to_string(), name: name.to_string() });
+    }
+
+    pub fn try_recv(&self) -> Option<ForwardEvent> {
+        self.events.try_recv().ok()
+    }
+
+    pub fn recv_timeout(&self, timeout: Duration) -> Option<ForwardEvent> {
+        self.events.recv_timeout(timeout).ok()
+    }
+
+    /// Fecha todos os túneis e espera a thread terminar.
+    pub fn shutdown(mut self) {
+        self.control = None;
+
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+
+    fn send(&self, control: Control) {
+        if let Some(sender) = &self.control {
+            let _ = sender.send(control);
+        }
+    }
+}
+
+enum TunnelListener {
+    Local(TcpListener),
+    Remote(Listener),
+}
+
+struct Tunnel {
+    server: String,
+    forward: PortForward,
+    session: Session,
+    timeout: Duration,
+    listener: TunnelListener,
+    pipes: Vec<Pipe>,
+}
+
+impl Tunnel {
+    /// Aceita uma conexão pendente e a liga ao outro lado. Retorna se houve alguma.
+    fn accept(&mut self, events: &Sender<ForwardEvent>) -> io::Result<bool> {
+        let opened = match &mut self.listener {
+            TunnelListener::Local(listener) => {
+                let (stream, peer) = match listener.accept() {
+                    Ok(accepted) => accepted,
+                    Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(false),
+                    Err(e) => return Err(e)
+                };
+
+                let (host, port) = (self.forward.host(), self.forward.port());
+                stream.set_nonblocking(true)
+                      .and_then(|_| blocking(&self.session, self.timeout, || {
+                          self.session.channel_direct_tcpip(host, port, Some((&peer.ip().to_string(), peer.port())))
+                                      .map_err(io::Error::from)
+                      }))
+                      .map(|channel| Pipe::new(channel, stream))
+            },
+            TunnelListener::Remote(listener) => {
+                let channel = match listener.accept() {
+                    Ok(channel) => channel,
+                    Err(e) => {
+                        let error = io::Error::from(e);
+                        return match error.kind() {
+                            io::ErrorKind::WouldBlock => Ok(false),
+                            _ => Err(error)
+                        };
+                    }
+                };
+
+                connect_local(self.forward.host(), self.forward.port(), self.timeout)
+                    .map(|stream| Pipe::new(channel, stream))
+            }
+        };
+
+        match opened {
+            Ok(pipe) => self.pipes.push(pipe),
+            Err(e) => {
+                let _ = events.send(ForwardEvent::ConnectionFailed {
+                    server: self.server.clone(),
+                    name: self.forward.name().to_string(),
+                    error: e.to_string(),
+                });
+            }
+        }
+        Ok(true)
+    }
+
+    /// Fecha a porta; o cancelamento do `-R` precisa de uma resposta do servidor.
+    fn close(self) {
+        let session = self.session.clone();
+        let timeout = self.timeout;
+        blocking(&session, timeout, move || drop(self));
+    }
+}
+
+fn run(control: Receiver<Control>, events: Sender<ForwardEvent>) {
+    let mut pool = SessionPool::default();
+    let mut tunnels: Vec<Tunnel> = vec![];
+    let mut buffer = [0u8; 16384];
+    let mut last_keepalive = Instant::now();
+    let mut idle_sleep = IDLE_SLEEP;
+
+    loop {
+        let message = if tunnels.is_empty() {
+            match control.recv() {
+                Ok(message) => Some(message),
+                Err(_) => break
+            }
+        } else {
+            match control.try_recv() {
+                Ok(message) => Some(message),
+                Err(TryRecvError::Empty) => None,
+                Err(TryRecvError::Disconnected) => break
+            }
+        };
+
+        match message {
+            Some(Control::Start { server, ssh, forward }) => {
+                let name = forward.name().to_string();
+
+                if tunnels.iter().any(|tunnel| tunnel.server == server && tunnel.forward.name() == name) {
+                    let _ = events.send(ForwardEvent::Started { server, name });
+                    continue;
+                }
+
+                match open(&mut pool, &tunnels, &server, &ssh, forward) {
+                    Ok(tunnel) => {
+                        tunnels.push(tunnel);
+                        let _ = events.send(ForwardEvent::Started { server, name });
+                    },
+                    Err(error) => {
+                        if !tunnels.iter().any(|tunnel| tunnel.server == server) {
+                            pool.discard(&server);
+                        }
+                        let _ = events.send(ForwardEvent::Stopped { server, name, error: Some(error) });
+                    }
+                }
+            },
+            Some(Control::Stop { server, name }) => {
+                if let Some(position) = tunnels.iter().position(|tunnel| tunnel.server == server && tunnel.forward.name() == name) {
+                    tunnels.remove(position).close();
+                }
+                if !tunnels.iter().any(|tunnel| tunnel.server == server) {
+                    pool.discard(&server);
+                }
+                let _ = events.send(ForwardEvent::Stopped { server, name, error: None });
+            },
+            None => {}
+        }
+
+        let mut active = false;
+        let mut failed = vec![];
+
+        for (i, tunnel) in tunnels.iter_mut().enumerate() {
+            let before = tunnel.pipes.len();
+
+            match tunnel.accept(&events) {
+                Ok(accepted) => active |= accepted,
+                Err(e) => {
+                    failed.push((i, e.to_string()));
+                    continue;
+                }
+            }
+
+            tunnel.pipes.retain_mut(|pipe| match pipe.step(&mut buffer) {
+                Some(moved) => {
+                    active |= moved;
+                    true
+                },
+                None => false
+            });
+
+            if tunnel.pipes.len() != before {
+                let _ = events.send(ForwardEvent::Connections {
+                    server: tunnel.server.clone(),
+                    name: tunnel.forward.name().to_string(),
+                    open: tunnel.pipes.len(),
+                });
+            }
+        }
+
+        for (i, error) in failed.into_iter().rev() {
+            let tunnel = tunnels.remove(i);
+            let _ = events.send(ForwardEvent::Stopped {
+                server: tunnel.server.clone(),
+                name: tunnel.forward.name().to_string(),
+                error: Some(error),
+            });
+            tunnel.close();
+        }
+
+        if last_keepalive.elapsed() >= KEEPALIVE_EVERY {
+            last_keepalive = Instant::now();
+            keepalive(&mut pool, &tunnels);
+        }
+
+        if active {
+            idle_sleep = IDLE_SLEEP;
+        } else {
+            thread::sleep(idle_sleep);
+            idle_sleep = (idle_sleep * 2).min(IDLE_SLEEP_MAX);
+        }
+    }
+
+    for tunnel in tunnels {
+        tunnel.close();
+    }
+    pool.close_all();
+}
+
+/// Sessões com túneis abertos contam como em uso, então o pool não as encerra por ociosidade;
+/// o keepalive do pool bloqueia, e elas voltam ao modo não bloqueante depois.
+fn keepalive(pool: &mut SessionPool, tunnels: &[Tunnel]) {
+    for tunnel in tunnels {
+        pool.touch(&tunnel.server);
+        tunnel.session.set_blocking(true);
+    }
+
+    pool.keepalive();
+
+    for tunnel in tunnels {
+        tunnel.session.set_blocking(false);
+    }
+}
+
+/// Abre a porta do túnel na sessão do servidor, aproveitando a dos outros túneis dele. A porta
+/// local é reservada antes de conectar, para que uma porta ocupada falhe sem abrir sessão.
+fn open(pool: &mut SessionPool, tunnels: &[Tunnel], server: &str, ssh: &SSH, forward: PortForward) -> Result<Tunnel,String> {
+    // As sessões em uso pelos túneis estão em modo não bloqueante; o pool as testa bloqueando.
+    let siblings: Vec<&Session> = tunnels.iter()
+                                         .filter(|tunnel| tunnel.server == server)
+                                         .map(|tunnel| &tunnel.session)
+                                         .collect();
+    for session in &siblings {
+        session.set_blocking(true);
+    }
+
+    let address = forward.bind_address().to_string();
+    let local = match forward.direction() {
+        ForwardDirection::Local => Some(TcpListener::bind((address.as_str(), forward.bind_port()))
+                                       .and_then(|listener| listener.set_nonblocking(true).map(|_| listener))
+                                       .map_err(|e| format!("{}:{}: {}", address, forward.bind_port(), e))?),
+        ForwardDirection::Remote => None
+    };
+
+    let opened = pool.session(server, ssh).map_err(|e| e.to_string());
+
+    for session in &siblings {
+        session.set_blocking(false);
+    }
+    let (session, _) = opened?;
+
+    let timeout = ssh.connect_timeout;
+    let listener = match local {
+        Some(listener) => TunnelListener::Local(listener),
+        None => {
+            let (listener, _) = blocking(&session, timeout, || session.channel_forward_listen(forward.bind_port(), Some(&address), None))
+                                    .map_err(|e| format!("{}:{} no servidor: {}", address, forward.bind_port(), e))?;
+            TunnelListener::Remote(listener)
+        }
+    };
+    session.set_blocking(false);
+
+    Ok(Tunnel {
+        server: server.to_string(),
+        forward,
+        session,
+        timeout,
+        listener,
+        pipes: vec![],
+    })
+}
+
+/// Executa `f` com a sessão bloqueante e com tempo limite, voltando depois ao modo não bloqueante.
+fn blocking<T>(session: &Session, timeout: Duration, f: impl FnOnce() -> T) -> T {
+    session.set_blocking(true);
+    session.set_timeout(timeout.as_millis().min(u32::MAX as u128) as u32);
+
+    let result = f();
+
+    session.set_timeout(0);
+    session.set_blocking(false);
+    result
+}
+
+fn connect_local(host: &str, port: u16, timeout: Duration) -> io::Result<TcpStream> {
+    let address = (host, port).to_socket_addrs()?
+                              .next()
+                              .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("{}: endereço não encontrado", host)))?;
+
+    let stream = TcpStream::connect_timeout(&address, timeout)?;
+    stream.set_nonblocking(true)?;
+    Ok(stream)
+}
+
+#[test]
+fn test_forwarder_reports_connection_failure() {
+    let connect = super::refused_connect();
+    let forward: PortForward = serde_yaml_ng::from_str("name: Postgres\nbind_port: 15432\nhost: db\nport: 5432\n").unwrap();
+
+    let forwarder = Forwarder::spawn();
+    forwarder.start("Servidor 1", SSH::new(&connect), forward);
+
+    match forwarder.recv_timeout(Duration::from_secs(10)) {
+        Some(ForwardEvent::Stopped { server, name, error: Some(_) }) => assert_eq!((server.as_str(), name.as_str()), ("Servidor 1", "Postgres")),
+        other => panic!("Esperado Stopped com erro, recebido {:?}",other)
+    }
+    forwarder.shutdown();
+}
+
+#[test]
+fn test_forwarder_reports_port_in_use() {
+    let occupied = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = occupied.local_addr().unwrap().port();
+    let forward: PortForward = serde_yaml_ng::from_str(&format!("name: Postgres\nbind_port: {}\nhost: db\nport: 5432\n", port)).unwrap();
+
+    let forwarder = Forwarder::spawn();
+    forwarder.start("Servidor 1", SSH::new(&super::refused_connect()), forward);
+
+    match forwarder.recv_timeout(Duration::from_secs(10)) {
+        Some(ForwardEvent::Stopped { error: Some(error), .. }) => assert!(error.starts_with(&format!("127.0.0.1:{}:", port)), "{}", error),
+        other => panic!("Esperado Stopped com erro, recebido {:?}",other)
+    }
+    forwarder.shutdown();
+}
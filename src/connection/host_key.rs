@@ -0,0 +1,151 @@
+use std::{fs, io, path::Path};
+
+use openssl::{base64, sha};
+use ssh2::{CheckResult, HostKeyType, KnownHostFileKind, Session};
+
+use super::ConnectionError;
+
+/// Código do libssh2 quando a sessão não tem chave de host disponível.
+const LIBSSH2_ERROR_HOSTKEY_INIT: i32 = -10;
+
+/// Comentário gravado junto das chaves aceitas. O libssh2 copia `len + 1` bytes do comentário,
+/// por isso o texto repassado é uma fatia deste valor, que termina em `\0`.
+const KNOWN_HOSTS_COMMENT: &str = "server_automation\0";
+
+/// Chave apresentada pelo servidor durante o handshake.
+#[derive(Debug,Clone)]
+pub struct HostKey {
+    host: String,
+    port: u16,
+    key: Vec<u8>,
+    key_type: HostKeyType,
+    fingerprint: String,
+}
+
+impl HostKey {
+    pub(crate) fn from_session(session: &Session, host: &str, port: u16) -> Result<HostKey,ConnectionError> {
+        let (key, key_type) = session.host_key().ok_or_else(|| ConnectionError::Handshake(
+            ssh2::Error::new(ssh2::ErrorCode::Session(LIBSSH2_ERROR_HOSTKEY_INIT), "Servidor não apresentou chave de host")
+            ))?;
+
+        Ok(HostKey {
+            host: host.to_string(),
+            port,
+            key: key.to_vec(),
+            key_type,
+            fingerprint: fingerprint(key),
+        })
+    }
+
+    pub fn host(&self) -> &str {
+        &self.host
+    }
+
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    /// Fingerprint no formato do OpenSSH: `SHA256:` seguido do hash em base64 sem `=`.
+    pub fn fingerprint(&self) -> &str {
+        &self.fingerprint
+    }
+
+    pub fn key_type(&self) -> &'static str {
+        match self.key_type {
+            HostKeyType::Rsa => "ssh-rsa",
+            HostKeyType::Dss => "ssh-dss",
+            HostKeyType::Ecdsa256 => "ecdsa-sha2-nistp256",
+            HostKeyType::Ecdsa384 => "ecdsa-sha2-nistp384",
+            HostKeyType::Ecdsa521 => "ecdsa-sha2-nistp521",
+            HostKeyType::Ed255219 => "ssh-ed25519",
+            HostKeyType::Unknown => "desconhecido",
+        }
+    }
+
+    /// Nome do host como o OpenSSH grava no `known_hosts`: `[host]:porta` fora da porta 22.
+    fn known_hosts_name(&self) -> String {
+        if self.port == 22 {
+            self.host.clone()
+        } else {
+            format!("[{}]:{}", self.host, self.port)
+        }
+    }
+}
+
+/// Fingerprint SHA256 de uma chave pública no formato exibido pelo `ssh-keygen -l`.
+pub fn fingerprint(key: &[u8]) -> String {
+    let encoded = base64::encode_block(&sha::sha256(key));
+    format!("SHA256:{}", encoded.trim_end_matches('='))
+}
+
+/// Compara fingerprints aceitando o valor com ou sem o prefixo `SHA256:` e o `=` final.
+pub fn fingerprint_matches(expected: &str, fingerprint: &str) -> bool {
+    let normalize = |value: &str| value.trim()
+                                       .trim_start_matches("SHA256:")
+                                       .trim_end_matches('=')
+                                       .to_string();
+
+    normalize(expected) == normalize(fingerprint)
+}
+
+/// Procura a chave no `known_hosts`. Um arquivo inexistente equivale a um arquivo vazio.
+pub(crate) fn check_known_hosts(path: &Path, key: &HostKey) -> Result<CheckResult,ConnectionError> {
+    let session = Session::new().map_err(|e| known_hosts_error(path, e))?;
+    let mut known_hosts = session.known_hosts().map_err(|e| known_hosts_error(path, e))?;
+
+    if path.exists() {
+        known_hosts.read_file(path, KnownHostFileKind::OpenSSH)
+                   .map_err(|e| known_hosts_error(path, e))?;
+    }
+
+    Ok(known_hosts.check_port(&key.host, key.port, &key.key))
+}
+
+/// Acrescenta a chave ao `known_hosts`, criando o arquivo (e `~/.ssh`) quando necessário.
+pub(crate) fn add_known_host(path: &Path, key: &HostKey) -> Result<(),ConnectionError> {
+    let session = Session::new().map_err(|e| known_hosts_error(path, e))?;
+    let mut known_hosts = session.known_hosts().map_err(|e| known_hosts_error(path, e))?;
+
+    if path.exists() {
+        known_hosts.read_file(path, KnownHostFileKind::OpenSSH)
+                   .map_err(|e| known_hosts_error(path, e))?;
+    } else if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+        fs::create_dir_all(parent).map_err(|source| ConnectionError::KnownHosts { path: path.to_path_buf(), source })?;
+    }
+
+    known_hosts.add(&key.known_hosts_name(), &key.key, &KNOWN_HOSTS_COMMENT[..KNOWN_HOSTS_COMMENT.len() - 1], key.key_type.into())
+               .map_err(|e| known_hosts_error(path, e))?;
+    known_hosts.write_file(path, KnownHostFileKind::OpenSSH)
+               .map_err(|e| known_hosts_error(path, e))
+}
+
+fn known_hosts_error(path: &Path, error: ssh2::Error) -> ConnectionError {
+    ConnectionError::KnownHosts { path: path.to_path_buf(), source: io::Error::other(error) }
+}
+
+#[test]
+fn test_known_hosts_round_trip() {
+    let path = std::env::temp_dir().join(format!("server_automation_known_hosts_{}", std::process::id()));
+    let _ = fs::remove_file(&path);
+
+    let mut key = HostKey {
+        host: String::from("app.example.com"),
+        port: 2222,
+        key: b"\x00\x00\x00\x0bssh-ed25519\x00\x00\x00\x20abcdefghijklmnopqrstuvwxyz012345".to_vec(),
+        key_type: HostKeyType::Ed255219,
+        fingerprint: String::new(),
+    };
+    key.fingerprint = fingerprint(&key.key);
+    assert!(fingerprint_matches(&format!("{}=", key.fingerprint.trim_start_matches("SHA256:")), &fingerprint(&key.key)));
+
+    assert!(matches!(check_known_hosts(&path, &key), Ok(CheckResult::NotFound)));
+
+    add_known_host(&path, &key).unwrap();
+    assert!(fs::read_to_string(&path).unwrap().starts_with("[app.example.com]:2222 ssh-ed25519 "));
+    assert!(matches!(check_known_hosts(&path, &key), Ok(CheckResult::Match)));
+
+    key.key[20] ^= 0xff;
+    assert!(matches!(check_known_hosts(&path, &key), Ok(CheckResult::Mismatch)));
+
+    let _ = fs::remove_file(&path);
+}
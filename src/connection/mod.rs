@@ -1,155 +1,941 @@
-use std::{io::Read, net::TcpStream, path::Path};
+use std::{io::{self, Read, Write}, net::{TcpStream, ToSocketAddrs}, path::{Path, PathBuf}, process, sync::{atomic::{AtomicBool, Ordering}, Arc}, thread, time::{Duration, Instant, SystemTime, UNIX_EPOCH}};
 
-use ssh2::{Channel, Session};
+use ssh2::{Channel, CheckResult, Session};
 
-use crate::parser::{ConfigYaml, ConnectionType, ServerCommands, ServerConnect};
+use crate::{
+    parser::{shell_quote, ConnectionType, FileTransfer, HostKeyPolicy, ServerCommands, ServerConnect, TransferDirection},
+    secrets::{PromptAnswers, Secret, SecretString},
+};
 
+mod browser;
+mod error;
+mod forward;
+mod host_key;
+mod parallel;
+mod pool;
+mod probe;
+mod shell;
+mod transfer;
+mod tunnel;
+mod worker;
+pub use browser::{FilePreview, RemoteEntry, PREVIEW_BYTES};
+pub use error::ConnectionError;
+pub use forward::{ForwardEvent, Forwarder};
+use transfer::TransferEnd;
+pub use host_key::{fingerprint, fingerprint_matches, HostKey};
+pub use parallel::{build_targets, run_parallel, run_parallel_cancelable, ParallelEvent, ServerRun, ServerStatus, ServerTarget};
+pub use pool::{SessionPool, DEFAULT_IDLE_TIMEOUT};
+pub use probe::{ConfigField, HealthReport, MountUsage};
+pub use shell::{interactive_shell, ShellInput};
+pub use worker::{Job, ShellSession, Worker, WorkerEvent};
+
+/// Origem de um trecho de saída lido do canal SSH.
+#[derive(Debug,PartialEq, Eq,Clone,Copy)]
+pub enum OutputStream {
+    Stdout,
+    Stderr,
+}
+
+/// Resultado da execução de um comando no servidor remoto.
+#[derive(Debug,PartialEq, Eq,Clone)]
+pub struct CommandResult {
+    command: String,
+    exit_code: i32,
+    stdout: String,
+    stderr: String,
+    duration: Duration,
+}
+
+impl CommandResult {
+    /// Linha de comando exatamente como foi enviada ao servidor.
+    pub fn command(&self) -> &str {
+        &self.command
+    }
+
+    pub fn exit_code(&self) -> i32 {
+        self.exit_code
+    }
+
+    pub fn stdout(&self) -> &str {
+        &self.stdout
+    }
+
+    pub fn stderr(&self) -> &str {
+        &self.stderr
+    }
+
+    pub fn duration(&self) -> Duration {
+        self.duration
+    }
+
+    pub fn is_success(&self) -> bool {
+        self.exit_code == 0
+    }
+}
+
+/// Evento emitido durante a execução etapa por etapa de um `ServerCommands`.
+#[derive(Debug)]
+pub enum StepEvent<'a> {
+    Started { index: usize, command: &'a str },
+    Output { index: usize, stream: OutputStream, text: &'a str },
+    /// Bytes já copiados por uma etapa `upload`/`download` e o tamanho total, quando conhecido.
+    Progress { index: usize, transferred: u64, total: Option<u64> },
+    Finished { index: usize, result: &'a CommandResult },
+}
+
+/// Resultado da execução etapa por etapa. As etapas após `failed_step` (ou após o cancelamento)
+/// não foram executadas.
+#[derive(Debug,PartialEq, Eq,Clone)]
+pub struct StepsReport {
+    steps: Vec<CommandResult>,
+    failed_step: Option<usize>,
+    cancelled: bool,
+    cancelled_step: Option<usize>,
+}
+
+impl StepsReport {
+    pub fn steps(&self) -> &Vec<CommandResult> {
+        &self.steps
+    }
+
+    pub fn failed_step(&self) -> Option<usize> {
+        self.failed_step
+    }
+
+    /// A execução foi interrompida por um `CancelToken`.
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled
+    }
+
+    /// Etapa que estava em andamento quando a execução foi cancelada; `None` se o cancelamento
+    /// aconteceu entre duas etapas.
+    pub fn cancelled_step(&self) -> Option<usize> {
+        self.cancelled_step
+    }
+
+    pub fn is_success(&self) -> bool {
+        self.failed_step.is_none() && !self.cancelled
+    }
+}
+
+/// Pedido de cancelamento compartilhado entre a interface e a thread que executa os comandos.
+#[derive(Debug,Clone,Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Debug,PartialEq, Eq,Clone)]
 pub struct SSH {
     type_connection: ConnectionType,
-    ip_address: String,
+    host: String,
+    port: u16,
     user_name: String,
-    password: Option<String>,
-    location: Option<String>
+    password: Option<Secret>,
+    /// Pergunta usada quando a senha é `prompt` sem rótulo.
+    password_label: String,
+    prompt_answers: PromptAnswers,
+    location: Option<String>,
+    connect_timeout: Duration,
+    command_timeout: Option<Duration>,
+    keepalive_interval: Option<u32>,
+    host_key_fingerprint: Option<String>,
+    host_key_check: HostKeyPolicy,
+    known_hosts: Option<PathBuf>,
+    /// Nome do `jump_host` e a conexão do bastião (ou o motivo de ela não ter sido resolvida).
+    jump: Option<(String,Result<Box<SSH>,String>)>,
 }
 
+/// Código de saída registrado para uma etapa interrompida por `command_timeout`, como no `timeout(1)`.
+pub const TIMEOUT_EXIT_CODE: i32 = 124;
+/// Código de saída registrado para uma etapa cancelada pelo usuário, como um processo terminado por SIGINT.
+pub const CANCELLED_EXIT_CODE: i32 = 130;
+
+/// Tempo máximo para avisar o servidor do cancelamento; ele pode nem responder mais.
+const CANCEL_TIMEOUT_MS: u32 = 2000;
+
 impl SSH {
 
     pub fn new(server_connect:  &ServerConnect) -> SSH {
 
-        let ssh = SSH {
+        SSH {
             type_connection: server_connect.type_connection().clone(),
-            ip_address: server_connect.ip_address().clone(),
+            host: server_connect.host(),
+            port: server_connect.port(),
             user_name: server_connect.user().clone(),
             password: server_connect.password().clone(),
-            location: server_connect.location().clone()
-        };
-        ssh
+            password_label: server_connect.password_label(),
+            prompt_answers: PromptAnswers::default(),
+            location: server_connect.location().clone(),
+            connect_timeout: server_connect.connect_timeout(),
+            command_timeout: server_connect.command_timeout(),
+            keepalive_interval: server_connect.keepalive_interval(),
+            host_key_fingerprint: server_connect.host_key_fingerprint().map(str::to_string),
+            host_key_check: server_connect.host_key_check(),
+            known_hosts: server_connect.known_hosts(),
+            jump: server_connect.jump_host().zip(server_connect.jump()).map(|(name, jump)| (
+                name.to_string(),
+                jump.map(|bastion| Box::new(SSH::new(bastion))).map_err(str::to_string)
+                )),
+        }
     }
 
-    pub fn connect(&self) -> Result<Session,ssh2::Error> {
+    /// Resolve o host (IP ou DNS) e tenta cada endereço até `connect_timeout`.
+    fn connect_tcp(&self) -> Result<TcpStream,ConnectionError> {
+        let addresses: Vec<_> = (self.host.as_str(), self.port).to_socket_addrs()
+                                                               .map_err(|source| ConnectionError::Dns { host: self.host.clone(), source })?
+                                                               .collect();
+
+        let mut last_error = io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("Nenhum endereço encontrado para {}",self.host)
+            );
+
+        for address in addresses {
+            match TcpStream::connect_timeout(&address, self.connect_timeout) {
+                Ok(tcp) => return Ok(tcp),
+                Err(e) => last_error = e
+            }
+        }
+
+        if last_error.kind() == io::ErrorKind::TimedOut {
+            return Err(ConnectionError::Timeout {
+                operation: format!("conexão TCP com {}:{} após {}s",self.host,self.port,self.connect_timeout.as_secs())
+            });
+        }
 
-        let tcp = match TcpStream::connect(&self.ip_address) {
-            Ok(res) => res,
-            Err(err) => panic!("Não foi possivel inicializar a conexão TCP: {:?}",err)
+        Err(ConnectionError::Tcp { address: format!("{}:{}",self.host,self.port), source: last_error })
+    }
+
+    /// Conecta no bastião e abre por ele o túnel até este servidor.
+    fn connect_through(&self, name: &str, jump: &Result<Box<SSH>,String>) -> Result<TcpStream,ConnectionError> {
+        let tunnel_error = |source| ConnectionError::Tunnel {
+            jump_host: name.to_string(),
+            target: format!("{}:{}",self.host,self.port),
+            source
+        };
+
+        let bastion = match jump {
+            Ok(bastion) => bastion,
+            Err(message) => return Err(tunnel_error(io::Error::new(io::ErrorKind::NotFound, message.clone())))
         };
 
-        let mut sess = match Session::new() {
-          Ok(ses) => ses,
-          Err(err) => panic!("Não foi possivel inicializar a sessão: {:?}",err)
+        let session = bastion.open_session()
+                             .map_err(|source| ConnectionError::JumpHost { name: name.to_string(), source: Box::new(source) })?;
+
+        tunnel::open_tunnel(session, &self.host, self.port).map_err(tunnel_error)
+    }
+
+    /// Conexão TCP (direta ou pelo `jump_host`) e handshake; handshake e autenticação respeitam `connect_timeout`.
+    fn start_session(&self) -> Result<Session,ConnectionError> {
+        let tcp = match &self.jump {
+            Some((name, jump)) => self.connect_through(name, jump)?,
+            None => self.connect_tcp()?
         };
 
+        let mut sess = Session::new().map_err(ConnectionError::handshake)?;
+
+        sess.set_timeout(self.connect_timeout.as_millis().min(u32::MAX as u128) as u32);
         sess.set_tcp_stream(tcp);
-        sess.handshake()?;
+        sess.handshake().map_err(ConnectionError::handshake)?;
+        self.verify_host_key(&sess)?;
 
-        if let Some(password) = &self.password {
-            sess.userauth_password(&self.user_name, password)?;
-        } else {
-            sess.userauth_agent(&self.user_name)?;
+        Ok(sess)
+    }
+
+    /// Confere a chave do servidor com `host_key_fingerprint` ou, na falta dele, com o `known_hosts`,
+    /// aplicando `host_key_check` quando o servidor ainda não é conhecido.
+    fn verify_host_key(&self, sess: &Session) -> Result<(),ConnectionError> {
+        let key = HostKey::from_session(sess, &self.host, self.port)?;
+
+        if let Some(expected) = &self.host_key_fingerprint {
+            return match fingerprint_matches(expected, key.fingerprint()) {
+                true => Ok(()),
+                false => Err(ConnectionError::HostKeyMismatch { key, expected: Some(expected.clone()) })
+            };
         }
 
-        Ok(sess)
+        let result = match &self.known_hosts {
+            Some(path) => host_key::check_known_hosts(path, &key)?,
+            None => CheckResult::NotFound
+        };
 
+        match result {
+            CheckResult::Match => Ok(()),
+            CheckResult::Mismatch => Err(ConnectionError::HostKeyMismatch { key, expected: None }),
+            CheckResult::NotFound | CheckResult::Failure => match self.host_key_check {
+                HostKeyPolicy::AcceptNew if self.known_hosts.is_some() => self.trust_host_key(&key),
+                policy => Err(ConnectionError::UnknownHostKey { key, policy })
+            }
+        }
     }
 
-    pub fn connect_with_private_key(&self) -> Result<Session,ssh2::Error> {
+    /// Registra a chave no `known_hosts` do servidor (ou do bastião a que ela pertence);
+    /// a próxima conexão passa a reconhecê-la.
+    pub fn trust_host_key(&self, key: &HostKey) -> Result<(),ConnectionError> {
+        if let Some((_, Ok(bastion))) = &self.jump {
+            if (key.host(), key.port()) != (self.host.as_str(), self.port) {
+                return bastion.trust_host_key(key);
+            }
+        }
 
-        let tcp = match TcpStream::connect(&self.ip_address){
-             Ok(res) => res,
-             Err(err) => panic!("Não foi possivle inicializar a conexão TCP: {:?}",err)
-        };
+        match &self.known_hosts {
+            Some(path) => host_key::add_known_host(path, key),
+            None => Err(ConnectionError::KnownHosts {
+                path: PathBuf::from("~/.ssh/known_hosts"),
+                source: io::Error::new(io::ErrorKind::NotFound, "HOME não definido; informe known_hosts na conexão")
+            })
+        }
+    }
+
+    /// Depois da autenticação, as esperas passam a ser controladas por `command_timeout`.
+    fn finish_session(&self, sess: &Session) {
+        sess.set_timeout(0);
+
+        if let Some(interval) = self.keepalive_interval {
+            sess.set_keepalive(false, interval);
+        }
+    }
+
+    pub fn type_connection(&self) -> &ConnectionType {
+        &self.type_connection
+    }
+
+    /// Respostas para os segredos `prompt`, repassadas também aos bastiões.
+    pub fn with_prompt_answers(mut self, answers: &PromptAnswers) -> SSH {
+        self.prompt_answers = answers.clone();
+
+        if let Some((name, Ok(bastion))) = self.jump.take() {
+            self.jump = Some((name, Ok(Box::new(bastion.with_prompt_answers(answers)))));
+        }
+        self
+    }
+
+    /// Obtém a senha (de variável, arquivo, comando ou resposta digitada) só no momento da conexão.
+    fn resolve_password(&self) -> Result<Option<SecretString>,ConnectionError> {
+        self.password.as_ref()
+                     .map(|password| password.resolve(&self.prompt_answers, &self.password_label))
+                     .transpose()
+                     .map_err(ConnectionError::Secret)
+    }
+
+    pub fn connect(&self) -> Result<Session,ConnectionError> {
 
-        let mut sess = match Session::new() {
-            Ok(ses) => ses,
-            Err(err) => panic!("Não foi possivel inicalizar a sessa: {:?}",err)
+        let password = self.resolve_password()?;
+        let sess = self.start_session()?;
+
+        let auth = if let Some(password) = &password {
+            sess.userauth_password(&self.user_name, password.expose())
+        } else {
+            sess.userauth_agent(&self.user_name)
         };
+        auth.map_err(|e| ConnectionError::auth(&self.user_name, e))?;
 
-        sess.set_tcp_stream(tcp);
-        sess.handshake()?;
+        self.finish_session(&sess);
+        Ok(sess)
 
-        let localKey = match &self.location {
-            Some(local) => {
-                Path::new(local)
-            },
-            None => panic!("Chave não encontrada")
+    }
+
+    pub fn connect_with_private_key(&self) -> Result<Session,ConnectionError> {
+
+        // A chave é conferida antes de abrir a conexão para não gastar um handshake à toa.
+        let local_key = match self.location.as_deref().filter(|local| !local.trim().is_empty()) {
+            Some(local) => Path::new(local),
+            None => return Err(ConnectionError::KeyNotFound { path: None })
         };
 
-        if let Some(password) = &self.password {
-            sess.userauth_pubkey_file(&self.user_name, None,localKey,Some(password))?;
-        } else {
-            sess.userauth_pubkey_file(&self.user_name, None,localKey,None)?;
+        if !local_key.is_file() {
+            return Err(ConnectionError::KeyNotFound { path: Some(local_key.to_path_buf()) });
         }
 
+        let passphrase = self.resolve_password()?;
+        let sess = self.start_session()?;
 
+        sess.userauth_pubkey_file(&self.user_name, None,local_key,passphrase.as_ref().map(SecretString::expose))
+            .map_err(|e| ConnectionError::auth(&self.user_name, e))?;
+
+        self.finish_session(&sess);
         Ok(sess)
     }
 
+    /// Abre a sessão usando o método de autenticação definido em `type_connection`.
+    pub fn open_session(&self) -> Result<Session,ConnectionError> {
+        match self.type_connection {
+            ConnectionType::SSH => self.connect(),
+            ConnectionType::SSH_KEY => self.connect_with_private_key(),
+        }
+    }
+
     pub fn manager_commands<F>(exec_commands: &Vec<String>, concat_fn: F) -> String
     where
         F: Fn(&Vec<String>) -> String,
     {
-        let concatenated_commands = concat_fn(exec_commands);
+        concat_fn(exec_commands)
+    }
 
-        concatenated_commands
+    pub fn execute_commands(&self,server_commands: &ServerCommands, session: Session) -> Result<CommandResult,ConnectionError> {
+        self.execute_commands_streaming(server_commands, session, |_,_| {})
     }
 
-    pub fn execute_commands(&self,server_commands: &ServerCommands, session: Session) -> String {
+    /// Executa os comandos repassando cada trecho de stdout/stderr para `on_output` assim que chega do servidor.
+    pub fn execute_commands_streaming<F>(&self,server_commands: &ServerCommands, session: Session, mut on_output: F) -> Result<CommandResult,ConnectionError>
+    where
+        F: FnMut(OutputStream, &str),
+    {
 
         let commands_list = server_commands.commands();
 
-        let mut formated_commands: String = String::new();
+        let formated_commands = privileged(
+            server_commands,
+            &SSH::manager_commands(&commands_list, |commands| commands.join(" && "))
+            );
 
-        if *&self.type_connection == ConnectionType::SSH {
-            formated_commands = SSH::manager_commands(commands_list, |commands| commands.join(" && "));
-        } else {
-           let prefix = commands_list[0].clone();
+        let started = Instant::now();
+        let deadline = self.command_timeout.map(|timeout| started + timeout);
+
+        let mut channel = session.channel_session()?;
+        channel.exec(formated_commands.as_str())?;
+
+        let (stdout, stderr) = SSH::stream_output(&session, &mut channel, &mut on_output, deadline)?;
+
+        channel.wait_close()?;
+        let exit_code = channel.exit_status()?;
+
+        Ok(CommandResult {
+            command: formated_commands,
+            exit_code,
+            stdout,
+            stderr,
+            duration: started.elapsed(),
+        })
+
+    }
+
+    /// Executa cada linha de `exec` como uma etapa separada dentro do mesmo shell remoto,
+    /// preservando diretório e variáveis entre as etapas. A execução para na primeira etapa
+    /// que falhar, exceto quando ela declara `continue_on_error`. Uma etapa que excede
+    /// `command_timeout` termina com `TIMEOUT_EXIT_CODE` e encerra o shell remoto.
+    pub fn execute_steps<F>(&self,server_commands: &ServerCommands, session: Session, on_event: F) -> Result<StepsReport,ConnectionError>
+    where
+        F: FnMut(StepEvent),
+    {
+        self.execute_steps_cancelable(server_commands, session, &CancelToken::default(), on_event)
+    }
+
+    /// Como `execute_steps`, mas para quando `cancel` for acionado: a etapa em andamento recebe
+    /// SIGTERM (quando o servidor aceita o pedido `signal`), o canal é fechado e ela termina com
+    /// `CANCELLED_EXIT_CODE`. As etapas seguintes não são executadas. Com `become` o SIGTERM não
+    /// alcança o processo do outro usuário, então a saída da etapa avisa que ele pode continuar rodando.
+    pub fn execute_steps_cancelable<F>(&self,server_commands: &ServerCommands, session: Session, cancel: &CancelToken, mut on_event: F) -> Result<StepsReport,ConnectionError>
+    where
+        F: FnMut(StepEvent),
+    {
+        let marker = step_marker();
+
+        let mut channel = session.channel_session()?;
+        channel.exec(&privileged_shell(server_commands))?;
+
+        let mut streams = StepStreams::new(&marker);
+        let mut results: Vec<CommandResult> = vec![];
+        let mut failed_step = None;
+        let mut cancelled = false;
+        let mut cancelled_step = None;
+        let mut shell_closed = false;
+
+        for (index, step) in server_commands.steps().iter().enumerate() {
+            if cancel.is_cancelled() {
+                cancelled = true;
+                break;
+            }
+
+            if let Some(transfer) = step.transfer() {
+                let (result, interrupted) = self.transfer_step(&session, index, &step.label(), transfer, cancel, &mut on_event);
+                let exit_code = result.exit_code;
+
+                on_event(StepEvent::Finished { index, result: &result });
+                results.push(result);
+
+                if interrupted {
+                    cancelled = true;
+                    cancelled_step = Some(index);
+                    break;
+                }
+
+                if exit_code != 0 && !step.continue_on_error() {
+                    failed_step = Some(index);
+                    break;
+                }
+                continue;
+            }
+
+            let command = step.run();
+            on_event(StepEvent::Started { index, command });
+
+            let started = Instant::now();
+            let deadline = self.command_timeout.map(|timeout| started + timeout);
+
+            channel.write_all(step_script(command, &marker).as_bytes())?;
+            channel.flush()?;
+
+            session.set_blocking(false);
+            let outcome = SSH::wait_step(&session, &mut channel, index, &mut streams, &mut on_event, deadline, cancel);
+            let (stdout, mut stderr, end) = match outcome {
+                Ok(outcome) => outcome,
+                Err(e) => {
+                    session.set_blocking(true);
+                    return Err(e.into());
+                }
+            };
+
+            let exit_code = match end {
+                StepEnd::Status(code) => {
+                    session.set_blocking(true);
+                    code
+                },
+                StepEnd::Closed => {
+                    session.set_blocking(true);
+                    shell_closed = true;
+                    channel.wait_close()?;
+                    channel.exit_status()?
+                },
+                StepEnd::TimedOut => {
+                    // Sem bloquear: o servidor pode nem responder mais.
+                    let _ = channel.close();
+                    session.set_blocking(true);
+                    shell_closed = true;
+
+                    let message = format!(
+                        "Tempo limite de {}s excedido; etapa interrompida\n",
+                        self.command_timeout.unwrap_or_default().as_secs()
+                        );
+                    on_event(StepEvent::Output { index, stream: OutputStream::Stderr, text: &message });
+                    stderr.push_str(&message);
+                    TIMEOUT_EXIT_CODE
+                },
+                StepEnd::Cancelled => {
+                    session.set_blocking(true);
+                    session.set_timeout(CANCEL_TIMEOUT_MS);
+                    let _ = channel.process_startup("signal", Some("TERM"));
+                    let _ = channel.send_eof();
+                    let _ = channel.close();
+                    session.set_timeout(0);
+                    shell_closed = true;
+                    cancelled = true;
+                    cancelled_step = Some(index);
+
+                    let message = match server_commands.become_user() {
+                        Some(_) => "Execução cancelada pelo usuário; cancelamento pode não ter interrompido o processo remoto (etapa com become)\n",
+                        None => "Execução cancelada pelo usuário\n"
+                    };
+                    on_event(StepEvent::Output { index, stream: OutputStream::Stderr, text: message });
+                    stderr.push_str(message);
+                    CANCELLED_EXIT_CODE
+                }
+            };
+
+            let result = CommandResult {
+                command: command.to_string(),
+                exit_code,
+                stdout,
+                stderr,
+                duration: started.elapsed(),
+            };
 
-           let remaining_commands: Vec<String> = commands_list.iter()
-                                                              .skip(1)
-                                                              .cloned()
-                                                              .collect();
-           let format_commands = vec![format!(
-               "{} '{}'",
-               prefix,
-               remaining_commands.join(" && ")
-               )];
+            on_event(StepEvent::Finished { index, result: &result });
+            results.push(result);
 
-           formated_commands = SSH::manager_commands(&format_commands, |commands| commands.join(""));
+            if cancelled {
+                break;
+            }
 
+            if exit_code != 0 && (shell_closed || !step.continue_on_error()) {
+                failed_step = Some(index);
+                break;
+            }
+
+            if shell_closed {
+                break;
+            }
         }
-        //let formated_commands = SSH::manager_commands(commands_list,|commands| commands.join(" && "));
 
-        let mut channel = session.channel_session().unwrap();
+        if !shell_closed {
+            channel.write_all(b"exit\n")?;
+            channel.send_eof()?;
+            let deadline = self.command_timeout.map(|timeout| Instant::now() + timeout);
+            SSH::stream_output(&session, &mut channel, &mut |_,_| {}, deadline)?;
+            channel.wait_close()?;
+        }
 
-        match channel.exec(formated_commands.as_str()) {
-            Ok(_) => {
-                let mut output = String::new();
+        Ok(StepsReport {
+            steps: results,
+            failed_step,
+            cancelled,
+            cancelled_step,
+        })
+    }
 
-                match channel.read_to_string(&mut output) {
-                    Ok(_) => {
-                        //println!("Saida do comando: {:?}",s);
-                        channel.wait_close().unwrap();
-                        channel.exit_status().unwrap();
+    /// Executa uma etapa `upload`/`download` pelo SFTP da sessão. Falhas da transferência
+    /// (arquivo ausente, permissão, checksum diferente) viram código de saída 1 na etapa.
+    /// O segundo valor indica que a etapa foi cancelada.
+    fn transfer_step<F>(&self, session: &Session, index: usize, label: &str, (direction, transfer): (TransferDirection,&FileTransfer), cancel: &CancelToken, on_event: &mut F) -> (CommandResult,bool)
+    where
+        F: FnMut(StepEvent),
+    {
+        on_event(StepEvent::Started { index, command: label });
 
-                        return output;
-                    },
-                    Err(e) => {
-                        return format!("Erro ao executar comando: {:?}",e)
-                        //panic!("Erro ao ler a saida do comando: {:?}",e)
+        let started = Instant::now();
+        let deadline = self.command_timeout.map(|timeout| started + timeout);
+
+        let outcome = transfer::run_transfer(session, direction, transfer, cancel, deadline, |transferred, total| {
+            on_event(StepEvent::Progress { index, transferred, total });
+        });
+
+        let (exit_code, stdout, stderr) = match outcome {
+            Ok(TransferEnd::Done(summary)) => (
+                0,
+                format!("{}\n", summary.message()),
+                match summary.checksum {
+                    Some(_) => String::new(),
+                    None => String::from("Aviso: servidor sem sha256sum/shasum; checksum não verificado\n")
+                }
+                ),
+            Ok(TransferEnd::Cancelled) => (CANCELLED_EXIT_CODE, String::new(), String::from("Transferência cancelada pelo usuário\n")),
+            Ok(TransferEnd::TimedOut) => (
+                TIMEOUT_EXIT_CODE,
+                String::new(),
+                format!("Tempo limite de {}s excedido; transferência interrompida\n", self.command_timeout.unwrap_or_default().as_secs())
+                ),
+            Err(e) => (1, String::new(), format!("Falha na transferência: {}\n", e))
+        };
+
+        if !stdout.is_empty() {
+            on_event(StepEvent::Output { index, stream: OutputStream::Stdout, text: &stdout });
+        }
+        if !stderr.is_empty() {
+            on_event(StepEvent::Output { index, stream: OutputStream::Stderr, text: &stderr });
+        }
+
+        let result = CommandResult {
+            command: label.to_string(),
+            exit_code,
+            stdout,
+            stderr,
+            duration: started.elapsed(),
+        };
+        (result, exit_code == CANCELLED_EXIT_CODE && cancel.is_cancelled())
+    }
+
+    /// Lê stdout/stderr até encontrar o marcador de fim da etapa nos dois fluxos, o shell remoto
+    /// terminar, `deadline` passar ou `cancel` ser acionado.
+    fn wait_step<F>(session: &Session, channel: &mut Channel, index: usize, streams: &mut StepStreams, on_event: &mut F, deadline: Option<Instant>, cancel: &CancelToken) -> io::Result<(String,String,StepEnd)>
+    where
+        F: FnMut(StepEvent),
+    {
+        let mut stdout = String::new();
+        let mut stderr = String::new();
+        let mut status: Option<i32> = None;
+        let mut stderr_done = false;
+        let mut buffer = [0u8; 4096];
+
+        loop {
+            // Conferidos a cada volta: uma etapa que não para de imprimir também pode ser
+            // cancelada ou interrompida pelo `command_timeout`.
+            if cancel.is_cancelled() {
+                return Ok((stdout, stderr, StepEnd::Cancelled));
+            }
+            if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                return Ok((stdout, stderr, StepEnd::TimedOut));
+            }
+
+            let mut received = false;
+
+            if status.is_none() {
+                if let Some(text) = read_stream(channel, &mut buffer, &mut streams.stdout_pending)? {
+                    received = true;
+
+                    let (text, found) = streams.stdout.feed(&text);
+                    if !text.is_empty() {
+                        on_event(StepEvent::Output { index, stream: OutputStream::Stdout, text: &text });
+                        stdout.push_str(&text);
+                    }
+                    status = found;
+                }
+            }
+
+            if !stderr_done {
+                if let Some(text) = read_stream(&mut channel.stderr(), &mut buffer, &mut streams.stderr_pending)? {
+                    received = true;
+
+                    let (text, found) = streams.stderr.feed(&text);
+                    if !text.is_empty() {
+                        on_event(StepEvent::Output { index, stream: OutputStream::Stderr, text: &text });
+                        stderr.push_str(&text);
+                    }
+                    stderr_done = found.is_some();
+                }
+            }
+
+            if let (Some(code), true) = (status, stderr_done) {
+                return Ok((stdout, stderr, StepEnd::Status(code)));
+            }
+
+            if !received {
+                if channel.eof() {
+                    let text = streams.stdout.take_rest() + &String::from_utf8_lossy(&streams.stdout_pending);
+                    streams.stdout_pending.clear();
+                    if !text.is_empty() {
+                        on_event(StepEvent::Output { index, stream: OutputStream::Stdout, text: &text });
+                        stdout.push_str(&text);
+                    }
+
+                    let text = streams.stderr.take_rest() + &String::from_utf8_lossy(&streams.stderr_pending);
+                    streams.stderr_pending.clear();
+                    if !text.is_empty() {
+                        on_event(StepEvent::Output { index, stream: OutputStream::Stderr, text: &text });
+                        stderr.push_str(&text);
                     }
+
+                    let end = match status {
+                        Some(code) => StepEnd::Status(code),
+                        None => StepEnd::Closed
+                    };
+                    return Ok((stdout, stderr, end));
                 }
-            },
-            Err(e) => format!("Erro desconhecido: {:?}",e)
+
+                let _ = session.keepalive_send();
+                thread::sleep(Duration::from_millis(20));
+            }
+        }
+    }
+
+    fn stream_output<F>(session: &Session, channel: &mut Channel, on_output: &mut F, deadline: Option<Instant>) -> io::Result<(String,String)>
+    where
+        F: FnMut(OutputStream, &str),
+    {
+        let mut output = String::new();
+        let mut errors = String::new();
+        let mut stdout_pending: Vec<u8> = vec![];
+        let mut stderr_pending: Vec<u8> = vec![];
+        let mut buffer = [0u8; 4096];
+
+        session.set_blocking(false);
+
+        let result = loop {
+            // Conferido a cada volta, para que um comando que não para de imprimir também termine.
+            if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                let _ = channel.close();
+                break Err(io::Error::new(io::ErrorKind::TimedOut, "Tempo limite do comando excedido"));
+            }
+
+            let mut received = false;
+
+            match read_stream(channel, &mut buffer, &mut stdout_pending) {
+                Ok(Some(text)) => {
+                    received = true;
+                    on_output(OutputStream::Stdout, &text);
+                    output.push_str(&text);
+                },
+                Ok(None) => {},
+                Err(e) => break Err(e)
+            }
+
+            match read_stream(&mut channel.stderr(), &mut buffer, &mut stderr_pending) {
+                Ok(Some(text)) => {
+                    received = true;
+                    on_output(OutputStream::Stderr, &text);
+                    errors.push_str(&text);
+                },
+                Ok(None) => {},
+                Err(e) => break Err(e)
+            }
+
+            if !received {
+                if channel.eof() {
+                    break Ok(());
+                }
+
+                let _ = session.keepalive_send();
+                thread::sleep(Duration::from_millis(20));
+            }
+        };
+
+        session.set_blocking(true);
+        result?;
+
+        if !stdout_pending.is_empty() {
+            let text = String::from_utf8_lossy(&stdout_pending).into_owned();
+            on_output(OutputStream::Stdout, &text);
+            output.push_str(&text);
         }
 
+        if !stderr_pending.is_empty() {
+            let text = String::from_utf8_lossy(&stderr_pending).into_owned();
+            on_output(OutputStream::Stderr, &text);
+            errors.push_str(&text);
+        }
+
+        Ok((output, errors))
+    }
+}
+
+/// Como a espera por uma etapa terminou.
+enum StepEnd {
+    Status(i32),
+    /// O shell remoto terminou antes de imprimir o marcador.
+    Closed,
+    TimedOut,
+    Cancelled,
+}
+
+/// Envolve o script em `sudo` quando o comando declara `become`/`run_as`.
+/// O script é passado como um único argumento escapado para `sh -c`.
+pub fn privileged(server_commands: &ServerCommands, script: &str) -> String {
+    match server_commands.become_user() {
+        Some(user) => format!("sudo -n -u {} -- sh -c {}", shell_quote(user), shell_quote(script)),
+        None => script.to_string()
+    }
+}
+
+/// Shell que recebe as etapas pelo stdin, elevado com `sudo` quando o comando declara `become`/`run_as`.
+fn privileged_shell(server_commands: &ServerCommands) -> String {
+    match server_commands.become_user() {
+        Some(user) => format!("sudo -n -u {} -- sh -s", shell_quote(user)),
+        None => String::from("sh -s")
+    }
+}
+
+/// Lê o que estiver disponível em um fluxo não bloqueante, devolvendo o texto UTF-8 completo recebido.
+fn read_stream<R: Read>(stream: &mut R, buffer: &mut [u8], pending: &mut Vec<u8>) -> io::Result<Option<String>> {
+    match stream.read(buffer) {
+        Ok(0) => Ok(None),
+        Ok(size) => {
+            pending.extend_from_slice(&buffer[..size]);
+            Ok(Some(take_utf8(pending)))
+        },
+        Err(e) if e.kind() == io::ErrorKind::WouldBlock => Ok(None),
+        Err(e) => Err(e)
     }
 }
 
+/// Marcador único impresso pelo shell remoto ao final de cada etapa.
+fn step_marker() -> String {
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH)
+                                 .map(|elapsed| elapsed.subsec_nanos())
+                                 .unwrap_or_default();
+
+    format!("__SERVER_MANAGER_STEP_{}_{}__", process::id(), nanos)
+}
+
+/// Script enviado ao shell para uma etapa: executa o comando sem acesso ao stdin do shell
+/// e imprime o marcador com o código de saída em stderr e stdout.
+fn step_script(command: &str, marker: &str) -> String {
+    format!(
+        "{{ {}\n}} </dev/null\n__sm_status=$?\nprintf '%s:%d\\n' '{marker}' \"$__sm_status\" >&2\nprintf '%s:%d\\n' '{marker}' \"$__sm_status\"\n",
+        command
+        )
+}
+
+struct StepStreams {
+    stdout: StepBuffer,
+    stderr: StepBuffer,
+    stdout_pending: Vec<u8>,
+    stderr_pending: Vec<u8>,
+}
+
+impl StepStreams {
+    fn new(marker: &str) -> Self {
+        Self {
+            stdout: StepBuffer::new(marker),
+            stderr: StepBuffer::new(marker),
+            stdout_pending: vec![],
+            stderr_pending: vec![],
+        }
+    }
+}
+
+/// Separa a saída de uma etapa do marcador de fim, segurando trechos que podem ser o início do marcador.
+struct StepBuffer {
+    marker: String,
+    buffer: String,
+}
+
+impl StepBuffer {
+    fn new(marker: &str) -> Self {
+        Self {
+            marker: marker.to_string(),
+            buffer: String::new(),
+        }
+    }
+
+    /// Retorna o texto que já pode ser exibido e, se o marcador foi encontrado, o código de saída da etapa.
+    fn feed(&mut self, text: &str) -> (String, Option<i32>) {
+        self.buffer.push_str(text);
+
+        if let Some(position) = self.buffer.find(&self.marker) {
+            let after = position + self.marker.len();
+
+            return match self.buffer[after..].find('\n') {
+                Some(end) => {
+                    let code = self.buffer[after..after + end].trim_start_matches(':')
+                                                              .trim()
+                                                              .parse()
+                                                              .unwrap_or(-1);
+                    let text = self.buffer[..position].to_string();
+                    self.buffer = self.buffer[after + end + 1..].to_string();
+
+                    (text, Some(code))
+                },
+                None => {
+                    let text = self.buffer[..position].to_string();
+                    self.buffer.drain(..position);
+
+                    (text, None)
+                }
+            };
+        }
+
+        let keep = (1..self.marker.len()).rev()
+                                         .find(|len| self.buffer.ends_with(&self.marker[..*len]))
+                                         .unwrap_or(0);
+        let ready = self.buffer.len() - keep;
+        let text = self.buffer[..ready].to_string();
+        self.buffer.drain(..ready);
+
+        (text, None)
+    }
+
+    fn take_rest(&mut self) -> String {
+        std::mem::take(&mut self.buffer)
+    }
+}
+
+/// Retira de `pending` o maior prefixo UTF-8 válido, mantendo um caractere multibyte incompleto para a próxima leitura.
+fn take_utf8(pending: &mut Vec<u8>) -> String {
+    let valid = match std::str::from_utf8(pending) {
+        Ok(text) => text.len(),
+        Err(e) if e.error_len().is_none() => e.valid_up_to(),
+        Err(_) => pending.len()
+    };
+
+    let text = String::from_utf8_lossy(&pending[..valid]).into_owned();
+    pending.drain(..valid);
+    text
+}
+
 #[test]
+#[ignore = "requer um servidor SSH acessível configurado em config.yaml"]
 fn test_connect_server() {
     let path = "config.yaml";
 
-    let config = ConfigYaml::new(path).unwrap();
+    let config = crate::parser::ConfigYaml::new(path).unwrap();
 
     let server = config.get_info_server("Servidor 1").unwrap();
 
@@ -162,10 +948,11 @@ fn test_connect_server() {
 }
 
 #[test]
+#[ignore = "requer um servidor SSH acessível configurado em config.yaml"]
 fn test_command_server() {
     let path = "config.yaml";
 
-    let config = ConfigYaml::new(path).unwrap();
+    let config = crate::parser::ConfigYaml::new(path).unwrap();
 
     let server = config.get_info_server("Servidor 1").unwrap();
 
@@ -178,32 +965,124 @@ fn test_command_server() {
 
     let commands = server.2[0].clone();
 
-    ssh.execute_commands(&commands, session);
+    let result = ssh.execute_commands(&commands, session);
+
+    assert!(result.is_ok(),"Falha ao executar os comandos: {:?}",result.err());
 }
 
 #[test]
-fn test_manager_commands_ssh_key() {
-    let commands_list = vec![
-        String::from("sudo su -c"),
-        String::from("cd /home/ubuntu"),
-        String::from("touch testando_commands.txt")
-    ];
-
-    let prefix = commands_list[0].clone();
-
-    let remaining_commands: Vec<String> = commands_list.iter()
-                                         .skip(1)
-                                         .cloned()
-                                         .collect();
-    let formatted_commands = vec![format!(
-        "{} '{}'",
-        prefix,
-        remaining_commands.join(" && ")
-        )];
-
-    let result = SSH::manager_commands(&formatted_commands, |commands| commands.join(""));
-
-    let expected ="sudo su -c 'cd /home/ubuntu && touch testando_commands.txt'";
-
-    assert_eq!(result,expected,"Comands concatenados não correspondem ao formato esperado");
-}
\ No newline at end of file
+fn test_take_utf8_partial_chars() {
+    let bytes = "Saída".as_bytes();
+
+    let mut pending = bytes[..3].to_vec();
+    assert_eq!(take_utf8(&mut pending),"Sa");
+    assert_eq!(pending.len(),1);
+
+    pending.extend_from_slice(&bytes[3..]);
+    assert_eq!(take_utf8(&mut pending),"ída");
+    assert!(pending.is_empty());
+}
+
+#[test]
+fn test_step_buffer_marker_across_chunks() {
+    let mut buffer = StepBuffer::new("__FIM__");
+
+    assert_eq!(buffer.feed("Cloning into 'repo'...\n__F"),(String::from("Cloning into 'repo'...\n"),None));
+    assert_eq!(buffer.feed("IM"),(String::new(),None));
+    assert_eq!(buffer.feed("__:128"),(String::new(),None));
+    assert_eq!(buffer.feed("\n"),(String::new(),Some(128)));
+
+    assert_eq!(buffer.feed("sem quebra de linha__FIM__:0\n"),(String::from("sem quebra de linha"),Some(0)));
+    assert_eq!(buffer.take_rest(),"");
+}
+
+#[test]
+fn test_privileged_quotes_script() {
+    let config = crate::parser::ConfigYaml::new("config.yaml").unwrap();
+
+    let (_,_,commands) = config.get_info_server("Servidor 2").unwrap();
+
+    let script = SSH::manager_commands(&commands[0].commands(), |commands| commands.join(" && "));
+
+    assert_eq!(
+        privileged(&commands[0], &script),
+        "sudo -n -u root -- sh -c 'apachectl configtest && systemctl restart httpd'"
+    );
+    assert_eq!(
+        privileged(&commands[1], "echo \"it's\""),
+        "sudo -n -u deploy -- sh -c 'echo \"it'\\''s\"'"
+    );
+    assert_eq!(privileged_shell(&commands[0]),"sudo -n -u root -- sh -s");
+    assert_eq!(privileged_shell(&commands[1]),"sudo -n -u deploy -- sh -s");
+}
+
+/// Porta local que acabou de ser liberada: conectar nela é recusado na hora, sem depender de a
+/// porta 1 estar fechada na máquina que roda os testes.
+#[cfg(test)]
+pub(crate) fn closed_port() -> u16 {
+    std::net::TcpListener::bind("127.0.0.1:0").and_then(|listener| listener.local_addr()).unwrap().port()
+}
+
+/// Conexão por senha para uma `closed_port`, usada pelos testes de falha de conexão.
+#[cfg(test)]
+pub(crate) fn refused_connect() -> ServerConnect {
+    serde_yaml_ng::from_str(&format!(
+        "type_connection: SSH\nuser: deploy\nhostname: 127.0.0.1\nport: {}\nconnect_timeout: 2\npassword: null\n",
+        closed_port()
+        )).unwrap()
+}
+
+#[test]
+fn test_connect_tcp_resolves_hostname_and_port() {
+    let connect = refused_connect();
+
+    let ssh = SSH::new(&connect);
+    assert_eq!((ssh.host.as_str(), ssh.connect_timeout), ("127.0.0.1", Duration::from_secs(2)));
+    assert_eq!(ssh.port, connect.port());
+
+    let started = Instant::now();
+    assert!(matches!(ssh.connect_tcp(), Err(ConnectionError::Tcp { .. })));
+    assert!(started.elapsed() < Duration::from_secs(5));
+}
+
+#[test]
+fn test_private_key_missing_is_reported() {
+    let connect: ServerConnect = serde_yaml_ng::from_str(
+        "type_connection: SSH_KEY\nuser: deploy\nhostname: localhost\nport: 1\nlocation: /nao/existe/id_ed25519\npassword: null\n"
+        ).unwrap();
+
+    match SSH::new(&connect).open_session() {
+        Err(ConnectionError::KeyNotFound { path }) => assert_eq!(path, Some(std::path::PathBuf::from("/nao/existe/id_ed25519"))),
+        other => panic!("Esperado KeyNotFound, recebido {:?}",other.err())
+    }
+}
+
+#[test]
+fn test_jump_host_failures_are_wrapped() {
+    let unresolved: ServerConnect = serde_yaml_ng::from_str(
+        "type_connection: SSH\nuser: deploy\nip_address: 10.0.1.5\njump_host: Bastião\nlocation: null\npassword: null\n"
+        ).unwrap();
+    assert!(matches!(SSH::new(&unresolved).open_session(), Err(ConnectionError::Tunnel { .. })));
+
+    let port = closed_port();
+    let config: crate::parser::ConfigYaml = serde_yaml_ng::from_str(&format!(r#"
+version: "1.0.0"
+application: "Teste"
+servers:
+  - name: "Bastião"
+    config: {{ os: "Debian", memory: "1GB", disk: "10GB" }}
+    connect: {{ type_connection: SSH, user: "ops", hostname: "localhost", port: {port}, connect_timeout: 2 }}
+  - name: "App"
+    config: {{ os: "Ubuntu", memory: "4GB", disk: "40GB" }}
+    connect: {{ type_connection: SSH, user: "deploy", ip_address: "10.0.1.5", jump_host: "Bastião" }}
+"#)).unwrap();
+    let (_, connect, _) = config.get_info_server("App").unwrap();
+
+    match SSH::new(&connect).open_session() {
+        Err(error @ ConnectionError::JumpHost { .. }) => {
+            assert!(error.to_string().starts_with(&format!("Falha no jump host \"Bastião\": Não foi possivel conectar em localhost:{}", port)));
+            assert!(error.is_retryable());
+        },
+        other => panic!("Esperado JumpHost, recebido {:?}",other.err())
+    }
+}
@@ -0,0 +1,251 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{mpsc, Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{parser::{ConfigYaml, ServerCommands, ServerConnect}, secrets::PromptAnswers};
+
+use super::{CancelToken, ConnectionError, OutputStream, StepEvent, SSH};
+
+/// Servidor e comando (com os placeholders já preenchidos) de uma execução em lote.
+#[derive(Debug,Clone)]
+pub struct ServerTarget {
+    pub server: String,
+    pub connect: ServerConnect,
+    pub command: ServerCommands,
+    /// Respostas para as senhas `prompt`, coletadas antes de iniciar as threads.
+    pub prompt_answers: PromptAnswers,
+}
+
+#[derive(Debug,PartialEq, Eq,Clone)]
+pub enum ServerStatus {
+    Pending,
+    Running,
+    Succeeded,
+    /// Código de saída da etapa que interrompeu a execução.
+    Failed(i32),
+    /// Falha de conexão ou de comunicação com o servidor.
+    Error(String),
+    /// Execução interrompida pelo usuário, ou que nem chegou a começar.
+    Cancelled,
+}
+
+/// Resultado da execução em um servidor, com a saída completa recebida.
+#[derive(Debug,Clone)]
+pub struct ServerRun {
+    server: String,
+    status: ServerStatus,
+    output: Vec<(OutputStream,String)>,
+    duration: Duration,
+    auth_failure: bool,
+}
+
+impl ServerRun {
+    pub fn server(&self) -> &str {
+        &self.server
+    }
+
+    pub fn status(&self) -> &ServerStatus {
+        &self.status
+    }
+
+    pub fn output(&self) -> &Vec<(OutputStream,String)> {
+        &self.output
+    }
+
+    pub fn duration(&self) -> Duration {
+        self.duration
+    }
+
+    /// O servidor recusou a senha ou a chave; as senhas digitadas para ele devem ser pedidas de novo.
+    pub fn is_auth_failure(&self) -> bool {
+        self.auth_failure
+    }
+}
+
+/// Monta os alvos procurando o comando pelo nome em cada servidor e preenchendo os mesmos valores.
+pub fn build_targets(config: &ConfigYaml, servers: &[String], command: &str, values: &HashMap<String,String>) -> Result<Vec<ServerTarget>,String> {
+    servers.iter().map(|server| {
+        let (_, connect, commands) = config.get_info_server(server)
+                                           .ok_or_else(|| format!("Servidor não encontrado: {}",server))?;
+
+        let selected = commands.iter()
+                               .find(|item| item.name() == command)
+                               .ok_or_else(|| format!("Comando \"{}\" não encontrado no servidor \"{}\"",command,server))?;
+
+        Ok(ServerTarget {
+            server: server.clone(),
+            connect,
+            command: selected.fill_placeholders(values)?,
+            prompt_answers: PromptAnswers::default(),
+        })
+    }).collect()
+}
+
+pub enum ParallelEvent<'a> {
+    Started { index: usize, server: &'a str },
+    Finished { index: usize, run: &'a ServerRun },
+}
+
+/// Executa os alvos em até `limit` threads simultâneas. Os eventos são entregues na thread que
+/// chamou a função, e o resultado segue a ordem de `targets`.
+pub fn run_parallel<F>(targets: Vec<ServerTarget>, limit: usize, on_event: F) -> Vec<ServerRun>
+where
+    F: FnMut(ParallelEvent),
+{
+    run_parallel_cancelable(targets, limit, &CancelToken::default(), on_event)
+}
+
+/// Como `run_parallel`; depois de `cancel` os servidores em andamento são interrompidos e os
+/// que ainda não começaram terminam como `ServerStatus::Cancelled`.
+pub fn run_parallel_cancelable<F>(targets: Vec<ServerTarget>, limit: usize, cancel: &CancelToken, mut on_event: F) -> Vec<ServerRun>
+where
+    F: FnMut(ParallelEvent),
+{
+    let names: Vec<String> = targets.iter().map(|target| target.server.clone()).collect();
+    let workers_count = limit.clamp(1, targets.len().max(1));
+    let queue = Arc::new(Mutex::new(targets.into_iter().enumerate().collect::<VecDeque<_>>()));
+    let (sender, receiver) = mpsc::channel();
+
+    let workers: Vec<_> = (0..workers_count).map(|_| {
+        let queue = Arc::clone(&queue);
+        let sender = sender.clone();
+        let cancel = cancel.clone();
+
+        thread::spawn(move || loop {
+            if cancel.is_cancelled() {
+                break;
+            }
+
+            let next = match queue.lock() {
+                Ok(mut queue) => queue.pop_front(),
+                Err(_) => None
+            };
+
+            let Some((index, target)) = next else { break };
+
+            let _ = sender.send((index, None));
+            let run = run_target(&target, &cancel);
+            let _ = sender.send((index, Some(run)));
+        })
+    }).collect();
+    drop(sender);
+
+    let mut runs: Vec<Option<ServerRun>> = vec![None; names.len()];
+
+    for (index, run) in receiver {
+        match run {
+            None => on_event(ParallelEvent::Started { index, server: &names[index] }),
+            Some(run) => {
+                on_event(ParallelEvent::Finished { index, run: &run });
+                runs[index] = Some(run);
+            }
+        }
+    }
+
+    for worker in workers {
+        let _ = worker.join();
+    }
+
+    runs.into_iter()
+        .zip(names)
+        .map(|(run, server)| run.unwrap_or(ServerRun {
+            server,
+            status: if cancel.is_cancelled() { ServerStatus::Cancelled } else { ServerStatus::Error(String::from("Execução interrompida")) },
+            output: vec![],
+            duration: Duration::ZERO,
+            auth_failure: false,
+        }))
+        .collect()
+}
+
+fn run_target(target: &ServerTarget, cancel: &CancelToken) -> ServerRun {
+    let start = Instant::now();
+    let mut output: Vec<(OutputStream,String)> = vec![];
+
+    let ssh = SSH::new(&target.connect).with_prompt_answers(&target.prompt_answers);
+    let session = ssh.open_session();
+    let auth_failure = session.as_ref().err().is_some_and(ConnectionError::is_auth_failure);
+
+    let result = session.map_err(|e| format!("Não foi possivel conectar-se ao servidor: {}",e))
+                    .and_then(|session| ssh.execute_steps_cancelable(&target.command, session, cancel, |event| {
+                        match event {
+                            StepEvent::Started { command, .. } => output.push((OutputStream::Stdout, format!("$ {}\n",command))),
+                            StepEvent::Output { stream, text, .. } => output.push((stream, text.to_string())),
+                            StepEvent::Progress { .. } | StepEvent::Finished { .. } => {}
+                        }
+                    }).map_err(|e| format!("Erro ao executar os comandos: {}",e)));
+
+    let status = match result {
+        Ok(report) if report.is_cancelled() => ServerStatus::Cancelled,
+        Ok(report) => match report.failed_step() {
+            None => ServerStatus::Succeeded,
+            Some(index) => ServerStatus::Failed(report.steps()[index].exit_code())
+        },
+        Err(message) => ServerStatus::Error(message)
+    };
+
+    if let ServerStatus::Error(message) = &status {
+        output.push((OutputStream::Stderr, format!("{}\n",message)));
+    }
+
+    ServerRun {
+        server: target.server.clone(),
+        status,
+        output,
+        duration: start.elapsed(),
+        auth_failure,
+    }
+}
+
+#[test]
+fn test_run_parallel_keeps_order_and_reports_errors() {
+    let config = crate::parser::ConfigYaml::new("config.yaml").unwrap();
+    let (_, mut connect, commands) = config.get_info_server("Servidor 1").unwrap();
+    *connect.ip_address_mut() = format!("127.0.0.1:{}", super::closed_port());
+
+    let targets: Vec<ServerTarget> = ["A", "B", "C"].iter()
+                                                    .map(|name| ServerTarget {
+                                                        server: name.to_string(),
+                                                        connect: connect.clone(),
+                                                        command: commands[0].clone(),
+                                                        prompt_answers: PromptAnswers::default(),
+                                                    })
+                                                    .collect();
+
+    let mut started = 0;
+    let mut finished = 0;
+    let runs = run_parallel(targets, 2, |event| match event {
+        ParallelEvent::Started { .. } => started += 1,
+        ParallelEvent::Finished { .. } => finished += 1,
+    });
+
+    assert_eq!((started, finished), (3, 3));
+    assert_eq!(runs.iter().map(|run| run.server()).collect::<Vec<_>>(), vec!["A", "B", "C"]);
+    // Servidor com senha: a falha é a conexão recusada, não a chave ausente do `SSH_KEY`.
+    assert!(runs.iter().all(|run| matches!(run.status(), ServerStatus::Error(message) if message.contains("Não foi possivel conectar em 127.0.0.1"))));
+}
+
+#[test]
+fn test_cancelled_run_skips_pending_servers() {
+    let config = crate::parser::ConfigYaml::new("config.yaml").unwrap();
+    let (_, mut connect, commands) = config.get_info_server("Servidor 1").unwrap();
+    *connect.ip_address_mut() = format!("127.0.0.1:{}", super::closed_port());
+
+    let targets: Vec<ServerTarget> = ["A", "B"].iter()
+                                               .map(|name| ServerTarget {
+                                                   server: name.to_string(),
+                                                   connect: connect.clone(),
+                                                   command: commands[0].clone(),
+                                                   prompt_answers: PromptAnswers::default(),
+                                               })
+                                               .collect();
+
+    let cancel = CancelToken::default();
+    cancel.cancel();
+
+    let runs = run_parallel_cancelable(targets, 2, &cancel, |_| {});
+    assert!(runs.iter().all(|run| *run.status() == ServerStatus::Cancelled));
+}
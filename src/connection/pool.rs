@@ -0,0 +1,159 @@
+use std::{collections::HashMap, time::{Duration, Instant}};
+
+use ssh2::Session;
+
+use super::{ConnectionError, SSH};
+
+/// Intervalo de keepalive das sessões guardadas quando a conexão não define `keepalive_interval`.
+const DEFAULT_KEEPALIVE_INTERVAL: u32 = 30;
+
+/// Tempo sem uso depois do qual uma sessão guardada é encerrada.
+pub const DEFAULT_IDLE_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+/// Sessão autenticada e a conexão que a originou.
+struct PooledSession {
+    ssh: SSH,
+    session: Session,
+    last_used: Instant,
+}
+
+/// Mantém uma sessão autenticada por servidor para que comandos seguidos no mesmo host não
+/// repitam conexão TCP, handshake e autenticação. Antes de ser reaproveitada a sessão é testada
+/// com a abertura de um canal; se o servidor caiu ou a configuração mudou, outra é aberta.
+pub struct SessionPool {
+    sessions: HashMap<String,PooledSession>,
+    idle_timeout: Duration,
+}
+
+impl Default for SessionPool {
+    fn default() -> Self {
+        SessionPool::new(DEFAULT_IDLE_TIMEOUT)
+    }
+}
+
+impl SessionPool {
+    pub fn new(idle_timeout: Duration) -> Self {
+        SessionPool {
+            sessions: HashMap::new(),
+            idle_timeout,
+        }
+    }
+
+    /// Sessão do servidor `name`, reaproveitada quando ainda responde ou aberta com `ssh`.
+    /// O segundo valor indica se a sessão foi reaproveitada.
+    pub fn session(&mut self, name: &str, ssh: &SSH) -> Result<(Session,bool),ConnectionError> {
+        if let Some(pooled) = self.sessions.get_mut(name) {
+            if is_reusable(&pooled.ssh, ssh, pooled.last_used.elapsed(), self.idle_timeout) && is_alive(&pooled.session, ssh) {
+                pooled.last_used = Instant::now();
+                return Ok((pooled.session.clone(), true));
+            }
+        }
+        self.discard(name);
+
+        let session = ssh.open_session()?;
+        if ssh.keepalive_interval.is_none() {
+            session.set_keepalive(false, DEFAULT_KEEPALIVE_INTERVAL);
+        }
+
+        self.sessions.insert(name.to_string(), PooledSession {
+            ssh: ssh.clone(),
+            session: session.clone(),
+            last_used: Instant::now(),
+        });
+        Ok((session, false))
+    }
+
+    /// Marca a sessão do servidor como em uso, adiando o encerramento por ociosidade.
+    pub fn touch(&mut self, name: &str) {
+        if let Some(pooled) = self.sessions.get_mut(name) {
+            pooled.last_used = Instant::now();
+        }
+    }
+
+    /// Esquece a sessão do servidor, por exemplo depois de um erro no canal.
+    pub fn discard(&mut self, name: &str) {
+        self.sessions.remove(name);
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.sessions.contains_key(name)
+    }
+
+    pub fn len(&self) -> usize {
+        self.sessions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sessions.is_empty()
+    }
+
+    /// Envia os keepalives pendentes e descarta as sessões ociosas ou que deram erro.
+    /// Deve ser chamado periodicamente enquanto há sessões guardadas.
+    pub fn keepalive(&mut self) {
+        let idle_timeout = self.idle_timeout;
+
+        self.sessions.retain(|_, pooled| {
+            if pooled.last_used.elapsed() >= idle_timeout {
+                close(&pooled.session);
+                return false;
+            }
+            pooled.session.keepalive_send().is_ok()
+        });
+    }
+
+    /// Encerra todas as sessões avisando os servidores.
+    pub fn close_all(&mut self) {
+        for (_, pooled) in self.sessions.drain() {
+            close(&pooled.session);
+        }
+    }
+}
+
+/// A sessão guardada só serve se foi aberta com a mesma configuração e não ficou ociosa demais;
+/// mesmo assim ela ainda passa por `is_alive` antes de ser reaproveitada.
+fn is_reusable(pooled: &SSH, requested: &SSH, idle: Duration, idle_timeout: Duration) -> bool {
+    pooled == requested && idle < idle_timeout
+}
+
+/// Abre e fecha um canal com o tempo limite da conexão; uma sessão morta falha aqui antes de
+/// qualquer comando ser enviado.
+fn is_alive(session: &Session, ssh: &SSH) -> bool {
+    session.set_timeout(ssh.connect_timeout.as_millis().min(u32::MAX as u128) as u32);
+
+    let alive = session.channel_session()
+                       .and_then(|mut channel| channel.close())
+                       .is_ok();
+
+    session.set_timeout(0);
+    alive
+}
+
+fn close(session: &Session) {
+    session.set_timeout(1000);
+    let _ = session.disconnect(None, "server_automation: sessão encerrada", None);
+}
+
+#[test]
+fn test_pool_reuse_decision() {
+    let connect = super::refused_connect();
+    let ssh = SSH::new(&connect);
+    let idle_timeout = Duration::from_secs(60);
+
+    assert!(is_reusable(&ssh, &SSH::new(&connect), Duration::from_secs(5), idle_timeout));
+
+    let mut changed = connect.clone();
+    *changed.password_mut() = Some(crate::secrets::Secret::new("env:OUTRA_SENHA"));
+    assert!(!is_reusable(&ssh, &SSH::new(&changed), Duration::from_secs(5), idle_timeout));
+
+    assert!(!is_reusable(&ssh, &SSH::new(&connect), idle_timeout, idle_timeout));
+}
+
+#[test]
+fn test_pool_reports_connection_errors() {
+    let connect = super::refused_connect();
+
+    let mut pool = SessionPool::default();
+    assert!(matches!(pool.session("Servidor 1", &SSH::new(&connect)), Err(ConnectionError::Tcp { .. })));
+    assert!(!pool.contains("Servidor 1"));
+    assert!(pool.is_empty());
+}
@@ -0,0 +1,286 @@
+use std::{
+    io::{self, Read},
+    time::Duration,
+};
+
+use ssh2::Session;
+
+use crate::parser::ServerConfig;
+
+/// Diferença aceita entre o tamanho declarado e o medido: o `MemTotal` desconta a memória
+/// reservada pelo kernel e os discos costumam ser vendidos em unidades decimais.
+const SIZE_TOLERANCE: f64 = 0.10;
+
+/// Comando enviado ao servidor; cada seção começa com uma linha `@@nome`.
+const PROBE_SCRIPT: &str = "\
+echo @@os; cat /etc/os-release 2>/dev/null; \
+echo @@kernel; uname -sr; \
+echo @@uptime; cat /proc/uptime 2>/dev/null; \
+echo @@cpus; nproc 2>/dev/null || getconf _NPROCESSORS_ONLN; \
+echo @@load; cat /proc/loadavg 2>/dev/null; \
+echo @@memory; cat /proc/meminfo 2>/dev/null; \
+echo @@disk; df -P -k -x tmpfs -x devtmpfs -x squashfs -x overlay -x nfs -x nfs4 -x cifs 2>/dev/null || df -P -k";
+
+/// Sistemas de arquivos que não são disco do servidor. O `df` do BusyBox não aceita `-x`, então
+/// eles também são descartados na leitura.
+const IGNORED_FILESYSTEMS: [&str; 6] = ["tmpfs", "devtmpfs", "squashfs", "overlay", "none", "udev"];
+
+/// Campos de `ServerConfig` comparados com o servidor.
+#[derive(Debug,PartialEq, Eq,Clone,Copy)]
+pub enum ConfigField {
+    Os,
+    Memory,
+    Disk,
+}
+
+/// Uso de um sistema de arquivos montado, em bytes.
+#[derive(Debug,PartialEq, Eq,Clone)]
+pub struct MountUsage {
+    /// Primeira coluna do `df`: o dispositivo ou a origem da montagem.
+    pub device: String,
+    pub mount: String,
+    pub size: u64,
+    pub used: u64,
+    pub available: u64,
+}
+
+impl MountUsage {
+    pub fn used_percent(&self) -> u64 {
+        match self.used + self.available {
+            0 => 0,
+            total => self.used * 100 / total
+        }
+    }
+}
+
+/// Situação real do servidor coletada por `probe`. Campos que o servidor não informou ficam vazios.
+/// A sondagem só exibe e compara: o `ServerConfig` continua sendo o declarado no YAML.
+#[derive(Debug,PartialEq,Clone,Default)]
+pub struct HealthReport {
+    os: Option<String>,
+    kernel: Option<String>,
+    uptime: Option<Duration>,
+    cpus: Option<u32>,
+    load: Option<[f64; 3]>,
+    memory_total: Option<u64>,
+    memory_available: Option<u64>,
+    mounts: Vec<MountUsage>,
+}
+
+impl HealthReport {
+    /// `PRETTY_NAME` do `/etc/os-release`.
+    pub fn os(&self) -> Option<&str> {
+        self.os.as_deref()
+    }
+
+    pub fn kernel(&self) -> Option<&str> {
+        self.kernel.as_deref()
+    }
+
+    pub fn uptime(&self) -> Option<Duration> {
+        self.uptime
+    }
+
+    pub fn cpus(&self) -> Option<u32> {
+        self.cpus
+    }
+
+    /// Carga média de 1, 5 e 15 minutos.
+    pub fn load(&self) -> Option<[f64; 3]> {
+        self.load
+    }
+
+    pub fn memory_total(&self) -> Option<u64> {
+        self.memory_total
+    }
+
+    pub fn memory_available(&self) -> Option<u64> {
+        self.memory_available
+    }
+
+    pub fn mounts(&self) -> &Vec<MountUsage> {
+        &self.mounts
+    }
+
+    /// Soma dos dispositivos montados; cada dispositivo aparece uma vez em `mounts`.
+    pub fn disk_total(&self) -> Option<u64> {
+        match self.mounts.is_empty() {
+            true => None,
+            false => Some(self.mounts.iter().map(|mount| mount.size).sum())
+        }
+    }
+
+    /// Compara o campo declarado com o medido. `None` quando não dá para comparar: o servidor
+    /// não informou o valor ou o declarado não é um tamanho.
+    pub fn matches(&self, config: &ServerConfig, field: ConfigField) -> Option<bool> {
+        match field {
+            ConfigField::Os => {
+                let declared = config.os().trim().to_lowercase();
+                let actual = self.os.as_ref()?.to_lowercase();
+                (!declared.is_empty()).then(|| actual.contains(&declared))
+            },
+            ConfigField::Memory => Some(within_tolerance(config.memory_bytes()?, self.memory_total?)),
+            ConfigField::Disk => Some(within_tolerance(config.disk_bytes()?, self.disk_total()?)),
+        }
+    }
+
+    /// Campos de `ServerConfig` que não conferem com o servidor.
+    pub fn mismatches(&self, config: &ServerConfig) -> Vec<ConfigField> {
+        [ConfigField::Os, ConfigField::Memory, ConfigField::Disk].into_iter()
+                                                                .filter(|field| self.matches(config, *field) == Some(false))
+                                                                .collect()
+    }
+}
+
+/// Executa o `PROBE_SCRIPT` na sessão e interpreta a saída.
+pub(crate) fn probe(session: &Session) -> io::Result<HealthReport> {
+    let mut channel = session.channel_session()?;
+    channel.exec(PROBE_SCRIPT)?;
+
+    let mut output = String::new();
+    channel.read_to_string(&mut output)?;
+    channel.wait_close()?;
+
+    Ok(parse_probe(&output))
+}
+
+fn within_tolerance(declared: u64, actual: u64) -> bool {
+    (declared as f64 - actual as f64).abs() <= declared.max(actual) as f64 * SIZE_TOLERANCE
+}
+
+fn parse_probe(output: &str) -> HealthReport {
+    let mut report = HealthReport::default();
+    let mut section = "";
+
+    for line in output.lines() {
+        if let Some(name) = line.strip_prefix("@@") {
+            section = name.trim();
+            continue;
+        }
+
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        match section {
+            "os" => {
+                if let Some((key, value)) = line.split_once('=') {
+                    let value = value.trim_matches('"').to_string();
+                    // `NAME` só vale se não houver `PRETTY_NAME`, que é mais completo.
+                    match key {
+                        "PRETTY_NAME" => report.os = Some(value),
+                        "NAME" if report.os.is_none() => report.os = Some(value),
+                        _ => {}
+                    }
+                }
+            },
+            "kernel" => report.kernel = Some(line.to_string()),
+            "uptime" => {
+                report.uptime = line.split_whitespace()
+                                    .next()
+                                    .and_then(|seconds| seconds.parse::<f64>().ok())
+                                    .map(Duration::from_secs_f64);
+            },
+            "cpus" => report.cpus = report.cpus.or(line.parse().ok()),
+            "load" => {
+                let values: Vec<f64> = line.split_whitespace().take(3).filter_map(|value| value.parse().ok()).collect();
+                if let [one, five, fifteen] = values[..] {
+                    report.load = Some([one, five, fifteen]);
+                }
+            },
+            "memory" => {
+                let mut fields = line.split_whitespace();
+                let key = fields.next().unwrap_or_default();
+                let kib = fields.next().and_then(|value| value.parse::<u64>().ok()).map(|kib| kib * 1024);
+
+                match key {
+                    "MemTotal:" => report.memory_total = kib,
+                    "MemAvailable:" => report.memory_available = kib,
+                    _ => {}
+                }
+            },
+            "disk" => {
+                let fields: Vec<&str> = line.split_whitespace().collect();
+                if fields.len() < 6 {
+                    continue;
+                }
+
+                // Montagens de rede (`host:/dir`, `//host/dir`) e pseudo-sistemas não são disco do servidor.
+                let device = fields[0];
+                if IGNORED_FILESYSTEMS.contains(&device) || device.contains(":/") || device.starts_with("//") {
+                    continue;
+                }
+
+                // Bind mounts e subvolumes repetem o dispositivo; só a primeira montagem conta.
+                if report.mounts.iter().any(|mount| mount.device == device) {
+                    continue;
+                }
+
+                // O cabeçalho do `df` não tem números e fica de fora aqui.
+                let number = |index: usize| fields[index].parse::<u64>().ok().map(|kib| kib * 1024);
+                if let (Some(size), Some(used), Some(available)) = (number(1), number(2), number(3)) {
+                    report.mounts.push(MountUsage {
+                        device: device.to_string(),
+                        mount: fields[5..].join(" "),
+                        size,
+                        used,
+                        available,
+                    });
+                }
+            },
+            _ => {}
+        }
+    }
+
+    report
+}
+
+#[test]
+fn test_parse_probe() {
+    let output = "\
+@@os
+NAME=\"Ubuntu\"
+PRETTY_NAME=\"Ubuntu 22.04.3 LTS\"
+@@kernel
+Linux 5.15.0-91-generic
+@@uptime
+1051200.52 4000000.00
+@@cpus
+4
+@@load
+0.12 0.08 0.05 1/234 5678
+@@memory
+MemTotal:       32768000 kB
+MemFree:         1000000 kB
+MemAvailable:   12000000 kB
+@@disk
+Filesystem     1024-blocks     Used Available Capacity Mounted on
+/dev/sda1        314572800 157286400 157286400      50% /
+/dev/sdb1        104857600  10485760  94371840      10% /var/lib/data
+/dev/sdb1        104857600  10485760  94371840      10% /srv/data
+tmpfs              1638400         0   1638400       0% /run
+nas:/exports      524288000 262144000 262144000      50% /mnt/backup
+";
+
+    let report = parse_probe(output);
+    assert_eq!(report.os(), Some("Ubuntu 22.04.3 LTS"));
+    assert_eq!(report.kernel(), Some("Linux 5.15.0-91-generic"));
+    assert_eq!(report.uptime().map(|uptime| uptime.as_secs()), Some(1051200));
+    assert_eq!(report.cpus(), Some(4));
+    assert_eq!(report.load(), Some([0.12, 0.08, 0.05]));
+    assert_eq!(report.memory_available(), Some(12000000 * 1024));
+    // `/srv/data` é bind mount de `/dev/sdb1`; tmpfs e NFS não são disco do servidor.
+    let mounts: Vec<&str> = report.mounts().iter().map(|mount| mount.mount.as_str()).collect();
+    assert_eq!(mounts, vec!["/", "/var/lib/data"]);
+    assert_eq!(report.mounts()[0].used_percent(), 50);
+    assert_eq!(report.disk_total(), Some(400 * 1024 * 1024 * 1024));
+
+    // O fixture declara "Ubuntu", "32GB" e "400GB".
+    let config = crate::parser::ConfigYaml::new("config.yaml").unwrap().get_info_server("Servidor 1").unwrap().0;
+    assert_eq!(report.matches(&config, ConfigField::Os), Some(true));
+    assert!(report.mismatches(&config).is_empty());
+
+    let report = HealthReport { memory_total: Some(8 * 1024 * 1024 * 1024), ..report };
+    assert_eq!(report.mismatches(&config), vec![ConfigField::Memory]);
+}
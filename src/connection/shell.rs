@@ -0,0 +1,209 @@
+use std::{
+    io::{self, Read, Write},
+    time::Duration,
+};
+
+use ssh2::{Channel, Session};
+
+/// Tipo de terminal pedido ao servidor quando `TERM` não está definido.
+const DEFAULT_TERM: &str = "xterm-256color";
+/// Espera máxima por uma tecla antes de voltar a ler o canal.
+const INPUT_POLL: Duration = Duration::from_millis(10);
+
+/// Entrada do terminal local repassada ao shell remoto.
+#[derive(Debug,PartialEq, Eq,Clone)]
+pub enum ShellInput {
+    Bytes(Vec<u8>),
+    /// Novo tamanho da janela, em colunas e linhas.
+    Resize { cols: u16, rows: u16 },
+}
+
+/// Abre um shell interativo com PTY na sessão e liga o terminal local a ele até o shell terminar.
+/// `input` espera até o tempo indicado por uma tecla ou mudança de tamanho; tudo o que o servidor
+/// envia é escrito em `output`. Retorna o código de saída do shell.
+pub fn interactive_shell<I, W>(session: &Session, (cols, rows): (u16,u16), mut input: I, output: &mut W) -> io::Result<i32>
+where
+    I: FnMut(Duration) -> io::Result<Option<ShellInput>>,
+    W: Write,
+{
+    let term = std::env::var("TERM").ok().filter(|term| !term.is_empty()).unwrap_or_else(|| DEFAULT_TERM.to_string());
+
+    let mut channel = session.channel_session()?;
+    channel.request_pty(&term, None, Some((cols as u32, rows as u32, 0, 0)))?;
+    channel.shell()?;
+
+    session.set_blocking(false);
+    let result = pump(&mut channel, &mut input, output);
+    session.set_blocking(true);
+    result?;
+
+    let _ = channel.close();
+    let _ = channel.wait_close();
+    Ok(channel.exit_status().unwrap_or(0))
+}
+
+/// Operações do canal usadas por `pump`; separadas para que o repasse possa ser testado sem servidor.
+trait ShellChannel {
+    fn read_stream(&mut self, stream_id: i32, buffer: &mut [u8]) -> io::Result<usize>;
+    fn write_input(&mut self, bytes: &[u8]) -> io::Result<usize>;
+    fn resize(&mut self, cols: u16, rows: u16);
+    fn is_eof(&self) -> bool;
+}
+
+impl ShellChannel for Channel {
+    fn read_stream(&mut self, stream_id: i32, buffer: &mut [u8]) -> io::Result<usize> {
+        self.stream(stream_id).read(buffer)
+    }
+
+    fn write_input(&mut self, bytes: &[u8]) -> io::Result<usize> {
+        self.write(bytes)
+    }
+
+    fn resize(&mut self, cols: u16, rows: u16) {
+        let _ = self.request_pty_size(cols as u32, rows as u32, None, None);
+    }
+
+    fn is_eof(&self) -> bool {
+        self.eof()
+    }
+}
+
+/// Repassa a saída do canal e as teclas até o servidor fechar o shell.
+fn pump<C, I, W>(channel: &mut C, input: &mut I, output: &mut W) -> io::Result<()>
+where
+    C: ShellChannel,
+    I: FnMut(Duration) -> io::Result<Option<ShellInput>>,
+    W: Write,
+{
+    let mut buffer = [0u8; 16384];
+    let mut pending: Vec<u8> = vec![];
+
+    loop {
+        let mut received = false;
+
+        for stream_id in [0, 1] {
+            match channel.read_stream(stream_id, &mut buffer) {
+                Ok(0) => {},
+                Ok(size) => {
+                    output.write_all(&buffer[..size])?;
+                    received = true;
+                },
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {},
+                Err(e) => return Err(e)
+            }
+        }
+
+        if received {
+            output.flush()?;
+        } else if channel.is_eof() {
+            return Ok(());
+        }
+
+        // Com saída chegando sem parar, as teclas (inclusive Ctrl+C) são lidas sem esperar.
+        let wait = if received { Duration::ZERO } else { INPUT_POLL };
+        match input(wait)? {
+            Some(ShellInput::Bytes(bytes)) => pending.extend_from_slice(&bytes),
+            Some(ShellInput::Resize { cols, rows }) => channel.resize(cols, rows),
+            None => {}
+        }
+
+        if !pending.is_empty() {
+            match channel.write_input(&pending) {
+                Ok(size) => { pending.drain(..size); },
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {},
+                Err(e) => return Err(e)
+            }
+        }
+    }
+}
+
+#[test]
+fn test_pump_relays_input_and_output() {
+    /// Shell falso: devolve a entrada como saída (eco) e fecha depois de receber "exit\r".
+    #[derive(Default)]
+    struct EchoChannel {
+        stdout: Vec<u8>,
+        written: Vec<u8>,
+        sizes: Vec<(u16,u16)>,
+        closed: bool,
+    }
+
+    impl ShellChannel for EchoChannel {
+        fn read_stream(&mut self, stream_id: i32, buffer: &mut [u8]) -> io::Result<usize> {
+            if stream_id != 0 || self.stdout.is_empty() {
+                return Err(io::ErrorKind::WouldBlock.into());
+            }
+            let size = self.stdout.len().min(buffer.len());
+            buffer[..size].copy_from_slice(&self.stdout[..size]);
+            self.stdout.drain(..size);
+            Ok(size)
+        }
+
+        fn write_input(&mut self, bytes: &[u8]) -> io::Result<usize> {
+            // Aceita um byte por vez, como um canal com a janela cheia.
+            self.written.push(bytes[0]);
+            self.stdout.push(bytes[0]);
+            self.closed = self.written.ends_with(b"exit\r");
+            Ok(1)
+        }
+
+        fn resize(&mut self, cols: u16, rows: u16) {
+            self.sizes.push((cols, rows));
+        }
+
+        fn is_eof(&self) -> bool {
+            self.closed && self.stdout.is_empty()
+        }
+    }
+
+    let mut inputs = vec![
+        ShellInput::Bytes(b"ls\r".to_vec()),
+        ShellInput::Resize { cols: 120, rows: 40 },
+        ShellInput::Bytes(b"exit\r".to_vec()),
+    ].into_iter();
+    let mut channel = EchoChannel::default();
+    let mut output: Vec<u8> = vec![];
+
+    pump(&mut channel, &mut |_| Ok(inputs.next()), &mut output).unwrap();
+    assert_eq!(channel.written, b"ls\rexit\r");
+    assert_eq!(output, b"ls\rexit\r");
+    assert_eq!(channel.sizes, vec![(120, 40)]);
+
+    /// Programa que imprime sem parar (como o `yes`) até receber Ctrl+C.
+    #[derive(Default)]
+    struct FloodChannel {
+        reads: usize,
+        written: Vec<u8>,
+    }
+
+    impl ShellChannel for FloodChannel {
+        fn read_stream(&mut self, stream_id: i32, buffer: &mut [u8]) -> io::Result<usize> {
+            if stream_id != 0 || self.written.contains(&0x03) {
+                return Err(io::ErrorKind::WouldBlock.into());
+            }
+            self.reads += 1;
+            if self.reads > 10_000 {
+                return Err(io::Error::other("a entrada não chegou ao canal"));
+            }
+            buffer[..2].copy_from_slice(b"y\n");
+            Ok(2)
+        }
+
+        fn write_input(&mut self, bytes: &[u8]) -> io::Result<usize> {
+            self.written.extend_from_slice(bytes);
+            Ok(bytes.len())
+        }
+
+        fn resize(&mut self, _cols: u16, _rows: u16) {}
+
+        fn is_eof(&self) -> bool {
+            self.written.contains(&0x03)
+        }
+    }
+
+    let mut inputs = vec![ShellInput::Bytes(vec![0x03])].into_iter();
+    let mut channel = FloodChannel::default();
+
+    pump(&mut channel, &mut |_| Ok(inputs.next()), &mut io::sink()).unwrap();
+    assert_eq!(channel.written, vec![0x03]);
+}
@@ -0,0 +1,271 @@
+use std::{
+    fs::{self, File},
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use openssl::sha::Sha256;
+use ssh2::{FileStat, OpenFlags, OpenType, RenameFlags, Session, Sftp};
+
+use crate::{parser::{shell_quote, FileTransfer, TransferDirection}, secrets::expand_home};
+
+use super::CancelToken;
+
+const CHUNK_SIZE: usize = 32 * 1024;
+/// Permissão de arquivos enviados sem `mode`, antes da umask do servidor.
+const DEFAULT_MODE: i32 = 0o644;
+/// Espera máxima de cada operação SFTP. Sem ela um servidor que para de responder prende a
+/// thread em uma leitura e o cancelamento nunca é conferido.
+const SFTP_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Como a transferência terminou.
+pub(crate) enum TransferEnd {
+    Done(TransferSummary),
+    Cancelled,
+    TimedOut,
+}
+
+pub(crate) struct TransferSummary {
+    pub bytes: u64,
+    /// SHA-256 conferido nos dois lados; `None` quando o servidor não tem `sha256sum` nem `shasum`.
+    pub checksum: Option<String>,
+}
+
+impl TransferSummary {
+    /// Resumo exibido ao fim da transferência.
+    pub fn message(&self) -> String {
+        format!(
+            "{} bytes transferidos{}",
+            self.bytes,
+            self.checksum.as_deref().map(|checksum| format!(", sha256 {} conferido", checksum)).unwrap_or_default()
+            )
+    }
+}
+
+/// Envia ou baixa o arquivo pelo SFTP da sessão e confere o SHA-256 dos dois lados.
+/// `on_progress` recebe os bytes já copiados e o tamanho total, quando conhecido.
+pub(crate) fn run_transfer<F>(session: &Session, direction: TransferDirection, transfer: &FileTransfer, cancel: &CancelToken, deadline: Option<Instant>, on_progress: F) -> io::Result<TransferEnd>
+where
+    F: FnMut(u64, Option<u64>),
+{
+    // O tempo limite vale para a sessão inteira e volta a 0 (sem limite) no fim, como nos outros usos.
+    let remaining = deadline.map(|deadline| deadline.saturating_duration_since(Instant::now()));
+    let timeout = remaining.map_or(SFTP_TIMEOUT, |remaining| remaining.min(SFTP_TIMEOUT));
+    session.set_timeout(timeout.as_millis().max(1) as u32);
+
+    let result = match direction {
+        TransferDirection::Upload => upload(session, transfer, cancel, deadline, on_progress),
+        TransferDirection::Download => download(session, transfer, cancel, deadline, on_progress),
+    };
+
+    session.set_timeout(0);
+    match result {
+        Err(e) if e.kind() == io::ErrorKind::TimedOut && deadline.is_some_and(|deadline| Instant::now() >= deadline) => Ok(TransferEnd::TimedOut),
+        result => result
+    }
+}
+
+/// Envia para `<destino>.part` e só renomeia sobre o destino depois de conferir o checksum,
+/// para que uma falha no meio não deixe o arquivo do servidor truncado.
+fn upload<F>(session: &Session, transfer: &FileTransfer, cancel: &CancelToken, deadline: Option<Instant>, on_progress: F) -> io::Result<TransferEnd>
+where
+    F: FnMut(u64, Option<u64>),
+{
+    let mode = transfer.mode().map_err(|message| io::Error::new(io::ErrorKind::InvalidInput, message))?;
+    let local_path = expand_home(transfer.from());
+    let remote_path = Path::new(transfer.to());
+    let partial = format!("{}.part", transfer.to());
+
+    let mut local = File::open(&local_path).map_err(|e| with_path(e, &local_path))?;
+    let total = local.metadata()?.len();
+
+    let sftp = session.sftp()?;
+    let mut remote = sftp.open_mode(Path::new(&partial), OpenFlags::WRITE | OpenFlags::CREATE | OpenFlags::TRUNCATE, mode.unwrap_or(DEFAULT_MODE), OpenType::File)
+                         .map_err(|e| with_path(e.into(), Path::new(&partial)))?;
+
+    let outcome = copy(&mut local, &mut remote, Some(total), cancel, deadline, on_progress);
+    drop(remote);
+
+    let finished = outcome.and_then(|outcome| match outcome {
+        Copy::Done(bytes, digest) => finish_upload(session, &sftp, &partial, remote_path, mode, digest).map(|checksum| {
+            TransferEnd::Done(TransferSummary { bytes, checksum })
+        }),
+        Copy::Cancelled => Ok(TransferEnd::Cancelled),
+        Copy::TimedOut => Ok(TransferEnd::TimedOut),
+    });
+
+    if !matches!(finished, Ok(TransferEnd::Done(_))) {
+        let _ = sftp.unlink(Path::new(&partial));
+    }
+    finished
+}
+
+/// Ajusta a permissão do `.part`, confere o checksum e o coloca no lugar do destino.
+fn finish_upload(session: &Session, sftp: &Sftp, partial: &str, remote_path: &Path, mode: Option<i32>, digest: String) -> io::Result<Option<String>> {
+    // O `mode` de `open_mode` só vale na criação e passa pela umask.
+    if let Some(mode) = mode {
+        sftp.setstat(Path::new(partial), FileStat { size: None, uid: None, gid: None, perm: Some(mode as u32), atime: None, mtime: None })
+            .map_err(|e| with_path(e.into(), Path::new(partial)))?;
+    }
+
+    let checksum = verify(session, partial, digest)?;
+
+    // Servidores com SFTP versão 3 ignoram as flags e recusam renomear sobre um arquivo existente;
+    // nesse caso o `mv` faz a troca.
+    if sftp.rename(Path::new(partial), remote_path, Some(RenameFlags::OVERWRITE | RenameFlags::ATOMIC | RenameFlags::NATIVE)).is_err() {
+        let mut channel = session.channel_session()?;
+        channel.exec(&format!("mv -f -- {} {}", shell_quote(partial), shell_quote(&remote_path.to_string_lossy())))?;
+        channel.wait_close()?;
+
+        if channel.exit_status()? != 0 {
+            return Err(io::Error::other(format!("{}: não foi possível substituir pelo arquivo enviado", remote_path.display())));
+        }
+    }
+
+    Ok(checksum)
+}
+
+/// Baixa para `<destino>.part` e só renomeia depois de conferir o checksum.
+fn download<F>(session: &Session, transfer: &FileTransfer, cancel: &CancelToken, deadline: Option<Instant>, on_progress: F) -> io::Result<TransferEnd>
+where
+    F: FnMut(u64, Option<u64>),
+{
+    let remote_path = Path::new(transfer.from());
+    let local_path = expand_home(transfer.to());
+    let partial = PathBuf::from(format!("{}.part", local_path.display()));
+
+    let sftp = session.sftp()?;
+    let mut remote = sftp.open(remote_path).map_err(|e| with_path(e.into(), remote_path))?;
+    let total = remote.stat().ok().and_then(|stat| stat.size);
+
+    if let Some(parent) = local_path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+        fs::create_dir_all(parent).map_err(|e| with_path(e, parent))?;
+    }
+    let mut local = File::create(&partial).map_err(|e| with_path(e, &partial))?;
+
+    let outcome = copy(&mut remote, &mut local, total, cancel, deadline, on_progress)
+                      .and_then(|outcome| local.sync_all().map(|_| outcome));
+
+    let (bytes, digest) = match outcome {
+        Ok(Copy::Done(bytes, digest)) => (bytes, digest),
+        other => {
+            let _ = fs::remove_file(&partial);
+            return match other? {
+                Copy::Cancelled => Ok(TransferEnd::Cancelled),
+                _ => Ok(TransferEnd::TimedOut),
+            };
+        }
+    };
+
+    let checksum = match verify(session, transfer.from(), digest) {
+        Ok(checksum) => checksum,
+        Err(e) => {
+            let _ = fs::remove_file(&partial);
+            return Err(e);
+        }
+    };
+
+    fs::rename(&partial, &local_path).map_err(|e| with_path(e, &local_path))?;
+    Ok(TransferEnd::Done(TransferSummary { bytes, checksum }))
+}
+
+enum Copy {
+    Done(u64, String),
+    Cancelled,
+    TimedOut,
+}
+
+/// Copia em blocos calculando o SHA-256 do que passou; cancelamento e tempo limite são
+/// conferidos entre os blocos. Um bloco parado é interrompido pelo `SFTP_TIMEOUT` da sessão.
+fn copy<R: Read, W: Write, F: FnMut(u64, Option<u64>)>(reader: &mut R, writer: &mut W, total: Option<u64>, cancel: &CancelToken, deadline: Option<Instant>, mut on_progress: F) -> io::Result<Copy> {
+    let mut buffer = vec![0u8; CHUNK_SIZE];
+    let mut hasher = Sha256::new();
+    let mut copied: u64 = 0;
+
+    on_progress(0, total);
+
+    loop {
+        if cancel.is_cancelled() {
+            return Ok(Copy::Cancelled);
+        }
+
+        if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            return Ok(Copy::TimedOut);
+        }
+
+        let size = reader.read(&mut buffer)?;
+        if size == 0 {
+            break;
+        }
+
+        writer.write_all(&buffer[..size])?;
+        hasher.update(&buffer[..size]);
+        copied += size as u64;
+        on_progress(copied, total);
+    }
+
+    writer.flush()?;
+    Ok(Copy::Done(copied, hex(&hasher.finish())))
+}
+
+/// Compara o SHA-256 local com o calculado no servidor. Sem ferramenta de checksum no servidor
+/// a transferência é aceita sem verificação.
+fn verify(session: &Session, remote_path: &str, local_digest: String) -> io::Result<Option<String>> {
+    match remote_sha256(session, remote_path)? {
+        None => Ok(None),
+        Some(remote) if remote == local_digest => Ok(Some(remote)),
+        Some(remote) => Err(io::Error::new(io::ErrorKind::InvalidData, format!(
+            "checksum diferente após a transferência: local {}, servidor {}", local_digest, remote
+            )))
+    }
+}
+
+fn remote_sha256(session: &Session, path: &str) -> io::Result<Option<String>> {
+    let path = shell_quote(path);
+    let mut channel = session.channel_session()?;
+    channel.exec(&format!("sha256sum -- {path} 2>/dev/null || shasum -a 256 -- {path} 2>/dev/null"))?;
+
+    let mut output = String::new();
+    channel.read_to_string(&mut output)?;
+    channel.wait_close()?;
+
+    if channel.exit_status()? != 0 {
+        return Ok(None);
+    }
+
+    Ok(output.split_whitespace()
+             .next()
+             .filter(|digest| digest.len() == 64 && digest.chars().all(|c| c.is_ascii_hexdigit()))
+             .map(str::to_ascii_lowercase))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn with_path(error: io::Error, path: &Path) -> io::Error {
+    io::Error::new(error.kind(), format!("{}: {}", path.display(), error))
+}
+
+#[test]
+fn test_copy_hashes_and_cancels() {
+    let mut reader: &[u8] = b"abc";
+    let mut writer: Vec<u8> = vec![];
+    let mut progress = vec![];
+
+    match copy(&mut reader, &mut writer, Some(3), &CancelToken::default(), None, |copied, _| progress.push(copied)).unwrap() {
+        Copy::Done(bytes, digest) => {
+            assert_eq!(bytes, 3);
+            assert_eq!(digest, "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+        },
+        _ => panic!("Esperado Copy::Done")
+    }
+    assert_eq!(writer, b"abc");
+    assert_eq!(progress, vec![0, 3]);
+
+    let cancel = CancelToken::default();
+    cancel.cancel();
+    let mut reader: &[u8] = b"abc";
+    assert!(matches!(copy(&mut reader, &mut writer, None, &cancel, None, |_, _| {}), Ok(Copy::Cancelled)));
+}
@@ -0,0 +1,146 @@
+use std::{
+    io::{self, Read, Write},
+    net::{Ipv4Addr, Shutdown, TcpListener, TcpStream},
+    thread,
+    time::Duration,
+};
+
+use ssh2::{Channel, Session};
+
+/// Pausa depois de uma volta sem dados; dobra enquanto o túnel fica parado, até `IDLE_SLEEP_MAX`.
+/// O máximo é baixo porque o túnel carrega a sessão do servidor de destino, inclusive o shell.
+const IDLE_SLEEP: Duration = Duration::from_millis(2);
+const IDLE_SLEEP_MAX: Duration = Duration::from_millis(20);
+
+/// Abre um canal `direct-tcpip` no bastião até `host:port` e o expõe como um `TcpStream` local,
+/// que pode ser entregue a outra `Session`. Uma thread repassa os dados até um dos lados fechar
+/// e mantém a sessão do bastião viva enquanto isso.
+pub(crate) fn open_tunnel(bastion: Session, host: &str, port: u16) -> io::Result<TcpStream> {
+    let channel = bastion.channel_direct_tcpip(host, port, None)?;
+
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))?;
+    let local = TcpStream::connect(listener.local_addr()?)?;
+    let (remote, peer) = listener.accept()?;
+
+    // Outro processo da máquina poderia se conectar à porta antes de nós.
+    if peer != local.local_addr()? {
+        return Err(io::Error::new(io::ErrorKind::PermissionDenied, "Conexão inesperada na porta local do túnel"));
+    }
+
+    thread::spawn(move || pump(bastion, channel, remote));
+    Ok(local)
+}
+
+/// Copia os dados entre o canal e o socket local nos dois sentidos, sem bloquear nenhum dos lados.
+fn pump(bastion: Session, channel: Channel, local: TcpStream) {
+    if local.set_nonblocking(true).is_err() {
+        return;
+    }
+    bastion.set_blocking(false);
+
+    let mut pipe = Pipe::new(channel, local);
+    let mut buffer = [0u8; 16384];
+    let mut idle_sleep = IDLE_SLEEP;
+
+    while let Some(active) = pipe.step(&mut buffer) {
+        if active {
+            idle_sleep = IDLE_SLEEP;
+        } else {
+            let _ = bastion.keepalive_send();
+            thread::sleep(idle_sleep);
+            idle_sleep = (idle_sleep * 2).min(IDLE_SLEEP_MAX);
+        }
+    }
+}
+
+/// Ligação entre um canal SSH e um socket TCP, os dois em modo não bloqueante. Cada `step` move
+/// o que estiver disponível sem esperar; quem chama decide quando dormir.
+pub(crate) struct Pipe {
+    channel: Channel,
+    local: TcpStream,
+    to_channel: Vec<u8>,
+    to_local: Vec<u8>,
+    local_closed: bool,
+    eof_sent: bool,
+}
+
+impl Pipe {
+    /// A sessão do canal e o `local` já devem estar em modo não bloqueante.
+    pub(crate) fn new(channel: Channel, local: TcpStream) -> Self {
+        Pipe {
+            channel,
+            local,
+            to_channel: vec![],
+            to_local: vec![],
+            local_closed: false,
+            eof_sent: false,
+        }
+    }
+
+    /// Move os dados disponíveis. Retorna se algo passou, ou `None` quando a ligação terminou
+    /// (um dos lados fechou ou deu erro); nesse caso os dois lados já foram fechados.
+    pub(crate) fn step(&mut self, buffer: &mut [u8]) -> Option<bool> {
+        match self.transfer(buffer) {
+            Ok(Some(active)) => Some(active),
+            _ => {
+                let _ = self.local.shutdown(Shutdown::Both);
+                let _ = self.channel.close();
+                None
+            }
+        }
+    }
+
+    fn transfer(&mut self, buffer: &mut [u8]) -> io::Result<Option<bool>> {
+        let mut active = false;
+
+        if !self.local_closed && self.to_channel.is_empty() {
+            match self.local.read(buffer) {
+                Ok(0) => self.local_closed = true,
+                Ok(size) => self.to_channel.extend_from_slice(&buffer[..size]),
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {},
+                Err(e) => return Err(e)
+            }
+            active = self.local_closed || !self.to_channel.is_empty();
+        }
+
+        if !self.to_channel.is_empty() {
+            match self.channel.write(&self.to_channel) {
+                Ok(size) => {
+                    self.to_channel.drain(..size);
+                    active |= size > 0;
+                },
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {},
+                Err(e) => return Err(e)
+            }
+        } else if self.local_closed && !self.eof_sent {
+            self.eof_sent = self.channel.send_eof().is_ok();
+        }
+
+        if self.to_local.is_empty() {
+            match self.channel.read(buffer) {
+                Ok(size) => {
+                    self.to_local.extend_from_slice(&buffer[..size]);
+                    active |= size > 0;
+                },
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {},
+                Err(e) => return Err(e)
+            }
+        }
+
+        if !self.to_local.is_empty() {
+            match self.local.write(&self.to_local) {
+                Ok(size) => {
+                    self.to_local.drain(..size);
+                    active |= size > 0;
+                },
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {},
+                Err(e) => return Err(e)
+            }
+        }
+
+        if self.to_local.is_empty() && self.channel.eof() {
+            return Ok(None);
+        }
+        Ok(Some(active))
+    }
+}
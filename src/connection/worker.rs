@@ -0,0 +1,323 @@
+use std::{
+    fmt,
+    sync::mpsc::{self, Receiver, RecvTimeoutError, Sender, TryRecvError},
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use ssh2::Session;
+
+use crate::parser::{FileTransfer, ServerCommands, TransferDirection};
+
+use super::{
+    browser, probe, run_parallel_cancelable, transfer::{self, TransferEnd}, CancelToken, CommandResult, ConnectionError, FilePreview, HealthReport, OutputStream,
+    ParallelEvent, RemoteEntry, ServerRun, ServerStatus, ServerTarget, SessionPool, StepEvent, StepsReport, SSH,
+};
+
+/// De quanto em quanto tempo a thread ociosa envia os keepalives das sessões guardadas.
+const KEEPALIVE_TICK: Duration = Duration::from_secs(1);
+
+/// Trabalho entregue à thread de conexões.
+#[derive(Debug)]
+pub enum Job {
+    /// Executa o comando etapa por etapa em um servidor, reaproveitando a sessão guardada.
+    Run { server: String, ssh: Box<SSH>, command: ServerCommands },
+    /// Executa em vários servidores ao mesmo tempo, como `run_parallel`.
+    RunParallel { targets: Vec<ServerTarget>, limit: usize },
+    /// Lista um diretório do servidor pelo SFTP.
+    Browse { server: String, ssh: Box<SSH>, path: String },
+    /// Lê o começo de um arquivo remoto ou, com `tail`, o final.
+    Preview { server: String, ssh: Box<SSH>, path: String, tail: bool },
+    /// Lê o que foi acrescentado ao arquivo acompanhado depois de `offset`.
+    Follow { server: String, ssh: Box<SSH>, path: String, offset: u64 },
+    /// Envia ou baixa um arquivo escolhido no navegador; o progresso chega como `Progress` da etapa 0.
+    Transfer { server: String, ssh: Box<SSH>, direction: TransferDirection, transfer: FileTransfer },
+    /// Coleta sistema, carga, memória e discos do servidor.
+    Probe { server: String, ssh: Box<SSH> },
+    /// Conecta uma sessão nova para o shell interativo, fora do pool: o shell a deixa em modo não bloqueante.
+    OpenShell { server: String, ssh: Box<SSH> },
+}
+
+/// Sessão aberta por `Job::OpenShell`, entregue à interface para o shell interativo.
+pub struct ShellSession(Session);
+
+impl ShellSession {
+    pub fn into_session(self) -> Session {
+        self.0
+    }
+}
+
+impl fmt::Debug for ShellSession {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("ShellSession")
+    }
+}
+
+/// Progresso de um `Job`, na ordem em que acontece.
+#[derive(Debug)]
+pub enum WorkerEvent {
+    Connected { server: String, reused: bool },
+    StepStarted { index: usize, command: String },
+    Output { index: usize, stream: OutputStream, text: String },
+    Progress { index: usize, transferred: u64, total: Option<u64> },
+    StepFinished { index: usize, result: CommandResult },
+    /// Fim de um `Job::Run`; o erro pode ser da conexão ou da execução.
+    Finished { server: String, result: Result<StepsReport,ConnectionError> },
+    ServerStarted { index: usize },
+    ServerFinished { index: usize, server: String, status: ServerStatus },
+    /// Fim de um `Job::RunParallel`, com o resultado na ordem dos alvos.
+    ParallelFinished { runs: Vec<ServerRun> },
+    /// Fim de um `Job::Browse`, com o caminho absoluto do diretório e seus itens.
+    Listing { server: String, result: Result<(String,Vec<RemoteEntry>),ConnectionError> },
+    /// Fim de um `Job::Preview` ou `Job::Follow`.
+    Preview { server: String, result: Result<FilePreview,ConnectionError> },
+    /// Fim de um `Job::Transfer`, com o resumo (ou o motivo da interrupção) para exibir.
+    Transferred { server: String, direction: TransferDirection, result: Result<String,ConnectionError> },
+    /// Fim de um `Job::Probe`.
+    Probed { server: String, result: Result<HealthReport,ConnectionError> },
+    /// Fim de um `Job::OpenShell`.
+    ShellOpened { server: String, result: Result<ShellSession,ConnectionError> },
+    /// A thread de conexões terminou (pânico) com um `Job` em andamento; o evento final dele
+    /// nunca vai chegar.
+    Stopped,
+}
+
+impl WorkerEvent {
+    /// Indica se o evento encerra o `Job` em andamento.
+    pub fn is_final(&self) -> bool {
+        matches!(
+            self,
+            WorkerEvent::Finished { .. }
+                | WorkerEvent::ParallelFinished { .. }
+                | WorkerEvent::Listing { .. }
+                | WorkerEvent::Preview { .. }
+                | WorkerEvent::Transferred { .. }
+                | WorkerEvent::Probed { .. }
+                | WorkerEvent::ShellOpened { .. }
+                | WorkerEvent::Stopped
+        )
+    }
+}
+
+/// Thread que faz todo o trabalho bloqueante do ssh2 (conexão, autenticação, execução) fora do
+/// loop da interface e devolve o progresso por um canal. Executa um `Job` por vez e mantém as
+/// sessões em um `SessionPool`.
+pub struct Worker {
+    jobs: Option<Sender<(Job,CancelToken)>>,
+    events: Receiver<WorkerEvent>,
+    handle: Option<JoinHandle<()>>,
+    busy: bool,
+    cancel: CancelToken,
+}
+
+impl Worker {
+    pub fn spawn() -> Worker {
+        let (jobs, job_receiver) = mpsc::channel::<(Job,CancelToken)>();
+        let (event_sender, events) = mpsc::channel();
+
+        let handle = thread::spawn(move || {
+            let mut pool = SessionPool::default();
+
+            loop {
+                match job_receiver.recv_timeout(KEEPALIVE_TICK) {
+                    Ok((job, cancel)) => run_job(job, &cancel, &mut pool, &event_sender),
+                    Err(RecvTimeoutError::Timeout) => pool.keepalive(),
+                    Err(RecvTimeoutError::Disconnected) => break
+                }
+            }
+
+            pool.close_all();
+        });
+
+        Worker {
+            jobs: Some(jobs),
+            events,
+            handle: Some(handle),
+            busy: false,
+            cancel: CancelToken::default(),
+        }
+    }
+
+    /// Há um `Job` em andamento; outro só é aceito depois do evento final.
+    pub fn is_busy(&self) -> bool {
+        self.busy
+    }
+
+    /// Entrega o trabalho à thread. Retorna `false` se já houver um em andamento.
+    pub fn submit(&mut self, job: Job) -> bool {
+        if self.busy {
+            return false;
+        }
+
+        self.cancel = CancelToken::default();
+        self.busy = self.jobs.as_ref().is_some_and(|jobs| jobs.send((job, self.cancel.clone())).is_ok());
+        self.busy
+    }
+
+    /// Pede o cancelamento do `Job` em andamento; o evento final chega quando a thread terminar
+    /// de fechar o canal. Retorna `false` se não houver nada em andamento.
+    pub fn cancel(&self) -> bool {
+        if self.busy {
+            self.cancel.cancel();
+        }
+        self.busy
+    }
+
+    /// Próximo evento já disponível, sem bloquear.
+    pub fn try_recv(&mut self) -> Option<WorkerEvent> {
+        let disconnected = match self.events.try_recv() {
+            Ok(event) => return Some(self.received(event)),
+            Err(error) => error == TryRecvError::Disconnected
+        };
+        self.stopped(disconnected)
+    }
+
+    /// Espera o próximo evento por até `timeout`.
+    pub fn recv_timeout(&mut self, timeout: Duration) -> Option<WorkerEvent> {
+        let disconnected = match self.events.recv_timeout(timeout) {
+            Ok(event) => return Some(self.received(event)),
+            Err(error) => error == RecvTimeoutError::Disconnected
+        };
+        self.stopped(disconnected)
+    }
+
+    fn received(&mut self, event: WorkerEvent) -> WorkerEvent {
+        if event.is_final() {
+            self.busy = false;
+        }
+        event
+    }
+
+    /// Com o canal desconectado a thread morreu; o `Job` em andamento termina com `Stopped`.
+    fn stopped(&mut self, disconnected: bool) -> Option<WorkerEvent> {
+        if !(disconnected && self.busy) {
+            return None;
+        }
+
+        self.busy = false;
+        Some(WorkerEvent::Stopped)
+    }
+
+    /// Encerra a thread fechando as sessões. Com um `Job` em andamento não espera por ele.
+    pub fn shutdown(mut self) {
+        self.jobs = None;
+
+        if let Some(handle) = self.handle.take().filter(|_| !self.busy) {
+            let _ = handle.join();
+        }
+    }
+}
+
+fn run_job(job: Job, cancel: &CancelToken, pool: &mut SessionPool, events: &Sender<WorkerEvent>) {
+    match job {
+        Job::Run { server, ssh, command } => {
+            let result = run_steps(&server, &ssh, &command, cancel, pool, events);
+            if result.is_err() {
+                // A sessão pode ter caído no meio; a próxima execução reconecta.
+                pool.discard(&server);
+            }
+            let _ = events.send(WorkerEvent::Finished { server, result });
+        },
+        Job::RunParallel { targets, limit } => {
+            let runs = run_parallel_cancelable(targets, limit, cancel, |event| {
+                let _ = events.send(match event {
+                    ParallelEvent::Started { index, .. } => WorkerEvent::ServerStarted { index },
+                    ParallelEvent::Finished { index, run } => WorkerEvent::ServerFinished {
+                        index,
+                        server: run.server().to_string(),
+                        status: run.status().clone(),
+                    },
+                });
+            });
+            let _ = events.send(WorkerEvent::ParallelFinished { runs });
+        },
+        Job::Browse { server, ssh, path } => {
+            let result = pool.session(&server, &ssh)
+                             .and_then(|(session, _)| browser::list_dir(&session, &path).map_err(ConnectionError::Channel));
+            let _ = events.send(WorkerEvent::Listing { server, result });
+        },
+        Job::Preview { server, ssh, path, tail } => {
+            let result = pool.session(&server, &ssh)
+                             .and_then(|(session, _)| browser::read_preview(&session, &path, tail).map_err(ConnectionError::Channel));
+            let _ = events.send(WorkerEvent::Preview { server, result });
+        },
+        Job::Follow { server, ssh, path, offset } => {
+            let result = pool.session(&server, &ssh)
+                             .and_then(|(session, _)| browser::read_appended(&session, &path, offset).map_err(ConnectionError::Channel));
+            let _ = events.send(WorkerEvent::Preview { server, result });
+        },
+        Job::Transfer { server, ssh, direction, transfer } => {
+            let result = transfer_file(&server, &ssh, direction, &transfer, cancel, pool, events);
+            let _ = events.send(WorkerEvent::Transferred { server, direction, result });
+        },
+        Job::Probe { server, ssh } => {
+            let result = pool.session(&server, &ssh)
+                             .and_then(|(session, _)| probe::probe(&session).map_err(ConnectionError::Channel));
+            let _ = events.send(WorkerEvent::Probed { server, result });
+        },
+        Job::OpenShell { server, ssh } => {
+            let result = ssh.open_session().map(ShellSession);
+            let _ = events.send(WorkerEvent::ShellOpened { server, result });
+        }
+    }
+}
+
+fn transfer_file(server: &str, ssh: &SSH, direction: TransferDirection, file: &FileTransfer, cancel: &CancelToken, pool: &mut SessionPool, events: &Sender<WorkerEvent>) -> Result<String,ConnectionError> {
+    let (session, _) = pool.session(server, ssh)?;
+    let deadline = ssh.command_timeout.map(|timeout| Instant::now() + timeout);
+
+    let outcome = transfer::run_transfer(&session, direction, file, cancel, deadline, |transferred, total| {
+        let _ = events.send(WorkerEvent::Progress { index: 0, transferred, total });
+    });
+
+    match outcome.map_err(ConnectionError::Channel)? {
+        TransferEnd::Done(summary) => Ok(summary.message()),
+        TransferEnd::Cancelled => Ok(String::from("Transferência cancelada pelo usuário")),
+        TransferEnd::TimedOut => Err(ConnectionError::Timeout { operation: format!("transferência de {}", file.from()) }),
+    }
+}
+
+fn run_steps(server: &str, ssh: &SSH, command: &ServerCommands, cancel: &CancelToken, pool: &mut SessionPool, events: &Sender<WorkerEvent>) -> Result<StepsReport,ConnectionError> {
+    let (session, reused) = pool.session(server, ssh)?;
+    let _ = events.send(WorkerEvent::Connected { server: server.to_string(), reused });
+
+    ssh.execute_steps_cancelable(command, session, cancel, |event| {
+        let _ = events.send(match event {
+            StepEvent::Started { index, command } => WorkerEvent::StepStarted { index, command: command.to_string() },
+            StepEvent::Output { index, stream, text } => WorkerEvent::Output { index, stream, text: text.to_string() },
+            StepEvent::Progress { index, transferred, total } => WorkerEvent::Progress { index, transferred, total },
+            StepEvent::Finished { index, result } => WorkerEvent::StepFinished { index, result: result.clone() },
+        });
+    })
+}
+
+#[test]
+fn test_worker_reports_connection_failure() {
+    let config = crate::parser::ConfigYaml::new("config.yaml").unwrap();
+    let (_, mut connect, commands) = config.get_info_server("Servidor 1").unwrap();
+    *connect.ip_address_mut() = format!("127.0.0.1:{}", super::closed_port());
+
+    let mut worker = Worker::spawn();
+    assert!(worker.submit(Job::Run { server: String::from("Servidor 1"), ssh: Box::new(SSH::new(&connect)), command: commands[0].clone() }));
+    assert!(worker.is_busy());
+    assert!(!worker.submit(Job::RunParallel { targets: vec![], limit: 1 }));
+
+    match worker.recv_timeout(Duration::from_secs(10)) {
+        Some(WorkerEvent::Finished { server, result: Err(ConnectionError::Tcp { .. }) }) => assert_eq!(server, "Servidor 1"),
+        other => panic!("Esperado Finished com erro de conexão, recebido {:?}",other)
+    }
+    assert!(!worker.is_busy());
+    worker.shutdown();
+}
+
+#[test]
+fn test_worker_reports_dead_thread() {
+    let (event_sender, events) = mpsc::channel::<WorkerEvent>();
+    drop(event_sender);
+
+    let mut worker = Worker { jobs: None, events, handle: None, busy: true, cancel: CancelToken::default() };
+    assert!(matches!(worker.try_recv(), Some(WorkerEvent::Stopped)));
+    assert!(!worker.is_busy());
+    assert!(worker.try_recv().is_none());
+    assert!(worker.recv_timeout(Duration::from_millis(10)).is_none());
+}
@@ -1,3 +1,5 @@
-mod parser;
-mod connection;
-mod view;
\ No newline at end of file
+pub mod parser;
+pub mod connection;
+pub mod view;
+pub mod cli;
+pub mod secrets;
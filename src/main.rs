@@ -1,133 +1,575 @@
-mod parser;
-mod connection;
-use core::panic;
-use std::{io, sync::{Arc, Mutex}};
-use connection::SSH;
-use parser::{ConfigYaml, ConnectionType, ServerCommands, ServerConnect};
-use ssh2::Session;
+use std::{collections::HashMap, io, time::Duration};
+use server_automation::{
+    cli::{self, Args, Command, USAGE},
+    connection::{build_targets, interactive_shell, ConnectionError, ForwardEvent, Forwarder, HealthReport, HostKey, Job, OutputStream, ServerStatus, ShellInput, ShellSession, Worker, WorkerEvent, PREVIEW_BYTES, SSH},
+    secrets::{PromptAnswers, SecretString},
+    parser::{contains_vault, has_errors, resolve_config_paths, validate_files, ConfigYaml, FileTransfer, PortForward, ServerCommands, ServerConfig, ServerConnect, TransferDirection},
+    view::{health_summary, key_bytes, sidebar_rows, suspend_tui, BrowserRequest, FileBrowser, InputForm, OutputPane, RenderComponent, RenderizeComponents, SidebarRow, StepStatus, TunnelPanel, TunnelState}
+};
 use tui::{
-    backend::CrosstermBackend,
-    layout::{Constraint, Direction,Layout},
+    backend::{Backend, CrosstermBackend},
+    layout::{Constraint, Direction,Layout, Rect},
     style::{Color, Modifier, Style},
-    terminal,
     text::{Span,Spans},
-    widgets::{Block, Borders, List, ListItem, ListState, Paragraph},
+    widgets::{Block, Borders, Clear, List, ListItem, ListState, Paragraph},
+    Frame,
     Terminal
 
 };
 use crossterm::{
     event::{self,DisableMouseCapture,EnableMouseCapture,Event,KeyCode}, execute, terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen}
 };
-use tokio::time::{sleep,Duration};
 
-async fn update_info_paragraph(
-    output_messages: Arc<Mutex<String>>,
-     area: tui::layout::Rect
-){
-    let mut terminal = Terminal::new(CrosstermBackend::new(std::io::stdout())).unwrap();
-    let mut out = output_messages.lock().unwrap();
+type LayoutAreas = (Vec<Rect>,Vec<Rect>,Vec<Rect>);
+
+/// Estado da TUI compartilhado entre o loop de eventos e `draw_ui`.
+struct App {
+    sidebar_rows: Vec<SidebarRow>,
+    collapsed_groups: Vec<String>,
+    selected_index: usize,
+    mainblock_selected_index: Option<usize>,
+    focused_block: &'static str,
+    input_info: String,
+    commands_server: Vec<ServerCommands>,
+    /// Nome do servidor selecionado, usado como chave das sessões reaproveitadas.
+    server_name: String,
+    server_connect: ServerConnect,
+    /// Valores declarados em `config` do servidor selecionado.
+    server_config: ServerConfig,
+    input_form: Option<InputForm>,
+    pending_command: Option<ServerCommands>,
+    output_pane: OutputPane,
+    steps: Vec<(String,StepStatus)>,
+    steps_title: &'static str,
+    /// Servidores marcados com Espaço; quando houver algum, o comando roda em todos eles.
+    marked_servers: Vec<String>,
+    /// Comando cuja conexão falhou; `r` tenta executá-lo de novo no mesmo servidor.
+    retry_command: Option<ServerCommands>,
+    /// Chave desconhecida aguardando confirmação, junto da operação repetida se ela for aceita.
+    host_key_prompt: Option<(HostKey,HostKeyRetry)>,
+    /// Senhas `prompt` já digitadas nesta sessão da TUI; nunca são gravadas.
+    secret_answers: PromptAnswers,
+    /// Comando (e valores dos placeholders) aguardando o formulário de senhas.
+    pending_secrets: Option<(ServerCommands,HashMap<String,String>)>,
+    /// Comando entregue à thread de conexões e ainda não concluído.
+    running_command: Option<ServerCommands>,
+    /// Navegador de arquivos do servidor selecionado, aberto com `b`.
+    browser: Option<FileBrowser>,
+    /// Transferência aguardando o formulário de caminhos, com o arquivo remoto escolhido.
+    pending_transfer: Option<(TransferDirection,String)>,
+    /// Operação do navegador aguardando o formulário de senhas.
+    pending_browser: Option<BrowserRequest>,
+    /// Operação do navegador entregue à thread de conexões, repetida se a chave do servidor for aceita.
+    running_browser: Option<BrowserRequest>,
+    /// Descrição da transferência do navegador em andamento, exibida com o progresso.
+    running_transfer: Option<String>,
+    /// Shell interativo aguardando o formulário de senhas.
+    pending_shell: bool,
+    /// Sessão conectada pela thread de conexões; o shell abre no próximo ciclo do loop.
+    ready_shell: Option<ShellSession>,
+    /// Túneis de `forwards:` e sua situação; o painel aparece com `f`.
+    tunnels: TunnelPanel,
+    tunnels_open: bool,
+    /// Túnel aguardando o formulário de senhas.
+    pending_tunnel: Option<(String,PortForward)>,
+    /// Última sondagem com `h` e o servidor a que pertence; o painel aparece com `health_open`.
+    health: Option<(String,HealthReport)>,
+    health_open: bool,
+    /// Sondagem aguardando o formulário de senhas.
+    pending_probe: bool,
+}
 
-    let info_paragraph = Paragraph::new(out.clone())
-    .block(
-        Block::default()
-                         .title("Saída das informações")
-                         .borders(Borders::ALL)
-                         .style(Style::default().fg(Color::Yellow).add_modifier(Modifier::BOLD))
-        ).style(Style::default().fg(Color::White));
+/// Operação que esbarrou em uma chave de servidor desconhecida e é repetida se ela for aceita.
+enum HostKeyRetry {
+    Command(ServerCommands),
+    Browser(BrowserRequest),
+    Shell,
+}
 
-    terminal.draw(|f| {
-        f.render_widget(info_paragraph, area);
-    }).unwrap();
+/// Mensagem de falha exibida no painel de informações, com a dica de `r` quando vale tentar de novo.
+fn connection_failure(error: &ConnectionError) -> String {
+    if error.is_retryable() {
+        format!("{}. Pressione r para tentar novamente.",error)
+    } else {
+        format!("{}. Verifique a configuração do servidor.",error)
+    }
 }
 
+/// Senha digitada errada: a próxima tentativa pergunta de novo.
+fn forget_rejected_answers(app: &mut App, servers: &ConfigYaml, server: &str, error: &ConnectionError) {
+    if error.is_auth_failure() {
+        forget_server_answers(app, servers, server);
+    }
+}
 
-#[tokio::main]
-async fn main() -> Result<(), io::Error> {
+fn forget_server_answers(app: &mut App, servers: &ConfigYaml, server: &str) {
+    if let Some((_, connect, _)) = servers.get_info_server(server) {
+        for label in connect.prompt_labels() {
+            app.secret_answers.remove(&label);
+        }
+    }
+}
 
-    let mut stdout = io::stdout();
-    execute!(stdout,EnterAlternateScreen,EnableMouseCapture)?;
-    let backend = CrosstermBackend::new(stdout);
-    let mut terminal = Terminal::new(backend)?;
+/// Senhas `prompt` da conexão que ainda não foram digitadas nesta sessão.
+fn missing_prompts(app: &App, connect: &ServerConnect) -> Vec<String> {
+    connect.prompt_labels()
+           .into_iter()
+           .filter(|label| !app.secret_answers.contains(label))
+           .collect()
+}
 
-    //let mut input = String::new();
+/// Atualiza o painel de túneis com o que a thread de encaminhamento informou.
+fn apply_forward_event(app: &mut App, event: ForwardEvent) {
+    match event {
+        ForwardEvent::Started { server, name } => {
+            app.input_info = format!("Túnel \"{}\" de {} aberto.",name,server);
+            app.tunnels.set_state(&server, &name, TunnelState::Active { connections: 0 });
+        },
+        ForwardEvent::Connections { server, name, open } => {
+            app.tunnels.set_state(&server, &name, TunnelState::Active { connections: open });
+        },
+        ForwardEvent::ConnectionFailed { server, name, error } => {
+            app.input_info = format!("Túnel \"{}\" de {}: conexão não encaminhada, {}",name,server,error);
+        },
+        ForwardEvent::Stopped { server, name, error: None } => {
+            app.input_info = format!("Túnel \"{}\" de {} fechado.",name,server);
+            app.tunnels.set_state(&server, &name, TunnelState::Stopped);
+        },
+        ForwardEvent::Stopped { server, name, error: Some(error) } => {
+            app.input_info = format!("Túnel \"{}\" de {} parou: {}",name,server,error);
+            app.tunnels.set_state(&server, &name, TunnelState::Failed(error));
+        }
+    }
+}
+
+/// Chave desconhecida em uma operação do navegador: pergunta se ela é confiável e, se for, repete
+/// a operação. Retorna `false` quando o erro é outro ou o servidor selecionado mudou.
+fn ask_browser_host_key(app: &mut App, server: &str, error: &ConnectionError, request: Option<BrowserRequest>) -> bool {
+    match (error.unknown_host_key(), request) {
+        (Some(key), Some(request)) if server == app.server_name => {
+            app.input_info = String::from("Servidor desconhecido; confira o fingerprint antes de confiar na chave.");
+            app.host_key_prompt = Some((key.clone(), HostKeyRetry::Browser(request)));
+            true
+        },
+        _ => false
+    }
+}
+
+/// Monta o `Job` de uma operação do navegador de arquivos no servidor selecionado.
+fn browser_job(app: &App, request: BrowserRequest) -> Job {
+    let server = app.server_name.clone();
+    let ssh = Box::new(SSH::new(&app.server_connect).with_prompt_answers(&app.secret_answers));
+
+    match request {
+        BrowserRequest::List(path) => Job::Browse { server, ssh, path },
+        BrowserRequest::Preview { path, tail } => Job::Preview { server, ssh, path, tail },
+        BrowserRequest::Follow { path, offset } => Job::Follow { server, ssh, path, offset },
+        BrowserRequest::Transfer(direction, transfer) => Job::Transfer { server, ssh, direction, transfer },
+    }
+}
 
-    let mut selected_index = 0;
-    let mut mainblock_selected_index: Option<usize> = None;
-    let mut focused_block = "sidebar";
+/// Suspende a TUI e entrega o terminal ao shell remoto do servidor selecionado até ele terminar.
+/// A sessão é própria, conectada pela thread de conexões: o canal fica em modo não bloqueante
+/// enquanto o shell está aberto.
+fn open_shell<B: Backend + io::Write>(terminal: &mut Terminal<B>, app: &mut App, session: ShellSession) -> io::Result<()> {
+    let session = session.into_session();
+    let size = crossterm::terminal::size()?;
 
-    let path = "config.yaml";
+    let result = suspend_tui(terminal.backend_mut(), || interactive_shell(&session, size, |timeout| {
+        if !event::poll(timeout)? {
+            return Ok(None);
+        }
 
-    let servers = ConfigYaml::new(&path);
+        Ok(match event::read()? {
+            Event::Key(key) => Some(ShellInput::Bytes(key_bytes(&key))),
+            Event::Resize(cols, rows) => Some(ShellInput::Resize { cols, rows }),
+            _ => None
+        })
+    }, &mut io::stdout()))?;
 
-    let server_items = match &servers {
-        Ok(servers) => servers.list_servers().clone(),
-        Err(_) => panic!("Erro ao ler arquivo yaml")
+    let _ = session.disconnect(None, "server_automation: shell encerrado", None);
+    terminal.clear()?;
+
+    app.input_info = match result {
+        Ok(code) => format!("Shell em {} encerrado (código de saída {}).",app.server_name,code),
+        Err(e) => format!("Shell em {} interrompido: {}",app.server_name,e)
     };
+    Ok(())
+}
 
-    let mut input_info = String::new();
+/// Situação exibida para o resultado de um servidor na execução em lote.
+fn server_step_status(status: &ServerStatus) -> StepStatus {
+    match status {
+        ServerStatus::Pending => StepStatus::Pending,
+        ServerStatus::Running => StepStatus::Running,
+        ServerStatus::Succeeded => StepStatus::Succeeded,
+        ServerStatus::Cancelled => StepStatus::Cancelled,
+        ServerStatus::Failed(_) | ServerStatus::Error(_) => StepStatus::Failed,
+    }
+}
 
-    let mut commands_server: Vec<ServerCommands> = vec![];
-    let mut server_connect: ServerConnect = ServerConnect::default();
-    let mut  output_messages_async: Arc<Mutex<String>> = Arc::new(Mutex::new(String::new()));
-    
-    let layout_areas = {
-        let size = terminal.size()?;
+/// Atualiza etapas, saída e mensagens com o progresso vindo da thread de conexões.
+fn apply_worker_event(app: &mut App, servers: &ConfigYaml, event: WorkerEvent) {
+    match event {
+        WorkerEvent::Connected { reused, .. } => {
+            app.input_info = if reused {
+                String::from("Executando comandos no servidor (conexão reaproveitada)...")
+            } else {
+                String::from("Conexão estabelecida; executando comandos no servidor...")
+            };
+        },
+        WorkerEvent::StepStarted { index, command } => {
+            if let Some(step) = app.steps.get_mut(index) {
+                step.1 = StepStatus::Running;
+            }
+            app.output_pane.push(OutputStream::Stdout, &format!("$ {}\n",command));
+        },
+        WorkerEvent::Output { stream, text, .. } => app.output_pane.push(stream, &text),
+        WorkerEvent::Progress { index, transferred, total } => {
+            let progress = match total {
+                Some(total) if total > 0 => format!("{}%", transferred * 100 / total),
+                _ => format!("{} KiB", transferred / 1024)
+            };
+
+            if let Some(transfer) = &app.running_transfer {
+                app.input_info = format!("{} ({})",transfer,progress);
+                return;
+            }
 
-        let chunks = Layout::default()
-        .direction(Direction::Vertical)
-                .constraints([
-                    Constraint::Percentage(80),
-                    Constraint::Percentage(20),
-                    ])
-                .split(size);
+            let label = app.running_command.as_ref()
+                                           .and_then(|command| command.steps().get(index))
+                                           .map(|step| step.label())
+                                           .unwrap_or_default();
+            if let Some(step) = app.steps.get_mut(index) {
+                step.0 = format!("{} ({})",label,progress);
+            }
+        },
+        WorkerEvent::StepFinished { index, result } => {
+            let continue_on_error = app.running_command.as_ref()
+                                                       .and_then(|command| command.steps().get(index))
+                                                       .is_some_and(|step| step.continue_on_error());
+            if let Some(step) = app.steps.get_mut(index) {
+                step.1 = if result.is_success() {
+                    StepStatus::Succeeded
+                } else if continue_on_error {
+                    StepStatus::Ignored
+                } else {
+                    StepStatus::Failed
+                };
+            }
+        },
+        WorkerEvent::Stopped => {
+            app.running_command = None;
+            app.running_browser = None;
+            app.running_transfer = None;
+            for (_,status) in app.steps.iter_mut() {
+                if *status == StepStatus::Pending || *status == StepStatus::Running {
+                    *status = StepStatus::Failed;
+                }
+            }
+            app.input_info = String::from("Erro interno na thread de conexões; a operação foi interrompida e as sessões abertas foram perdidas.");
+        },
+        WorkerEvent::Finished { server, result } => {
+            let command = app.running_command.take();
+            // Se outro servidor foi selecionado durante a execução, não oferece confiar na chave nem repetir.
+            let current = server == app.server_name;
+
+            if let Err(e) = &result {
+                if let (Some(key), Some(command), true) = (e.unknown_host_key(), command.clone(), current) {
+                    app.input_info = String::from("Servidor desconhecido; confira o fingerprint antes de confiar na chave.");
+                    app.host_key_prompt = Some((key.clone(), HostKeyRetry::Command(command)));
+                    return;
+                }
 
-        let top_chunks = Layout::default()
-                .direction(Direction::Horizontal)
-                .constraints([
-                    Constraint::Percentage(30),
-                    Constraint::Percentage(70),
-                    ])
-                .split(chunks[0]);
+                forget_rejected_answers(app, servers, &server, e);
 
-        let main_block_chunks = Layout::default()
-            .direction(Direction::Vertical)
-            .constraints([
-                Constraint::Min(3),
-                Constraint::Length(1),
-                Constraint::Min(5)
-            ]).split(top_chunks[1]);
+                if e.is_retryable() && current {
+                    app.retry_command = command;
+                }
+            }
 
-        (chunks,top_chunks,main_block_chunks)
-    };
+            for (_,status) in app.steps.iter_mut() {
+                if *status == StepStatus::Pending || *status == StepStatus::Running {
+                    *status = StepStatus::Skipped;
+                }
+            }
 
-    enable_raw_mode()?;
+            if let Some(index) = result.as_ref().ok().and_then(|report| report.cancelled_step()) {
+                if let Some(step) = app.steps.get_mut(index) {
+                    step.1 = StepStatus::Cancelled;
+                }
+            }
 
-    loop {
-        terminal.draw(|f| {
+            app.input_info = match result {
+                Ok(report) if report.is_cancelled() => format!(
+                    "Comando cancelado; {} etapa(s) executada(s), as demais não foram iniciadas.",
+                    report.steps().len()
+                    ),
+                Ok(report) => match report.failed_step() {
+                    None => format!(
+                        "Comandos executados com sucesso ({} etapas) em {:.1}s.",
+                        report.steps().len(),
+                        report.steps().iter().map(|step| step.duration().as_secs_f64()).sum::<f64>()
+                        ),
+                    Some(index) => format!(
+                        "Falha na etapa {}: {} (código de saída {}).",
+                        index + 1,
+                        report.steps()[index].command(),
+                        report.steps()[index].exit_code()
+                        )
+                },
+                Err(e) => connection_failure(&e)
+            };
+        },
+        WorkerEvent::ServerStarted { index } => {
+            if let Some(step) = app.steps.get_mut(index) {
+                step.1 = StepStatus::Running;
+            }
+        },
+        WorkerEvent::ServerFinished { index, server, status } => {
+            if let Some(step) = app.steps.get_mut(index) {
+                *step = (
+                    format!("{} - {}",server,cli::status_label(&status)),
+                    server_step_status(&status)
+                    );
+            }
 
-            let (chunks,top_chunks,main_block_chunks) = &layout_areas;
+            let finished = app.steps.iter()
+                                    .filter(|(_, status)| matches!(status, StepStatus::Succeeded | StepStatus::Failed))
+                                    .count();
+            let name = app.running_command.as_ref().map(|command| command.name().to_string()).unwrap_or_default();
+            app.input_info = format!("Executando \"{}\": {}/{} servidores concluídos",name,finished,app.steps.len());
+        },
+        WorkerEvent::ParallelFinished { runs } => {
+            let name = app.running_command.take().map(|command| command.name().to_string()).unwrap_or_default();
+
+            for (step, run) in app.steps.iter_mut().zip(&runs) {
+                *step = (format!("{} - {}",run.server(),cli::status_label(run.status())), server_step_status(run.status()));
+            }
 
-            let mut sidebar_items = vec![];
+            for run in runs.iter().filter(|run| run.is_auth_failure()) {
+                forget_server_answers(app, servers, run.server());
+            }
 
-            if server_items.len() > 0 {
-             sidebar_items = server_items.iter()
-                                         .map(|item| ListItem::new(item.name.clone()))
-                                         .collect();
+            for run in &runs {
+                app.output_pane.push(OutputStream::Stdout, &format!("=== {} ({}, {:.1}s) ===\n",run.server(),cli::status_label(run.status()),run.duration().as_secs_f64()));
+                for (stream, text) in run.output() {
+                    app.output_pane.push(*stream, text);
+                }
+                if run.output().last().is_some_and(|(_, text)| !text.ends_with('\n')) {
+                    app.output_pane.push(OutputStream::Stdout, "\n");
+                }
             }
 
-            let mut sidebar_state = ListState::default();
+            let succeeded = runs.iter().filter(|run| *run.status() == ServerStatus::Succeeded).count();
+            let cancelled = runs.iter().filter(|run| *run.status() == ServerStatus::Cancelled).count();
+            app.input_info = match cancelled {
+                0 => format!("{} de {} servidores concluíram \"{}\" com sucesso.",succeeded,runs.len(),name),
+                _ => format!("\"{}\" cancelado: {} de {} servidores concluíram com sucesso, {} cancelado(s).",name,succeeded,runs.len(),cancelled)
+            };
+        },
+        WorkerEvent::ShellOpened { server, result } => {
+            let current = server == app.server_name;
+
+            match result {
+                Ok(session) if current => app.ready_shell = Some(session),
+                Ok(_) => app.input_info = format!("Shell em {} não aberto: outro servidor foi selecionado.",server),
+                Err(e) => {
+                    if let (Some(key), true) = (e.unknown_host_key(), current) {
+                        app.input_info = String::from("Servidor desconhecido; confira o fingerprint antes de confiar na chave.");
+                        app.host_key_prompt = Some((key.clone(), HostKeyRetry::Shell));
+                        return;
+                    }
 
-            sidebar_state.select(Some(selected_index));
+                    forget_rejected_answers(app, servers, &server, &e);
+                    app.input_info = connection_failure(&e);
+                }
+            }
+        },
+        WorkerEvent::Probed { server, result } => {
+            match result {
+                Ok(report) => {
+                    if server == app.server_name {
+                        app.input_info = health_summary(&report, &app.server_config);
+                        app.health_open = true;
+                        app.tunnels_open = false;
+                        app.browser = None;
+                    }
+                    app.health = Some((server, report));
+                },
+                Err(e) => {
+                    forget_rejected_answers(app, servers, &server, &e);
+                    app.input_info = connection_failure(&e);
+                }
+            }
+        },
+        WorkerEvent::Listing { server, result } => {
+            let request = app.running_browser.take();
+            let Some(browser) = app.browser.as_mut().filter(|browser| browser.server() == server) else {
+                return;
+            };
+
+            match result {
+                Ok((path, entries)) => {
+                    app.input_info = format!("{}: {} item(ns). Enter abre, Backspace volta, t acompanha, d baixa, u envia.",path,entries.len());
+                    browser.set_listing(path, entries);
+                },
+                Err(e) => {
+                    if ask_browser_host_key(app, &server, &e, request) {
+                        return;
+                    }
+                    forget_rejected_answers(app, servers, &server, &e);
+                    app.input_info = connection_failure(&e);
+                }
+            }
+        },
+        WorkerEvent::Preview { server, result } => {
+            let request = app.running_browser.take();
+            let Some(browser) = app.browser.as_mut().filter(|browser| browser.server() == server) else {
+                return;
+            };
+
+            match result {
+                Ok(preview) if preview.is_appended() => {
+                    browser.set_offset(preview.path(), preview.end());
+                    app.output_pane.push(OutputStream::Stdout, preview.text());
+                },
+                Ok(preview) => {
+                    if preview.is_tail() {
+                        browser.set_offset(preview.path(), preview.end());
+                    }
 
-            let sidebar = List::new(sidebar_items.into_iter().enumerate().map(|(i,item)|{
-                if i == selected_index {
-                    item.style(Style::default().fg(Color::Black).bg(Color::White))
-                } else {
-                    item
+                    let size = preview.size().map(|size| format!("{} bytes",size)).unwrap_or_default();
+                    app.output_pane.clear();
+                    app.output_pane.push(OutputStream::Stdout, &format!(
+                        "==> {} ({}{}) <==\n",
+                        preview.path(),
+                        size,
+                        match (preview.is_truncated(), preview.is_tail()) {
+                            (true, true) => format!(", últimos {} KiB", PREVIEW_BYTES / 1024),
+                            (true, false) => format!(", primeiros {} KiB", PREVIEW_BYTES / 1024),
+                            _ => String::new()
+                        }
+                        ));
+
+                    if preview.is_binary() {
+                        app.output_pane.push(OutputStream::Stderr, "Arquivo binário; use d para baixá-lo.\n");
+                    } else {
+                        app.output_pane.push(OutputStream::Stdout, preview.text());
+                        if !preview.text().ends_with('\n') {
+                            app.output_pane.push(OutputStream::Stdout, "\n");
+                        }
+                    }
+                },
+                Err(e) => {
+                    if ask_browser_host_key(app, &server, &e, request) {
+                        return;
+                    }
+                    if let Some(browser) = app.browser.as_mut() {
+                        browser.stop_following();
+                    }
+                    forget_rejected_answers(app, servers, &server, &e);
+                    app.input_info = connection_failure(&e);
                 }
-            }).collect::<Vec<_>>())
-            .block(Block::default().title("Menu").borders(Borders::ALL))
-            .style(Style::default().fg(Color::Green).add_modifier(Modifier::ITALIC))
+            }
+        },
+        WorkerEvent::Transferred { server, direction, result } => {
+            let request = app.running_browser.take();
+            let transfer = app.running_transfer.take().unwrap_or_default();
+
+            match result {
+                Ok(summary) => {
+                    app.input_info = format!("{}: {}",transfer,summary);
+                    if direction == TransferDirection::Upload {
+                        if let Some(browser) = app.browser.as_mut().filter(|browser| browser.server() == server) {
+                            browser.request_refresh();
+                        }
+                    }
+                },
+                Err(e) => {
+                    if ask_browser_host_key(app, &server, &e, request) {
+                        return;
+                    }
+                    forget_rejected_answers(app, servers, &server, &e);
+                    app.input_info = format!("{}: {}",transfer,connection_failure(&e));
+                }
+            }
+        }
+    }
+}
+
+fn draw_ui<B: Backend>(f: &mut Frame<B>, app: &App, layout_areas: &LayoutAreas) {
+
+    let (chunks,top_chunks,main_block_chunks) = layout_areas;
+
+    let grouped = app.sidebar_rows.iter().any(|row| matches!(row, SidebarRow::Group { .. }));
+
+    let sidebar_items: Vec<ListItem> = app.sidebar_rows.iter()
+                                                       .map(|row| {
+                                                           let item = ListItem::new(row.label(grouped, &app.marked_servers));
+                                                           match row {
+                                                               SidebarRow::Group { .. } => item.style(Style::default().fg(Color::Yellow).add_modifier(Modifier::BOLD)),
+                                                               SidebarRow::Server { .. } => item
+                                                           }
+                                                       })
+                                                       .collect();
+
+    let mut sidebar_state = ListState::default();
+
+    sidebar_state.select(Some(app.selected_index));
+
+    let sidebar = List::new(sidebar_items.into_iter().enumerate().map(|(i,item)|{
+        if i == app.selected_index {
+            item.style(Style::default().fg(Color::Black).bg(Color::White))
+        } else {
+            item
+        }
+    }).collect::<Vec<_>>())
+    .block(Block::default().title("Menu").borders(Borders::ALL))
+    .style(Style::default().fg(Color::Green).add_modifier(Modifier::ITALIC))
+    .highlight_style(
+        Style::default()
+                .fg(Color::Black)
+                .bg(Color::White)
+                .add_modifier(Modifier::BOLD)
+        );
+
+    f.render_stateful_widget(sidebar, top_chunks[0],&mut sidebar_state);
+
+
+
+    let mut server_commands = vec![];
+
+    if !app.commands_server.is_empty() {
+        server_commands = app.commands_server.iter()
+                                             .map(|item| ListItem::new(item.name()))
+                                             .collect();
+    }
+
+    let mut mainblock_state = ListState::default();
+
+    if let Some(index) = app.mainblock_selected_index {
+        mainblock_state.select(Some(index));
+    }
+
+    let info_paragraph = Paragraph::new(app.input_info.clone())
+    .block(
+            Block::default()
+                 .title("Saída das informações")
+                 .borders(Borders::ALL)
+                 .style(Style::default().fg(Color::Yellow).add_modifier(Modifier::BOLD))
+        ).style(Style::default().fg(Color::White));
+
+    f.render_widget(info_paragraph, main_block_chunks[0]);
+
+    let main_block = List::new(server_commands)
+        .block(
+            Block::default()
+                     .title("Opções")
+                     .borders(Borders::ALL)
+                     .style(Style::default().fg(Color::Green).add_modifier(Modifier::ITALIC)),
+            )
+            .style(Style::default().fg(Color::White))
             .highlight_style(
                 Style::default()
                         .fg(Color::Black)
@@ -135,249 +577,776 @@ async fn main() -> Result<(), io::Error> {
                         .add_modifier(Modifier::BOLD)
                 );
 
-            f.render_stateful_widget(sidebar, top_chunks[0],&mut sidebar_state);
-
 
+    let options_chunks = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints([
+            Constraint::Percentage(45),
+            Constraint::Percentage(55),
+        ]).split(main_block_chunks[1]);
+
+    f.render_stateful_widget(main_block, options_chunks[0], &mut mainblock_state);
+    let health = app.health.as_ref().filter(|(server, _)| app.health_open && *server == app.server_name);
+
+    match &app.browser {
+        _ if app.tunnels_open => {
+            let mut tunnels_state = ListState::default();
+            tunnels_state.select(Some(app.tunnels.selected()));
+            f.render_stateful_widget(RenderizeComponents::tunnels_component(&app.tunnels, app.focused_block == "tunnels"), options_chunks[1], &mut tunnels_state);
+        },
+        Some(browser) => {
+            let mut browser_state = ListState::default();
+            browser_state.select(Some(browser.selected()));
+            f.render_stateful_widget(RenderizeComponents::browser_component(browser, app.focused_block == "browser"), options_chunks[1], &mut browser_state);
+        },
+        None if health.is_some() => {
+            if let Some((_, report)) = health {
+                f.render_widget(RenderizeComponents::health_component(report, &app.server_config), options_chunks[1]);
+            }
+        },
+        None => f.render_widget(RenderizeComponents::steps_component(app.steps_title, &app.steps), options_chunks[1])
+    }
 
-            let mut server_commands = vec![];
+    let output_height = output_height(layout_areas);
+    f.render_widget(RenderizeComponents::output_component(&app.output_pane, output_height), main_block_chunks[2]);
+
+
+    let bottom_block = Paragraph::new(vec![
+        Spans::from(vec![
+          Span::raw("1 - Para acessar o item, aperte a tecla "),
+          Span::styled("Up/Down", Style::default().fg(Color::Green).add_modifier(Modifier::BOLD)),
+          Span::raw(" e selecione com "),
+          Span::styled("Enter", Style::default().fg(Color::Green).add_modifier(Modifier::BOLD))
+        ]),
+        Spans::from(vec![
+            Span::raw("2 - Altere entre o menu lateral e comandos com "),
+            Span::styled("Left/Right", Style::default().fg(Color::Green).add_modifier(Modifier::BOLD)),
+            Span::raw("; marque servidores com "),
+            Span::styled("Espaço", Style::default().fg(Color::Green).add_modifier(Modifier::BOLD)),
+            Span::raw(" para executar em todos"),
+        ]),
+        Spans::from(vec![
+            Span::raw("3 - Sai com "),
+            Span::styled("Esc",Style::default().fg(Color::Red).add_modifier(Modifier::BOLD)),
+            Span::raw("; após falha de conexão, tente de novo com "),
+            Span::styled("r",Style::default().fg(Color::Green).add_modifier(Modifier::BOLD)),
+            Span::raw("; cancele o comando em andamento com "),
+            Span::styled("c",Style::default().fg(Color::Red).add_modifier(Modifier::BOLD))
+        ]),
+        Spans::from(vec![
+            Span::raw("4 - Arquivos do servidor com "),
+            Span::styled("b",Style::default().fg(Color::Green).add_modifier(Modifier::BOLD)),
+            Span::raw("; shell com "),
+            Span::styled("s",Style::default().fg(Color::Green).add_modifier(Modifier::BOLD)),
+            Span::raw("; túneis com "),
+            Span::styled("f",Style::default().fg(Color::Green).add_modifier(Modifier::BOLD)),
+            Span::raw("; saúde com "),
+            Span::styled("h",Style::default().fg(Color::Green).add_modifier(Modifier::BOLD))
+        ])
+    ])
+    .block(Block::default().title("Instruções").borders(Borders::ALL))
+    .style(Style::default().fg(Color::Yellow).add_modifier(Modifier::BOLD));
+
+    f.render_widget(bottom_block, chunks[1]);
+
+    if let Some(form) = &app.input_form {
+        let area = Rect {
+            x: top_chunks[1].x + 2,
+            y: top_chunks[1].y + 1,
+            width: top_chunks[1].width.saturating_sub(4),
+            height: (form.fields.len() as u16 * 2 + 4).min(top_chunks[1].height.saturating_sub(2)),
+        };
+
+        f.render_widget(Clear, area);
+        f.render_widget(RenderizeComponents::input_form_component(form), area);
+    }
 
-            if !commands_server.is_empty() {
-                server_commands = commands_server.iter()
-                                                  .map(|item| ListItem::new(item.name()))
-                                                  .collect();
-            }
+    if let Some((host_key,_)) = &app.host_key_prompt {
+        let area = Rect {
+            x: top_chunks[1].x + 2,
+            y: top_chunks[1].y + 1,
+            width: top_chunks[1].width.saturating_sub(4),
+            height: 8.min(top_chunks[1].height.saturating_sub(2)),
+        };
 
-            let mut mainblock_state = ListState::default();
+        f.render_widget(Clear, area);
+        f.render_widget(RenderizeComponents::host_key_component(host_key), area);
+    }
+}
 
-            if let Some(index) = mainblock_selected_index {
-                mainblock_state.select(Some(index));
-            }
+/// Quantidade de linhas visíveis no painel de saída, descontando as bordas.
+fn output_height(layout_areas: &LayoutAreas) -> usize {
+    layout_areas.2[2].height.saturating_sub(2) as usize
+}
 
-            let info_paragraph = Paragraph::new(input_info.clone())
-            .block(
-                    Block::default()
-                         .title("Saída das informações")
-                         .borders(Borders::ALL)
-                         .style(Style::default().fg(Color::Yellow).add_modifier(Modifier::BOLD))
-                ).style(Style::default().fg(Color::White));
+#[tokio::main]
+async fn main() -> Result<(), io::Error> {
 
-            f.render_widget(info_paragraph, main_block_chunks[0]);
+    let args = match Args::parse(std::env::args().skip(1)) {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("{}\n\n{}",e,USAGE);
+            std::process::exit(2);
+        }
+    };
 
-            let main_block = List::new(server_commands)
-                .block(
-                    Block::default()
-                             .title("Opções")
-                             .borders(Borders::ALL)
-                             .style(Style::default().fg(Color::Green).add_modifier(Modifier::ITALIC)),
-                    )
-                    .style(Style::default().fg(Color::White))
-                    .highlight_style(
-                        Style::default()
-                                .fg(Color::Black)
-                                .bg(Color::White)
-                                .add_modifier(Modifier::BOLD)
-                        );
+    if args.help {
+        println!("{}",USAGE);
+        return Ok(());
+    }
 
+    match &args.command {
+        Some(Command::Encrypt { file, output }) => std::process::exit(cli::encrypt_file(file, output.as_deref())),
+        Some(Command::Decrypt { file, output }) => std::process::exit(cli::decrypt_file(file, output.as_deref())),
+        Some(Command::Edit { file }) => std::process::exit(cli::edit_file(file)),
+        _ => {}
+    }
 
-            f.render_stateful_widget(main_block, main_block_chunks[2], &mut mainblock_state);
+    let config_paths = match resolve_config_paths(&args.config_paths) {
+        Ok(paths) => paths,
+        Err(e) => {
+            eprintln!("{}",e);
+            std::process::exit(1);
+        }
+    };
 
+    let passphrase = if contains_vault(&config_paths) {
+        match cli::vault_passphrase(false) {
+            Ok(passphrase) => Some(passphrase),
+            Err(e) => {
+                eprintln!("{}",e);
+                std::process::exit(1);
+            }
+        }
+    } else {
+        None
+    };
 
-            let bottom_block = Paragraph::new(vec![
-                Spans::from(vec![
-                  Span::raw("1 - Para acessar o item, aperte a tecla "),
-                  Span::styled("Up/Down", Style::default().fg(Color::Green).add_modifier(Modifier::BOLD)),
-                  Span::raw(" e selecione com "),
-                  Span::styled("Enter", Style::default().fg(Color::Green).add_modifier(Modifier::BOLD))
-                ]),
-                Spans::from(vec![
-                    Span::raw("2 - Altere entre o menu lateral e comandos com "),
-                    Span::styled("Left/Right", Style::default().fg(Color::Green).add_modifier(Modifier::BOLD)),
-                ]),
-                Spans::from(vec![
-                    Span::raw("2 - Sai com "),
-                    Span::styled("Esc",Style::default().fg(Color::Red).add_modifier(Modifier::BOLD))
-                ])
-            ])
-            .block(Block::default().title("Instruções").borders(Borders::ALL))
-            .style(Style::default().fg(Color::Yellow).add_modifier(Modifier::BOLD));
+    if args.command == Some(Command::Validate) {
+        let diagnostics = validate_files(&config_paths, passphrase.as_ref());
+        for diagnostic in &diagnostics {
+            println!("{}",diagnostic);
+        }
 
-            f.render_widget(bottom_block, chunks[1]);
+        if has_errors(&diagnostics) {
+            std::process::exit(1);
+        }
 
-        })?;
+        println!("Configuração válida ({} aviso(s))",diagnostics.len());
+        return Ok(());
+    }
 
-        if crossterm::event::poll(Duration::from_millis(100))? {
-            if let Event::Key(key) = event::read()? {
-                match key.code {
-                    KeyCode::Esc => {
-                        focused_block  = "sidebar";
-
-                        break
-                    },
-                    KeyCode::Up => {
-                        if focused_block == "sidebar" {
-                            if selected_index > 0 {
-                                selected_index -= 1;
-                            }
-                        } else if focused_block == "mainblock" {
-                            if let Some(index) = mainblock_selected_index {
-                                if index > 0 {
-                                    mainblock_selected_index = Some(index -1);
-                                }
-                            }
-                        }
+    let servers = match ConfigYaml::load(&config_paths, passphrase.as_ref()) {
+        Ok(servers) => servers,
+        Err(_) => {
+            for diagnostic in validate_files(&config_paths, passphrase.as_ref()) {
+                eprintln!("{}",diagnostic);
+            }
+            std::process::exit(1);
+        }
+    };
 
-                    },
-                    KeyCode::Down => {
-                        if focused_block == "sidebar" {
-                            if selected_index < server_items.len() - 1 {
-                                selected_index += 1
-                            }
-                        } else if focused_block == "mainblock" {
-                            if let Some(index) = mainblock_selected_index {
-                                mainblock_selected_index = Some(index + 1);
-                            }
-                        }
-                    },
-                    KeyCode::Left => {
-                        focused_block = "sidebar";
-                    },
-                    KeyCode::Right => {
-                        if !commands_server.is_empty() {
-                            focused_block = "mainblock";
-                        }
-                    }
-                    KeyCode::Enter => {
-                        if focused_block == "sidebar" {
-                            if let Some(selected_server) = server_items.get(selected_index) {
+    match &args.command {
+        Some(Command::ListServers { selection }) => {
+            std::process::exit(cli::list_servers(&servers, selection));
+        },
+        Some(Command::ListCommands { selection }) => {
+            std::process::exit(cli::list_commands(&servers, selection));
+        },
+        Some(Command::Run { selection, command, vars, parallel }) => {
+            std::process::exit(cli::run_command(&servers, selection, command, vars, *parallel));
+        },
+        _ => {}
+    }
 
-                                let server_info = match &servers {
-                                    Ok(server) => server.get_info_server(&selected_server.name),
-                                    Err(erro) => panic!("Erro ao buscar servidor {:?}",erro)
 
-                                };
+    let mut stdout = io::stdout();
+    execute!(stdout,EnterAlternateScreen,EnableMouseCapture)?;
+    let backend = CrosstermBackend::new(stdout);
+    let mut terminal = Terminal::new(backend)?;
 
+    //let mut input = String::new();
 
-                                match server_info {
-                                    Some((config,connect,commands)) => {
-                                        input_info = format!(
-                                            "So: {:?}, Memória: {:?}, Disco: {:?}",
-                                            config.os(), config.memory(), config.disk()
-                                            );
-                                        commands_server = commands;
-                                        server_connect = connect;
-                                        mainblock_selected_index = Some(0);
-                                    },
-                                    None => {
-                                        input_info = "Não foi possivel obter as informações".to_string();
-                                    }
-                                }
+    let mut app = App {
+        sidebar_rows: sidebar_rows(&servers, &[]),
+        collapsed_groups: vec![],
+        selected_index: 0,
+        mainblock_selected_index: None,
+        focused_block: "sidebar",
+        input_info: String::new(),
+        commands_server: vec![],
+        server_name: String::new(),
+        server_connect: ServerConnect::default(),
+        server_config: ServerConfig::default(),
+        input_form: None,
+        pending_command: None,
+        output_pane: OutputPane::default(),
+        steps: vec![],
+        steps_title: "Etapas",
+        marked_servers: vec![],
+        retry_command: None,
+        host_key_prompt: None,
+        secret_answers: PromptAnswers::default(),
+        pending_secrets: None,
+        running_command: None,
+        browser: None,
+        pending_transfer: None,
+        pending_browser: None,
+        running_transfer: None,
+        pending_shell: false,
+        running_browser: None,
+        ready_shell: None,
+        tunnels: TunnelPanel::default(),
+        tunnels_open: false,
+        pending_tunnel: None,
+        health: None,
+        health_open: false,
+        pending_probe: false,
+    };
 
-                            }
-                        } else if focused_block == "mainblock" {
-                            if let Some(index) = mainblock_selected_index {
-                                 let selected_command = &commands_server[index];
-                                 let (_,_,main_block_chunks) = &layout_areas;
+    let layout_areas = {
+        let size = terminal.size()?;
 
-                                 let ssh = SSH::new(&server_connect);
+        let chunks = Layout::default()
+        .direction(Direction::Vertical)
+                .constraints([
+                    Constraint::Percentage(80),
+                    Constraint::Percentage(20),
+                    ])
+                .split(size);
 
-                                 output_messages_async = Arc::new(Mutex::new(String::from("Iniciando conexão com o servidor")));
+        let top_chunks = Layout::default()
+                .direction(Direction::Horizontal)
+                .constraints([
+                    Constraint::Percentage(30),
+                    Constraint::Percentage(70),
+                    ])
+                .split(chunks[0]);
 
-                                 let messages = output_messages_async.clone();
-                                 let blocks = main_block_chunks[0];
+        let main_block_chunks = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([
+                Constraint::Length(3),
+                Constraint::Percentage(35),
+                Constraint::Min(5)
+            ]).split(top_chunks[1]);
 
-                                 tokio::spawn(async move {
-                                      update_info_paragraph(messages,blocks).await;
-                                 });
-                                 sleep(Duration::from_secs(5)).await;
+        (chunks,top_chunks,main_block_chunks)
+    };
 
-                                 let mut session = Session::new()?;
-                                 if *server_connect.type_connection() == ConnectionType::SSH {
+    let mut worker = Worker::spawn();
+    let forwarder = Forwarder::spawn();
 
-                                     session = match ssh.connect() {
-                                         Ok(sess) => {
-                                             output_messages_async = Arc::new(Mutex::new(String::from("Conexão com o servidor estabelecida....")));
+    enable_raw_mode()?;
 
-                                             let messages = output_messages_async.clone();
-                                             tokio::spawn(async move {
-                                                 update_info_paragraph(messages,blocks).await;
-                                            });
-                                            sleep(Duration::from_secs(5)).await;
+    loop {
+        while let Some(event) = worker.try_recv() {
+            let stopped = matches!(event, WorkerEvent::Stopped);
+            apply_worker_event(&mut app, &servers, event);
+            if stopped {
+                // A thread morta não aceita mais `Job`s; uma nova começa com o pool vazio.
+                worker = Worker::spawn();
+            }
+        }
+        while let Some(event) = forwarder.try_recv() {
+            apply_forward_event(&mut app, event);
+        }
+        if let Some(session) = app.ready_shell.take() {
+            open_shell(&mut terminal, &mut app, session)?;
+        }
 
-                                             sess
-                                         },
+        terminal.draw(|f| draw_ui(f, &app, &layout_areas))?;
 
-                                         Err(e) => {
-                                             output_messages_async = Arc::new(Mutex::new(String::from(format!("Não foi possivel conectar-se ao servidor, {:?}",e))));
+        let mut command_to_run: Option<ServerCommands> = None;
+        let mut run_values: HashMap<String,String> = HashMap::new();
+        let mut browser_request: Option<BrowserRequest> = None;
+        let mut shell_requested = false;
+        let mut tunnel_to_start: Option<(String,PortForward)> = None;
+        let mut probe_requested = false;
 
-                                             let messages = output_messages_async.clone();
-                                             tokio::spawn(async move {
-                                                 update_info_paragraph(messages,blocks).await;
-                                            });
-                                            sleep(Duration::from_secs(5)).await;
+        if crossterm::event::poll(Duration::from_millis(100))? {
+            if let Event::Key(key) = event::read()? {
+                if let Some((host_key, retry)) = app.host_key_prompt.take() {
+                    match key.code {
+                        KeyCode::Char('s') | KeyCode::Char('S') => {
+                            match SSH::new(&app.server_connect).trust_host_key(&host_key) {
+                                Ok(()) => {
+                                    app.input_info = format!("Chave {} registrada no known_hosts.",host_key.fingerprint());
+                                    match retry {
+                                        HostKeyRetry::Command(command) => command_to_run = Some(command),
+                                        HostKeyRetry::Browser(request) => browser_request = Some(request),
+                                        HostKeyRetry::Shell => shell_requested = true,
+                                    }
+                                },
+                                Err(e) => app.input_info = e.to_string()
+                            }
+                        },
+                        KeyCode::Char('n') | KeyCode::Char('N') | KeyCode::Esc => {
+                            app.input_info = String::from("Conexão cancelada; a chave do servidor não foi registrada.");
+                        },
+                        _ => app.host_key_prompt = Some((host_key, retry))
+                    }
+                } else if let Some(form) = app.input_form.as_mut() {
+                    match key.code {
+                        KeyCode::Esc => {
+                            app.input_form = None;
+                            app.pending_command = None;
+                            app.pending_secrets = None;
+                            app.pending_transfer = None;
+                            app.pending_browser = None;
+                            app.pending_shell = false;
+                            app.pending_tunnel = None;
+                            app.pending_probe = false;
+                        },
+                        KeyCode::Tab | KeyCode::Down => form.next_field(),
+                        KeyCode::BackTab | KeyCode::Up => form.previous_field(),
+                        KeyCode::Backspace => form.pop_char(),
+                        KeyCode::Char(c) => form.push_char(c),
+                        KeyCode::Enter => {
+                            if let Some(command) = app.pending_command.take() {
+                                run_values = form.values();
+                                match command.fill_placeholders(&run_values) {
+                                    Ok(filled) => command_to_run = Some(filled),
+                                    Err(e) => app.input_info = e
+                                }
+                            }
+                            if let Some((command, values)) = app.pending_secrets.take() {
+                                for (label, value) in form.values() {
+                                    app.secret_answers.insert(&label, SecretString::new(&value));
+                                }
+                                run_values = values;
+                                command_to_run = Some(command);
+                            }
+                            if let Some(request) = app.pending_browser.take() {
+                                for (label, value) in form.values() {
+                                    app.secret_answers.insert(&label, SecretString::new(&value));
+                                }
+                                browser_request = Some(request);
+                            }
+                            if app.pending_shell {
+                                for (label, value) in form.values() {
+                                    app.secret_answers.insert(&label, SecretString::new(&value));
+                                }
+                                app.pending_shell = false;
+                                shell_requested = true;
+                            }
+                            if app.pending_probe {
+                                for (label, value) in form.values() {
+                                    app.secret_answers.insert(&label, SecretString::new(&value));
+                                }
+                                app.pending_probe = false;
+                                probe_requested = true;
+                            }
+                            if let Some(tunnel) = app.pending_tunnel.take() {
+                                for (label, value) in form.values() {
+                                    app.secret_answers.insert(&label, SecretString::new(&value));
+                                }
+                                tunnel_to_start = Some(tunnel);
+                            }
+                            if let Some((direction, remote)) = app.pending_transfer.take() {
+                                let values = form.values();
+                                let transfer = match direction {
+                                    TransferDirection::Download => FileTransfer::new(&remote, values.get("Destino local").map(String::as_str).unwrap_or_default()),
+                                    TransferDirection::Upload => {
+                                        let local = values.get("Arquivo local").cloned().unwrap_or_default();
+                                        let mut remote = values.get("Destino remoto").cloned().unwrap_or_default();
+                                        // Destino terminado em `/` recebe o nome do arquivo local.
+                                        if remote.ends_with('/') {
+                                            remote.push_str(std::path::Path::new(&local).file_name().map(|name| name.to_string_lossy()).unwrap_or_default().as_ref());
+                                        }
+                                        FileTransfer::new(&local, &remote)
+                                    }
+                                };
 
-                                             panic!("Não foi possivel conectar-se ao servidor: {:?}",e)
-                                         }
-                                     }
-                                 } else {
-                                     session = match ssh.connect_with_private_key() {
-                                         Ok(sess) => {
-                                             output_messages_async = Arc::new(Mutex::new(String::from("Conexão com o servidor estabelecida SSH_KEY....")));
+                                if transfer.from().trim().is_empty() || transfer.to().trim().is_empty() || transfer.to().ends_with('/') {
+                                    app.input_info = String::from("Informe a origem e o destino da transferência.");
+                                } else {
+                                    browser_request = Some(BrowserRequest::Transfer(direction, transfer));
+                                }
+                            }
+                            app.input_form = None;
+                        },
+                        _ => {}
+                    }
+                } else {
+                    match key.code {
+                        KeyCode::Esc if app.focused_block == "browser" => {
+                            app.browser = None;
+                            app.focused_block = "mainblock";
+                        },
+                        KeyCode::Esc if app.focused_block == "tunnels" => {
+                            app.tunnels_open = false;
+                            app.focused_block = "mainblock";
+                        },
+                        KeyCode::Char('h') if !app.server_name.is_empty() => {
+                            if app.health_open && app.health.as_ref().is_some_and(|(server, _)| *server == app.server_name) {
+                                app.health_open = false;
+                            } else {
+                                probe_requested = true;
+                            }
+                        },
+                        KeyCode::Char('f') if !app.server_name.is_empty() => {
+                            if app.tunnels_open {
+                                app.tunnels_open = false;
+                                app.focused_block = "mainblock";
+                            } else {
+                                app.tunnels.show_server(&app.server_name, servers.forwards(&app.server_name));
+                                app.tunnels_open = true;
+                                app.health_open = false;
+                                app.browser = None;
+                                app.focused_block = "tunnels";
+                            }
+                        },
+                        KeyCode::Up if app.focused_block == "tunnels" => app.tunnels.select_previous(),
+                        KeyCode::Down if app.focused_block == "tunnels" => app.tunnels.select_next(),
+                        KeyCode::Enter | KeyCode::Char(' ') if app.focused_block == "tunnels" => {
+                            if let Some(row) = app.tunnels.selected_row() {
+                                if row.state.is_running() {
+                                    forwarder.stop(&row.server, row.forward.name());
+                                } else {
+                                    tunnel_to_start = Some((row.server.clone(), row.forward.clone()));
+                                }
+                            }
+                        },
+                        KeyCode::Esc => {
+                            break
+                        },
+                        KeyCode::Char('s') if !app.server_name.is_empty() => {
+                            shell_requested = true;
+                        },
+                        KeyCode::Char('b') if !app.server_name.is_empty() => {
+                            if app.browser.take().is_some() {
+                                app.focused_block = "mainblock";
+                            } else {
+                                app.browser = Some(FileBrowser::new(&app.server_name));
+                                app.tunnels_open = false;
+                                app.health_open = false;
+                                app.focused_block = "browser";
+                            }
+                        },
+                        KeyCode::Up if app.focused_block == "browser" => {
+                            if let Some(browser) = app.browser.as_mut() {
+                                browser.select_previous();
+                            }
+                        },
+                        KeyCode::Down if app.focused_block == "browser" => {
+                            if let Some(browser) = app.browser.as_mut() {
+                                browser.select_next();
+                            }
+                        },
+                        KeyCode::Enter if app.focused_block == "browser" => {
+                            if let Some(browser) = app.browser.as_mut() {
+                                if let Some(entry) = browser.selected_entry().cloned() {
+                                    if entry.is_dir() {
+                                        browser_request = Some(BrowserRequest::List(entry.path().to_string()));
+                                    } else {
+                                        browser.stop_following();
+                                        browser_request = Some(BrowserRequest::Preview { path: entry.path().to_string(), tail: false });
+                                    }
+                                }
+                            }
+                        },
+                        KeyCode::Backspace if app.focused_block == "browser" => {
+                            browser_request = app.browser.as_ref().map(|browser| BrowserRequest::List(browser.parent()));
+                        },
+                        KeyCode::Char('t') if app.focused_block == "browser" => {
+                            if let Some(browser) = app.browser.as_mut() {
+                                match browser.selected_entry().filter(|entry| !entry.is_dir()).map(|entry| entry.path().to_string()) {
+                                    Some(path) if browser.following() == Some(path.as_str()) => {
+                                        browser.stop_following();
+                                        app.input_info = format!("Parou de acompanhar {}.",path);
+                                    },
+                                    Some(path) => {
+                                        app.input_info = format!("Acompanhando {}; t de novo para parar.",path);
+                                        browser.follow(&path);
+                                    },
+                                    None => {}
+                                }
+                            }
+                        },
+                        KeyCode::Char('d') if app.focused_block == "browser" => {
+                            if let Some(entry) = app.browser.as_ref().and_then(|browser| browser.selected_entry()).filter(|entry| !entry.is_dir()) {
+                                app.input_form = Some(InputForm::from_fields(
+                                    &format!("Baixar {}",entry.path()),
+                                    &[("Destino local", format!("./{}",entry.name()))]
+                                    ));
+                                app.pending_transfer = Some((TransferDirection::Download, entry.path().to_string()));
+                            }
+                        },
+                        KeyCode::Char('u') if app.focused_block == "browser" => {
+                            if let Some(browser) = app.browser.as_ref() {
+                                app.input_form = Some(InputForm::from_fields(
+                                    &format!("Enviar para {}",browser.path()),
+                                    &[("Arquivo local", String::new()), ("Destino remoto", format!("{}/",browser.path().trim_end_matches('/')))]
+                                    ));
+                                app.pending_transfer = Some((TransferDirection::Upload, browser.path().to_string()));
+                            }
+                        },
+                        KeyCode::Up => {
+                            if app.focused_block == "sidebar" {
+                                app.selected_index = app.selected_index.saturating_sub(1);
+                            } else if app.focused_block == "mainblock" {
+                                if let Some(index) = app.mainblock_selected_index {
+                                    if index > 0 {
+                                        app.mainblock_selected_index = Some(index -1);
+                                    }
+                                }
+                            }
 
-                                             let messages = output_messages_async.clone();
-                                             tokio::spawn(async move {
-                                                 update_info_paragraph(messages,blocks).await;
-                                            });
-                                            sleep(Duration::from_secs(5)).await;
+                        },
+                        KeyCode::Down => {
+                            if app.focused_block == "sidebar" {
+                                if app.selected_index + 1 < app.sidebar_rows.len() {
+                                    app.selected_index += 1
+                                }
+                            } else if app.focused_block == "mainblock" {
+                                if let Some(index) = app.mainblock_selected_index {
+                                    app.mainblock_selected_index = Some(index + 1);
+                                }
+                            }
+                        },
+                        KeyCode::PageUp => {
+                            app.output_pane.scroll_up(output_height(&layout_areas), output_height(&layout_areas));
+                        },
+                        KeyCode::PageDown => {
+                            app.output_pane.scroll_down(output_height(&layout_areas), output_height(&layout_areas));
+                        },
+                        KeyCode::Left => {
+                            app.focused_block = "sidebar";
+                        },
+                        KeyCode::Char(' ') if app.focused_block == "sidebar" => {
+                            match app.sidebar_rows.get(app.selected_index) {
+                                Some(SidebarRow::Server { name }) => {
+                                    match app.marked_servers.iter().position(|item| item == name) {
+                                        Some(position) => { app.marked_servers.remove(position); },
+                                        None => app.marked_servers.push(name.clone())
+                                    }
+                                },
+                                // Espaço no grupo marca todos os servidores; se já estavam marcados, desmarca.
+                                Some(SidebarRow::Group { servers: members, .. }) => {
+                                    if members.iter().all(|name| app.marked_servers.contains(name)) {
+                                        app.marked_servers.retain(|name| !members.contains(name));
+                                    } else {
+                                        for name in members {
+                                            if !app.marked_servers.contains(name) {
+                                                app.marked_servers.push(name.clone());
+                                            }
+                                        }
+                                    }
+                                },
+                                None => {}
+                            }
+                        },
+                        KeyCode::Char('c') if worker.is_busy() => {
+                            worker.cancel();
+                            app.input_info = String::from("Cancelando o comando em andamento...");
+                        },
+                        KeyCode::Char('r') if app.retry_command.is_some() && app.marked_servers.is_empty() => {
+                            command_to_run = app.retry_command.take();
+                        },
+                        KeyCode::Right if app.focused_block == "mainblock" && app.tunnels_open => {
+                            app.focused_block = "tunnels";
+                        },
+                        KeyCode::Right if app.focused_block == "mainblock" && app.browser.is_some() => {
+                            app.focused_block = "browser";
+                        },
+                        KeyCode::Right if !app.commands_server.is_empty() => {
+                            app.focused_block = "mainblock";
+                        }
+                        KeyCode::Enter => {
+                            if app.focused_block == "sidebar" {
+                                if let Some(SidebarRow::Group { name, .. }) = app.sidebar_rows.get(app.selected_index) {
+                                    match app.collapsed_groups.iter().position(|item| item == name) {
+                                        Some(position) => { app.collapsed_groups.remove(position); },
+                                        None => app.collapsed_groups.push(name.clone())
+                                    }
+                                    app.sidebar_rows = sidebar_rows(&servers, &app.collapsed_groups);
+                                } else if let Some(SidebarRow::Server { name }) = app.sidebar_rows.get(app.selected_index) {
+
+                                    let server_info = servers.get_info_server(name);
+
+                                    match server_info {
+                                        Some((config,connect,commands)) => {
+                                            app.retry_command = None;
+                                            app.input_info = format!(
+                                                "So: {:?}, Memória: {:?}, Disco: {:?}",
+                                                config.os(), config.memory(), config.disk()
+                                                );
+                                            app.commands_server = commands;
+                                            if app.server_name != *name {
+                                                app.browser = None;
+                                            }
+                                            if app.tunnels_open {
+                                                app.tunnels.show_server(name, servers.forwards(name));
+                                            }
+                                            app.server_name = name.clone();
+                                            app.server_connect = connect;
+                                            app.server_config = config;
+                                            app.mainblock_selected_index = Some(0);
+                                        },
+                                        None => {
+                                            app.input_info = "Não foi possivel obter as informações".to_string();
+                                        }
+                                    }
 
-                                             sess
-                                         },
+                                }
+                            } else if app.focused_block == "mainblock" {
+                                if let Some(selected_command) = app.mainblock_selected_index.and_then(|index| app.commands_server.get(index)) {
+                                    if selected_command.placeholders().is_empty() {
+                                        command_to_run = Some(selected_command.clone());
+                                    } else {
+                                        app.input_form = Some(InputForm::from_command(selected_command));
+                                        app.pending_command = Some(selected_command.clone());
+                                    }
+                                }
+                            }
 
-                                         Err(e) => {
-                                             output_messages_async = Arc::new(Mutex::new(String::from(format!("Não foi possivel conectar-se ao servidor, {:?}",e))));
+                        },
+                        _ => {}
+                    }
+                }
+            }
+        }
 
-                                             let messages = output_messages_async.clone();
-                                             tokio::spawn(async move {
-                                                 update_info_paragraph(messages,blocks).await;
-                                            });
-                                            sleep(Duration::from_secs(5)).await;
+        if let Some((server, forward)) = tunnel_to_start {
+            let connect = servers.get_info_server(&server).map(|(_, connect, _)| connect).unwrap_or_default();
+            let missing = missing_prompts(&app, &connect);
 
-                                             panic!("Não foi possivel conectar-se ao servidor: {:?}",e)
-                                         }
-                                     }
-                                 }
+            if missing.is_empty() {
+                app.tunnels.set_state(&server, forward.name(), TunnelState::Starting);
+                forwarder.start(&server, SSH::new(&connect).with_prompt_answers(&app.secret_answers), forward);
+            } else {
+                app.input_form = Some(InputForm::from_prompts(&missing));
+                app.pending_tunnel = Some((server, forward));
+            }
+            continue;
+        }
 
+        if probe_requested {
+            let missing = missing_prompts(&app, &app.server_connect);
+
+            if !missing.is_empty() {
+                app.input_form = Some(InputForm::from_prompts(&missing));
+                app.pending_probe = true;
+            } else if worker.is_busy() {
+                app.input_info = String::from("Aguarde a operação em andamento terminar.");
+            } else {
+                app.input_info = format!("Coletando a situação de {}...",app.server_name);
+                let ssh = SSH::new(&app.server_connect).with_prompt_answers(&app.secret_answers);
+                worker.submit(Job::Probe { server: app.server_name.clone(), ssh: Box::new(ssh) });
+            }
+            continue;
+        }
 
-                               output_messages_async = Arc::new(Mutex::new(String::from("Executando comandos no servidor...")));
+        if shell_requested {
+            let missing = missing_prompts(&app, &app.server_connect);
+            if !missing.is_empty() {
+                app.input_form = Some(InputForm::from_prompts(&missing));
+                app.pending_shell = true;
+            } else if worker.is_busy() {
+                app.input_info = String::from("Aguarde a operação em andamento terminar.");
+            } else {
+                app.input_info = format!("Abrindo shell em {}...",app.server_name);
+                let ssh = SSH::new(&app.server_connect).with_prompt_answers(&app.secret_answers);
+                worker.submit(Job::OpenShell { server: app.server_name.clone(), ssh: Box::new(ssh) });
+            }
+            continue;
+        }
 
-                               let messages = output_messages_async.clone();
-                               tokio::spawn(async move {
-                                   update_info_paragraph(messages,blocks).await;
-                              });
-                              sleep(Duration::from_secs(5)).await;
+        // Sem pedido do usuário, o navegador aproveita a thread livre para listar ou reler o `tail`.
+        if browser_request.is_none() && !worker.is_busy() && app.input_form.is_none() && app.host_key_prompt.is_none() {
+            browser_request = app.browser.as_mut().and_then(|browser| browser.pending_request());
+        }
 
-                                let output_command = ssh.execute_commands(&selected_command, session);
+        if let Some(request) = browser_request {
+            if worker.is_busy() {
+                app.input_info = String::from("Aguarde a operação em andamento terminar.");
+                continue;
+            }
 
+            let missing = missing_prompts(&app, &app.server_connect);
+            if !missing.is_empty() {
+                app.input_form = Some(InputForm::from_prompts(&missing));
+                app.pending_browser = Some(request);
+                continue;
+            }
 
-                               output_messages_async = Arc::new(Mutex::new(String::from(format!("Comandos executados com sucesso. Saída: {}",output_command))));
+            if let BrowserRequest::Transfer(direction, transfer) = &request {
+                let transfer = match direction {
+                    TransferDirection::Upload => format!("Enviando {} → {}",transfer.from(),transfer.to()),
+                    TransferDirection::Download => format!("Baixando {} → {}",transfer.from(),transfer.to()),
+                };
+                app.input_info = format!("{}...",transfer);
+                app.running_transfer = Some(transfer);
+            }
 
-                               let messages = output_messages_async.clone();
-                               tokio::spawn(async move {
-                                   update_info_paragraph(messages,blocks).await;
-                              });
-                              sleep(Duration::from_secs(5)).await;
+            app.running_browser = Some(request.clone());
+            worker.submit(browser_job(&app, request));
+        }
 
+        if let Some(selected_command) = command_to_run {
+            if worker.is_busy() {
+                app.input_info = String::from("Aguarde o comando em andamento terminar.");
+                continue;
+            }
 
+            let labels = if app.marked_servers.is_empty() {
+                app.server_connect.prompt_labels()
+            } else {
+                servers.prompt_labels(&app.marked_servers)
+            };
+            let missing: Vec<String> = labels.into_iter()
+                                             .filter(|label| !app.secret_answers.contains(label))
+                                             .collect();
+
+            if !missing.is_empty() {
+                app.input_form = Some(InputForm::from_prompts(&missing));
+                app.pending_secrets = Some((selected_command, run_values));
+                continue;
+            }
 
-                            }
-                        }
+            if !app.marked_servers.is_empty() {
+                let mut targets = match build_targets(&servers, &app.marked_servers, selected_command.name(), &run_values) {
+                    Ok(targets) => targets,
+                    Err(e) => {
+                        app.input_info = e;
+                        continue;
+                    }
+                };
 
-                    },
-                    _ => {}
+                for target in targets.iter_mut() {
+                    target.prompt_answers = app.secret_answers.clone();
                 }
+
+                app.output_pane.clear();
+                app.steps_title = "Servidores";
+                app.health_open = false;
+                app.steps = targets.iter()
+                                   .map(|target| (target.server.clone(), StepStatus::Pending))
+                                   .collect();
+                app.input_info = format!("Executando \"{}\" em {} servidores...",selected_command.name(),targets.len());
+                app.running_command = Some(selected_command);
+                worker.submit(Job::RunParallel { targets, limit: servers.parallelism() });
+                continue;
             }
+
+            let ssh = SSH::new(&app.server_connect).with_prompt_answers(&app.secret_answers);
+            app.steps_title = "Etapas";
+            app.health_open = false;
+
+            app.output_pane.clear();
+            app.steps = selected_command.steps()
+                                        .iter()
+                                        .map(|step| (step.label(), StepStatus::Pending))
+                                        .collect();
+            app.input_info = String::from("Conectando ao servidor...");
+            app.running_command = Some(selected_command.clone());
+            worker.submit(Job::Run { server: app.server_name.clone(), ssh: Box::new(ssh), command: selected_command });
         }
     }
 
+    worker.shutdown();
+    forwarder.shutdown();
     disable_raw_mode()?;
 
     execute!(
@@ -1,21 +1,136 @@
 
 use serde::{de::Error, Deserialize, Serialize};
-use std::result::Result;
+use std::{collections::HashMap, env, path::{Path, PathBuf}, result::Result, time::Duration};
 
+use crate::secrets::{Secret, SecretString};
 
-#[derive(Debug,PartialEq, Eq, Serialize ,Deserialize,Clone)]
+mod validation;
+mod vault;
+pub use validation::{has_errors, validate_file, validate_files, validate_str, Diagnostic, Severity};
+pub use vault::{contains_vault, decrypt, encrypt, is_vault, passphrase_from_env, read_config_file, VaultError, VAULT_EXTENSION, VAULT_PASSWORD_ENV};
+
+/// Variável de ambiente com um ou mais arquivos de configuração, separados como no `PATH`.
+pub const CONFIG_ENV: &str = "SERVER_MANAGER_CONFIG";
+
+/// Limite de execuções simultâneas quando `parallelism` não é informado.
+pub const DEFAULT_PARALLELISM: usize = 4;
+
+pub const DEFAULT_SSH_PORT: u16 = 22;
+
+/// Tempo limite de conexão quando `connect_timeout` não é informado.
+pub const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+
+#[allow(non_camel_case_types, clippy::upper_case_acronyms)]
+#[derive(Debug,PartialEq, Eq, Serialize ,Deserialize,Clone,Default)]
 pub enum ConnectionType {
+    #[default]
     SSH,
     SSH_KEY,
 
 }
 
+/// Como tratar um servidor cuja chave de host ainda não está no `known_hosts`.
+#[derive(Debug,PartialEq, Eq, Serialize, Deserialize,Clone,Copy,Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum HostKeyPolicy {
+    /// Recusa a conexão.
+    Strict,
+    /// Mostra o fingerprint e pergunta antes de confiar na chave.
+    #[default]
+    Ask,
+    /// Registra a chave no primeiro acesso sem perguntar.
+    AcceptNew,
+}
+
 #[derive(Debug,PartialEq, Eq, Serialize, Deserialize,Clone)]
 pub struct ServerDetails {
     pub name: String,
     config: ServerConfig,
     connect: ServerConnect,
+    #[serde(default)]
     commands: Vec<ServerCommands>,
+    /// Comandos do catálogo `commands:` do topo do arquivo usados por este servidor.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    uses: Vec<CommandReference>,
+    #[serde(default, rename = "become", alias = "sudo", skip_serializing_if = "Option::is_none")]
+    sudo: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    run_as: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    tags: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    groups: Vec<String>,
+    /// Túneis de porta que podem ser ligados pela TUI.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    forwards: Vec<PortForward>,
+}
+
+/// Túnel de porta do servidor.
+///
+/// ```yaml
+/// forwards:
+///   - name: "Postgres"
+///     direction: local     # porta local → host:port visto pelo servidor (padrão)
+///     bind_port: 15432
+///     host: "db.interno"
+///     port: 5432
+///   - name: "Webhook"
+///     direction: remote    # porta no servidor → host:port visto desta máquina
+///     bind_port: 8080
+///     host: "localhost"
+///     port: 3000
+/// ```
+#[derive(Debug,PartialEq, Eq, Serialize, Deserialize,Clone,Default)]
+pub struct PortForward {
+    name: String,
+    #[serde(default)]
+    direction: ForwardDirection,
+    /// Endereço em que a porta é aberta; por padrão, `127.0.0.1` (local) ou `localhost` (remoto).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    bind_address: Option<String>,
+    bind_port: u16,
+    host: String,
+    port: u16,
+}
+
+#[derive(Debug,PartialEq, Eq, Serialize, Deserialize,Clone,Copy,Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum ForwardDirection {
+    /// Escuta nesta máquina e encaminha pelo servidor (`ssh -L`).
+    #[default]
+    Local,
+    /// Escuta no servidor e encaminha para esta máquina (`ssh -R`).
+    Remote,
+}
+
+/// Referência a um comando do catálogo, com valores próprios do servidor para as variáveis.
+///
+/// ```yaml
+/// uses:
+///   - command: "Atualizar Servidor"
+///     vars:
+///       nome_pasta: "loja"
+/// ```
+#[derive(Debug,PartialEq, Eq, Serialize, Deserialize,Clone,Default)]
+pub struct CommandReference {
+    command: String,
+    /// Nome exibido no servidor; por padrão, o do comando do catálogo.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    vars: HashMap<String,String>,
+}
+
+/// Grupo declarado na seção `groups:`. Os membros são os servidores listados em `servers`
+/// mais os que citam o grupo no próprio `groups`.
+#[derive(Debug,PartialEq, Eq, Serialize, Deserialize,Clone,Default)]
+pub struct ServerGroup {
+    name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    description: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    servers: Vec<String>,
 }
 
 #[derive(Debug,PartialEq, Eq, Serialize, Deserialize,Clone,Default)]
@@ -29,62 +144,471 @@ pub struct ServerConfig {
 pub struct ServerConnect {
     type_connection: ConnectionType,
     user: String,
+    /// Endereço IP; o formato antigo `ip:porta` continua aceito.
+    #[serde(default)]
     ip_address: String,
+    /// Nome DNS do servidor, usado no lugar de `ip_address` quando informado.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    hostname: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    port: Option<u16>,
     location: Option<String>,
-    password: Option<String>,
+    /// Senha do usuário ou da chave privada; aceita as referências de `Secret` (`env:`, `file:`, `cmd:`, `prompt`).
+    password: Option<Secret>,
+    /// Segundos para abrir a conexão TCP e concluir handshake e autenticação.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    connect_timeout: Option<u64>,
+    /// Segundos que cada etapa pode levar antes de ser interrompida; sem limite quando ausente.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    command_timeout: Option<u64>,
+    /// Intervalo em segundos entre keepalives enviados durante a execução.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    keepalive_interval: Option<u32>,
+    /// Fingerprint esperado da chave do servidor (`SHA256:...`); quando informado, dispensa o `known_hosts`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    host_key_fingerprint: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    host_key_check: Option<HostKeyPolicy>,
+    /// Arquivo `known_hosts` usado no lugar de `~/.ssh/known_hosts`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    known_hosts: Option<String>,
+    /// Nome de outro servidor do arquivo usado como bastião. O bastião pode ter o próprio `jump_host`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    jump_host: Option<String>,
+    /// Conexão do bastião resolvida por `ConfigYaml::get_info_server`, ou o motivo de não ter sido resolvida.
+    #[serde(skip)]
+    jump: Option<Result<Box<ServerConnect>,String>>,
 }
 
-impl Default for ConnectionType {
-    fn default() -> Self {
-        ConnectionType::SSH
-    }
-}
 #[derive(Debug,PartialEq, Eq,Serialize, Deserialize,Clone,Default)]
 pub struct ServerCommands {
     name: String,
-    exec: Vec<String>
+    exec: Vec<CommandStep>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    vars: Vec<CommandVariable>,
+    #[serde(default, rename = "become", alias = "sudo", skip_serializing_if = "Option::is_none")]
+    sudo: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    run_as: Option<String>,
+}
+
+/// Entrada de `exec`: uma linha de shell simples, um mapa com opções da etapa ou uma
+/// transferência de arquivo pelo SFTP da mesma sessão.
+///
+/// ```yaml
+/// exec:
+///   - "git clone {url}"
+///   - run: "php index.php migrate"
+///     continue_on_error: true
+///   - upload: { from: "./nginx.conf", to: "/etc/nginx/nginx.conf", mode: "0644" }
+///   - download: { from: "/var/log/app.log", to: "./logs/app.log" }
+/// ```
+#[derive(Debug,PartialEq, Eq,Serialize, Deserialize,Clone)]
+#[serde(untagged)]
+pub enum CommandStep {
+    Shell(String),
+    Detailed {
+        run: String,
+        #[serde(default)]
+        continue_on_error: bool,
+    },
+    Upload {
+        upload: FileTransfer,
+        #[serde(default)]
+        continue_on_error: bool,
+    },
+    Download {
+        download: FileTransfer,
+        #[serde(default)]
+        continue_on_error: bool,
+    },
+}
+
+/// Origem e destino de uma etapa `upload` (local → servidor) ou `download` (servidor → local).
+/// Caminhos locais relativos partem do diretório atual; `~/` é o diretório home.
+#[derive(Debug,PartialEq, Eq,Serialize, Deserialize,Clone,Default)]
+pub struct FileTransfer {
+    from: String,
+    to: String,
+    /// Permissões em octal (`"0644"`) aplicadas ao arquivo enviado.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    mode: Option<String>,
+}
+
+#[derive(Debug,PartialEq, Eq,Clone,Copy)]
+pub enum TransferDirection {
+    Upload,
+    Download,
+}
+
+/// Declaração opcional de um placeholder `{nome}` usado nas linhas de `exec`.
+#[derive(Debug,PartialEq, Eq,Serialize, Deserialize,Clone,Default)]
+pub struct CommandVariable {
+    name: String,
+    default: Option<String>,
+    description: Option<String>,
 }
 
 #[derive(Debug, PartialEq, Eq, Serialize, Deserialize,Clone)]
 pub struct ConfigYaml {
     version: String,
     application: String,
+    /// Quantidade máxima de servidores executando ao mesmo tempo.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    parallelism: Option<usize>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    groups: Vec<ServerGroup>,
+    /// Catálogo de comandos compartilhados, referenciados pelos servidores em `uses`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    commands: Vec<ServerCommands>,
     servers: Vec<ServerDetails>
 }
 
 impl ConfigYaml {
     pub fn new(path: &str) -> Result<ConfigYaml,serde_yaml_ng::Error > {
+        ConfigYaml::open(Path::new(path), None)
+    }
 
-        let content_file = match std::fs::read_to_string(path) {
-            Ok(content) => content,
-            Err(e) => return Err(serde_yaml_ng::Error::custom(format!("Erro ao ler o arquivo: {}", e)))
-        };
+    /// Lê um arquivo, decifrando-o com `passphrase` (ou a de `SERVER_MANAGER_VAULT_PASSWORD`) quando está cifrado.
+    pub fn open(path: &Path, passphrase: Option<&SecretString>) -> Result<ConfigYaml,serde_yaml_ng::Error> {
 
-        let config: ConfigYaml = match serde_yaml_ng::from_str(&content_file) {
-            Ok(parsed) => parsed,
-            Err(e) => return Err(serde_yaml_ng::Error::custom(format!("Erro ao fazer parsing do arquivo Yaml: {}",e)))
-        };
+        let content_file = read_config_file(path, passphrase).map_err(serde_yaml_ng::Error::custom)?;
+
+        // O erro do serde é devolvido sem alteração para preservar `location()` (linha/coluna).
+        let config: ConfigYaml = serde_yaml_ng::from_str(&content_file)?;
         Ok(config)
 
     }
 
+    /// Carrega e mescla vários arquivos na ordem informada. Diretórios contribuem com todos os
+    /// seus arquivos `.yaml`/`.yml` (cifrados ou não) em ordem alfabética.
+    pub fn load(paths: &[PathBuf], passphrase: Option<&SecretString>) -> Result<ConfigYaml,serde_yaml_ng::Error> {
+        let mut merged: Option<ConfigYaml> = None;
+
+        for path in expand_config_paths(paths)? {
+            let config = ConfigYaml::open(&path, passphrase)
+                .map_err(|e| serde_yaml_ng::Error::custom(format!("{}: {}", path.display(), e)))?;
+
+            match merged.as_mut() {
+                Some(merged) => merged.merge(config),
+                None => merged = Some(config)
+            }
+        }
+
+        merged.ok_or_else(|| serde_yaml_ng::Error::custom("Nenhum arquivo de configuração informado"))
+    }
+
+    /// Acrescenta os servidores e grupos de `other`. Um item com o mesmo nome substitui o anterior;
+    /// `version` e `application` do primeiro arquivo são mantidos; `parallelism` vem do primeiro que o informar.
+    pub fn merge(&mut self, other: ConfigYaml) {
+        self.parallelism = self.parallelism.or(other.parallelism);
+
+        for command in other.commands {
+            match self.commands.iter_mut().find(|item| item.name == command.name) {
+                Some(existing) => *existing = command,
+                None => self.commands.push(command)
+            }
+        }
+
+        for group in other.groups {
+            match self.groups.iter_mut().find(|item| item.name == group.name) {
+                Some(existing) => *existing = group,
+                None => self.groups.push(group)
+            }
+        }
+
+        for server in other.servers {
+            match self.servers.iter_mut().find(|item| item.name == server.name) {
+                Some(existing) => *existing = server,
+                None => self.servers.push(server)
+            }
+        }
+    }
+
+    pub fn parallelism(&self) -> usize {
+        self.parallelism.unwrap_or(DEFAULT_PARALLELISM).max(1)
+    }
+
     pub fn list_servers(&self) -> &Vec<ServerDetails> {
         &self.servers
     }
 
+    /// Túneis declarados em `forwards:` pelo servidor.
+    pub fn forwards(&self, name_server: &str) -> Vec<PortForward> {
+        self.servers.iter()
+                    .find(|item| item.name == name_server)
+                    .map(|server| server.forwards.clone())
+                    .unwrap_or_default()
+    }
+
     pub fn get_info_server(&self,name_server: &str) -> Option<(ServerConfig,ServerConnect,Vec<ServerCommands>)> {
 
         self.servers.iter()
                     .find(| &item | item.name == name_server)
                     .map(| server | {
-                        (server.config.clone(),server.connect.clone(),server.commands.clone())
+                        (server.config.clone(),self.resolve_connect(server),server.effective_commands(&self.commands))
                     })
     }
 
+    /// Conexão do servidor com a cadeia de `jump_host` já resolvida.
+    fn resolve_connect(&self, server: &ServerDetails) -> ServerConnect {
+        let mut connect = server.connect.clone();
+
+        if connect.jump_host().is_some() {
+            connect.jump = Some(jump_chain(&self.servers, server).map(|chain| {
+                let mut jump: Option<Box<ServerConnect>> = None;
+
+                for bastion in chain.iter().rev() {
+                    let mut bastion_connect = bastion.connect.clone();
+                    bastion_connect.jump = jump.map(Ok);
+                    jump = Some(Box::new(bastion_connect));
+                }
+
+                jump.unwrap_or_default()
+            }));
+        }
+
+        connect
+    }
+
     pub fn get_quantity_servers(&self) -> usize {
         self.servers.len()
     }
 
+    pub fn commands(&self) -> &Vec<ServerCommands> {
+        &self.commands
+    }
+
+    pub fn groups(&self) -> &Vec<ServerGroup> {
+        &self.groups
+    }
+
+    /// Nomes de todos os grupos: primeiro os da seção `groups:`, depois os citados apenas nos servidores.
+    pub fn group_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.groups.iter().map(|group| group.name.clone()).collect();
+
+        for server in &self.servers {
+            for group in &server.groups {
+                if !names.contains(group) {
+                    names.push(group.clone());
+                }
+            }
+        }
+        names
+    }
+
+    pub fn servers_with_tag(&self, tag: &str) -> Vec<&ServerDetails> {
+        self.servers.iter()
+                    .filter(|server| server.tags.iter().any(|item| item == tag))
+                    .collect()
+    }
+
+    pub fn servers_in_group(&self, group: &str) -> Vec<&ServerDetails> {
+        let listed = self.groups.iter()
+                                .find(|item| item.name == group)
+                                .map(|item| item.servers.as_slice())
+                                .unwrap_or_default();
+
+        self.servers.iter()
+                    .filter(|server| listed.contains(&server.name) || server.groups.iter().any(|item| item == group))
+                    .collect()
+    }
+
+    /// Grupos do servidor, incluindo os da seção `groups:` que o listam.
+    pub fn groups_of(&self, server: &str) -> Vec<String> {
+        self.group_names()
+            .into_iter()
+            .filter(|group| self.servers_in_group(group).iter().any(|item| item.name == server))
+            .collect()
+    }
+
+    /// Rótulos das senhas `prompt` necessárias para conectar em `servers`, sem repetições.
+    pub fn prompt_labels(&self, servers: &[String]) -> Vec<String> {
+        let mut labels: Vec<String> = vec![];
+
+        for (_, connect, _) in servers.iter().filter_map(|server| self.get_info_server(server)) {
+            for label in connect.prompt_labels() {
+                if !labels.contains(&label) {
+                    labels.push(label);
+                }
+            }
+        }
+        labels
+    }
+
+    /// Servidores que não pertencem a nenhum grupo.
+    pub fn ungrouped_servers(&self) -> Vec<&ServerDetails> {
+        self.servers.iter()
+                    .filter(|server| server.groups.is_empty() && !self.groups.iter().any(|group| group.servers.contains(&server.name)))
+                    .collect()
+    }
+
+}
+
+/// Arquivos de configuração a carregar: os informados na linha de comando, senão os de
+/// `SERVER_MANAGER_CONFIG`, senão o primeiro existente entre `./config.yaml` e
+/// `$XDG_CONFIG_HOME/server_manager/config.yaml` (`~/.config` quando a variável não existe).
+pub fn resolve_config_paths(cli_paths: &[PathBuf]) -> Result<Vec<PathBuf>,String> {
+    if !cli_paths.is_empty() {
+        return Ok(cli_paths.to_vec());
+    }
+
+    if let Some(value) = env::var_os(CONFIG_ENV) {
+        let paths: Vec<PathBuf> = env::split_paths(&value)
+                                      .filter(|path| !path.as_os_str().is_empty())
+                                      .collect();
+        if !paths.is_empty() {
+            return Ok(paths);
+        }
+    }
+
+    let search_path = config_search_path();
+
+    match search_path.iter().find(|path| path.exists()) {
+        Some(path) => Ok(vec![path.clone()]),
+        None => Err(format!(
+            "Arquivo de configuração não encontrado. Procurado em: {}",
+            search_path.iter().map(|path| path.display().to_string()).collect::<Vec<_>>().join(", ")
+            ))
+    }
+}
+
+pub fn config_search_path() -> Vec<PathBuf> {
+    let mut paths = vec![PathBuf::from("config.yaml")];
+
+    let config_home = env::var_os("XDG_CONFIG_HOME")
+                          .filter(|value| !value.is_empty())
+                          .map(PathBuf::from)
+                          .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")));
+
+    if let Some(config_home) = config_home {
+        paths.push(config_home.join("server_manager").join("config.yaml"));
+    }
+    paths
+}
+
+/// `.yaml`/`.yml`, ou as mesmas extensões seguidas de `.enc`.
+fn is_config_file(file: &Path) -> bool {
+    let name = file.file_name().and_then(|name| name.to_str()).unwrap_or_default();
+    let name = name.strip_suffix(&format!(".{}", VAULT_EXTENSION)).unwrap_or(name);
+
+    name.ends_with(".yaml") || name.ends_with(".yml")
+}
+
+fn expand_config_paths(paths: &[PathBuf]) -> Result<Vec<PathBuf>,serde_yaml_ng::Error> {
+    let mut files = vec![];
+
+    for path in paths {
+        if path.is_dir() {
+            let entries = std::fs::read_dir(path)
+                .map_err(|e| serde_yaml_ng::Error::custom(format!("Erro ao ler o diretório {}: {}", path.display(), e)))?;
+
+            let mut yaml_files: Vec<PathBuf> = entries.filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                                                      .filter(|file| is_config_file(file))
+                                                      .collect();
+            yaml_files.sort();
+            files.extend(yaml_files);
+        } else {
+            files.push(path.clone());
+        }
+    }
+    Ok(files)
+}
+
+impl ServerGroup {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn description(&self) -> &Option<String> {
+        &self.description
+    }
+
+    pub fn servers(&self) -> &Vec<String> {
+        &self.servers
+    }
+}
+
+impl ServerDetails {
+    pub fn tags(&self) -> &Vec<String> {
+        &self.tags
+    }
+
+    pub fn groups(&self) -> &Vec<String> {
+        &self.groups
+    }
+
+    pub fn config(&self) -> &ServerConfig {
+        &self.config
+    }
+
+    pub fn connect(&self) -> &ServerConnect {
+        &self.connect
+    }
+
+    pub fn uses(&self) -> &Vec<CommandReference> {
+        &self.uses
+    }
+
+    pub fn forwards(&self) -> &Vec<PortForward> {
+        &self.forwards
+    }
+
+    /// Comandos do servidor: primeiro os próprios, depois os de `uses` resolvidos no `catalog`
+    /// (um comando próprio com o mesmo nome prevalece sobre o do catálogo). `become`/`run_as` do
+    /// servidor são aplicados onde o comando não declara os seus. Referências a comandos que
+    /// não existem no catálogo são ignoradas aqui e apontadas por `validate`.
+    pub fn effective_commands(&self, catalog: &[ServerCommands]) -> Vec<ServerCommands> {
+        let mut commands = self.commands.clone();
+
+        for command in self.uses.iter().filter_map(|reference| reference.resolve(catalog)) {
+            if !commands.iter().any(|item| item.name == command.name) {
+                commands.push(command);
+            }
+        }
+
+        for command in commands.iter_mut() {
+            command.sudo = command.sudo.or(self.sudo);
+            command.run_as = command.run_as.clone().or_else(|| self.run_as.clone());
+        }
+        commands
+    }
+}
+
+impl CommandReference {
+    pub fn command(&self) -> &str {
+        &self.command
+    }
+
+    pub fn name(&self) -> &str {
+        self.name.as_deref().unwrap_or(&self.command)
+    }
+
+    pub fn vars(&self) -> &HashMap<String,String> {
+        &self.vars
+    }
+
+    /// Cópia do comando do catálogo com o nome e os valores desta referência. Os valores viram o
+    /// `default` das variáveis, então ainda podem ser alterados no formulário ou com `--var`.
+    pub fn resolve(&self, catalog: &[ServerCommands]) -> Option<ServerCommands> {
+        let mut command = catalog.iter().find(|item| item.name == self.command)?.clone();
+        command.name = self.name().to_string();
+
+        let mut overrides: Vec<(&String,&String)> = self.vars.iter().collect();
+        overrides.sort();
+
+        for (name, value) in overrides {
+            match command.vars.iter_mut().find(|var| var.name == *name) {
+                Some(var) => var.default = Some(value.clone()),
+                None => command.vars.push(CommandVariable {
+                    name: name.clone(),
+                    default: Some(value.clone()),
+                    description: None,
+                })
+            }
+        }
+        Some(command)
+    }
 }
 
 impl ServerConfig {
@@ -99,6 +623,42 @@ impl ServerConfig {
     pub fn disk(&self) -> &str {
         &self.disk
     }
+
+    /// `memory` em bytes, quando escrito como tamanho (`"32GB"`).
+    pub fn memory_bytes(&self) -> Option<u64> {
+        parse_size(&self.memory)
+    }
+
+    /// `disk` em bytes, quando escrito como tamanho (`"400GB"`, `"16TB"`).
+    pub fn disk_bytes(&self) -> Option<u64> {
+        parse_size(&self.disk)
+    }
+}
+
+/// Converte tamanhos como `"32GB"`, `"1.5 TiB"` ou `"512M"` em bytes. As unidades são
+/// binárias (1 GB = 1024 MB), como o `free` e o `df -h` costumam mostrar.
+pub fn parse_size(text: &str) -> Option<u64> {
+    let text = text.trim();
+    let split = text.find(|c: char| !(c.is_ascii_digit() || c == '.' || c == ',')).unwrap_or(text.len());
+    let (number, unit) = text.split_at(split);
+    let number: f64 = number.replace(',', ".").parse().ok()?;
+
+    let unit = unit.trim().to_ascii_uppercase();
+    let prefix = unit.strip_suffix("IB").filter(|prefix| !prefix.is_empty())
+                     .or_else(|| unit.strip_suffix('B'))
+                     .unwrap_or(&unit);
+
+    let exponent = match prefix {
+        "" => 0,
+        "K" => 1,
+        "M" => 2,
+        "G" => 3,
+        "T" => 4,
+        "P" => 5,
+        _ => return None
+    };
+
+    Some((number * 1024f64.powi(exponent)) as u64)
 }
 
 impl ServerCommands {
@@ -110,13 +670,273 @@ impl ServerCommands {
         &mut self.name
     }
 
-    pub fn commands(&self) -> &Vec<String> {
+    /// Linhas de shell das etapas, na ordem declarada em `exec`. As transferências de arquivo
+    /// ficam de fora: só `SSH::execute_steps` as executa.
+    pub fn commands(&self) -> Vec<String> {
+        self.exec.iter()
+                 .filter(|step| step.transfer().is_none())
+                 .map(|step| step.run().to_string())
+                 .collect()
+    }
+
+    pub fn steps(&self) -> &Vec<CommandStep> {
         &self.exec
     }
 
-    pub fn commands_mut(&mut self) -> &mut Vec<String> {
+    pub fn steps_mut(&mut self) -> &mut Vec<CommandStep> {
         &mut self.exec
     }
+
+    /// Usuário com o qual o comando deve rodar via `sudo`, ou `None` para o usuário da conexão.
+    /// `run_as` implica `become`, a não ser que `become: false` seja declarado explicitamente.
+    pub fn become_user(&self) -> Option<&str> {
+        match (self.sudo, &self.run_as) {
+            (Some(false), _) => None,
+            (_, Some(user)) => Some(user),
+            (Some(true), None) => Some("root"),
+            (None, None) => None
+        }
+    }
+
+    pub fn vars(&self) -> &Vec<CommandVariable> {
+        &self.vars
+    }
+
+    pub fn variable(&self, name: &str) -> Option<&CommandVariable> {
+        self.vars.iter().find(|var| var.name == name)
+    }
+
+    /// Nomes dos placeholders `{nome}` encontrados nas linhas de `exec` (e nos caminhos das
+    /// transferências), na ordem em que aparecem.
+    pub fn placeholders(&self) -> Vec<String> {
+        let mut names: Vec<String> = vec![];
+
+        for step in &self.exec {
+            for text in step.texts() {
+                for (_, _, name) in find_placeholders(text) {
+                    if !names.iter().any(|item| item == name) {
+                        names.push(name.to_string());
+                    }
+                }
+            }
+        }
+        names
+    }
+
+    /// Substitui os placeholders pelos valores informados (ou pelo `default` declarado em `vars`).
+    /// Nas linhas de shell cada valor é escapado com `shell_quote`; nos caminhos das
+    /// transferências, que não passam pelo shell, é inserido como está.
+    pub fn fill_placeholders(&self, values: &HashMap<String,String>) -> Result<ServerCommands,String> {
+        let mut filled = self.clone();
+
+        for step in filled.exec.iter_mut() {
+            let quote = step.transfer().is_none();
+
+            for line in step.texts_mut() {
+                let mut result = String::new();
+                let mut last = 0;
+
+                for (start, end, name) in find_placeholders(line) {
+                    let value = match values.get(name) {
+                        Some(value) => value.clone(),
+                        None => match self.variable(name).and_then(|var| var.default.clone()) {
+                            Some(default) => default,
+                            None => return Err(format!("Valor não informado para o placeholder {{{}}}",name))
+                        }
+                    };
+
+                    result.push_str(&line[last..start]);
+                    result.push_str(&if quote { shell_quote(&value) } else { value });
+                    last = end;
+                }
+
+                result.push_str(&line[last..]);
+                *line = result;
+            }
+        }
+
+        Ok(filled)
+    }
+}
+
+impl CommandStep {
+    /// Linha de shell da etapa; vazia nas transferências de arquivo.
+    pub fn run(&self) -> &str {
+        match self {
+            CommandStep::Shell(run) => run,
+            CommandStep::Detailed { run, .. } => run,
+            CommandStep::Upload { .. } | CommandStep::Download { .. } => ""
+        }
+    }
+
+    /// Texto exibido na lista de etapas: a linha de shell ou a descrição da transferência.
+    pub fn label(&self) -> String {
+        match self.transfer() {
+            Some((TransferDirection::Upload, transfer)) => format!("upload {} → {}", transfer.from, transfer.to),
+            Some((TransferDirection::Download, transfer)) => format!("download {} → {}", transfer.from, transfer.to),
+            None => self.run().to_string()
+        }
+    }
+
+    pub fn transfer(&self) -> Option<(TransferDirection,&FileTransfer)> {
+        match self {
+            CommandStep::Upload { upload, .. } => Some((TransferDirection::Upload, upload)),
+            CommandStep::Download { download, .. } => Some((TransferDirection::Download, download)),
+            _ => None
+        }
+    }
+
+    /// Quando verdadeiro, uma falha nesta etapa não interrompe as etapas seguintes.
+    pub fn continue_on_error(&self) -> bool {
+        match self {
+            CommandStep::Shell(_) => false,
+            CommandStep::Detailed { continue_on_error, .. }
+                | CommandStep::Upload { continue_on_error, .. }
+                | CommandStep::Download { continue_on_error, .. } => *continue_on_error
+        }
+    }
+
+    /// Textos onde podem aparecer placeholders.
+    fn texts(&self) -> Vec<&str> {
+        match self {
+            CommandStep::Shell(run) | CommandStep::Detailed { run, .. } => vec![run],
+            CommandStep::Upload { upload: transfer, .. }
+                | CommandStep::Download { download: transfer, .. } => vec![&transfer.from, &transfer.to]
+        }
+    }
+
+    fn texts_mut(&mut self) -> Vec<&mut String> {
+        match self {
+            CommandStep::Shell(run) | CommandStep::Detailed { run, .. } => vec![run],
+            CommandStep::Upload { upload: transfer, .. }
+                | CommandStep::Download { download: transfer, .. } => vec![&mut transfer.from, &mut transfer.to]
+        }
+    }
+}
+
+impl PortForward {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn direction(&self) -> ForwardDirection {
+        self.direction
+    }
+
+    pub fn bind_address(&self) -> &str {
+        match (&self.bind_address, self.direction) {
+            (Some(address), _) => address,
+            (None, ForwardDirection::Local) => "127.0.0.1",
+            (None, ForwardDirection::Remote) => "localhost",
+        }
+    }
+
+    pub fn bind_port(&self) -> u16 {
+        self.bind_port
+    }
+
+    pub fn host(&self) -> &str {
+        &self.host
+    }
+
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    /// Resumo no formato do `ssh`: `-L 127.0.0.1:15432:db.interno:5432`.
+    pub fn label(&self) -> String {
+        let flag = match self.direction {
+            ForwardDirection::Local => "-L",
+            ForwardDirection::Remote => "-R",
+        };
+        format!("{} {}:{}:{}:{}", flag, self.bind_address(), self.bind_port, self.host, self.port)
+    }
+}
+
+impl FileTransfer {
+    pub fn new(from: &str, to: &str) -> Self {
+        FileTransfer {
+            from: from.to_string(),
+            to: to.to_string(),
+            mode: None,
+        }
+    }
+
+    pub fn from(&self) -> &str {
+        &self.from
+    }
+
+    pub fn to(&self) -> &str {
+        &self.to
+    }
+
+    /// Permissões declaradas em `mode`, já convertidas do octal.
+    pub fn mode(&self) -> Result<Option<i32>,String> {
+        match self.mode.as_deref().map(str::trim) {
+            None => Ok(None),
+            Some(mode) => i32::from_str_radix(mode.trim_start_matches("0o"), 8)
+                              .ok()
+                              .filter(|mode| (0..=0o7777).contains(mode))
+                              .map(Some)
+                              .ok_or_else(|| format!("mode \"{}\" não é uma permissão em octal como \"0644\"", mode))
+        }
+    }
+}
+
+impl From<&str> for CommandStep {
+    fn from(run: &str) -> Self {
+        CommandStep::Shell(run.to_string())
+    }
+}
+
+impl CommandVariable {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn default(&self) -> &Option<String> {
+        &self.default
+    }
+
+    pub fn description(&self) -> &Option<String> {
+        &self.description
+    }
+}
+
+/// Localiza os placeholders `{nome}` de uma linha, retornando `(inicio, fim, nome)`.
+/// Expansões do shell como `${HOME}` e chaves com outros caracteres (`{a,b}`, `{print $1}`) são ignoradas.
+fn find_placeholders(line: &str) -> Vec<(usize,usize,&str)> {
+    let bytes = line.as_bytes();
+    let mut found = vec![];
+    let mut index = 0;
+
+    while index < bytes.len() {
+        if bytes[index] == b'{' && (index == 0 || bytes[index - 1] != b'$') {
+            let name_len = bytes[index + 1..].iter()
+                                             .take_while(|byte| byte.is_ascii_alphanumeric() || **byte == b'_')
+                                             .count();
+            let close = index + 1 + name_len;
+
+            if name_len > 0 && close < bytes.len() && bytes[close] == b'}' {
+                found.push((index, close + 1, &line[index + 1..close]));
+                index = close + 1;
+                continue;
+            }
+        }
+        index += 1;
+    }
+    found
+}
+
+/// Escapa um valor para ser usado como uma única palavra no shell remoto.
+pub fn shell_quote(value: &str) -> String {
+    let is_safe = !value.is_empty() && value.chars().all(|c| c.is_ascii_alphanumeric() || "_-./:@%+=,".contains(c));
+
+    if is_safe {
+        value.to_string()
+    } else {
+        format!("'{}'", value.replace('\'', "'\\''"))
+    }
 }
 
 impl ServerConnect {
@@ -136,14 +956,37 @@ impl ServerConnect {
         &mut self.ip_address
     }
 
-    pub fn password(&self) -> &Option<String> {
+    pub fn password(&self) -> &Option<Secret> {
         &self.password
     }
 
-    pub fn password_mut(&mut self) -> &mut Option<String> {
+    pub fn password_mut(&mut self) -> &mut Option<Secret> {
         &mut self.password
     }
 
+    /// Pergunta usada quando `password` é um `prompt` sem rótulo próprio.
+    pub fn password_label(&self) -> String {
+        match self.type_connection {
+            ConnectionType::SSH => format!("Senha de {}@{}", self.user, self.host()),
+            ConnectionType::SSH_KEY => format!("Senha da chave {}", self.location.as_deref().unwrap_or_default()),
+        }
+    }
+
+    /// Rótulos dos segredos que precisam ser digitados para conectar, incluindo os dos bastiões.
+    pub fn prompt_labels(&self) -> Vec<String> {
+        let mut labels = match self.jump() {
+            Some(Ok(bastion)) => bastion.prompt_labels(),
+            _ => vec![]
+        };
+
+        if let Some(label) = self.password.as_ref().and_then(|password| password.prompt_label(&self.password_label())) {
+            if !labels.contains(&label) {
+                labels.push(label);
+            }
+        }
+        labels
+    }
+
     pub fn location(&self) -> &Option<String> {
         &self.location
     }
@@ -152,13 +995,120 @@ impl ServerConnect {
         &mut self.location
     }
 
+    pub fn hostname(&self) -> &Option<String> {
+        &self.hostname
+    }
+
+    /// Host usado na conexão: `hostname` quando informado, senão `ip_address` sem a porta.
+    pub fn host(&self) -> String {
+        match self.hostname.as_deref().map(str::trim).filter(|host| !host.is_empty()) {
+            Some(hostname) => split_host_port(hostname).0,
+            None => split_host_port(self.ip_address.trim()).0
+        }
+    }
+
+    /// Porta explícita, senão a escrita junto do endereço (`ip:porta`), senão 22.
+    pub fn port(&self) -> u16 {
+        let address = self.hostname.as_deref().filter(|host| !host.trim().is_empty()).unwrap_or(&self.ip_address);
+
+        self.port
+            .or_else(|| split_host_port(address.trim()).1)
+            .unwrap_or(DEFAULT_SSH_PORT)
+    }
+
+    pub fn connect_timeout(&self) -> Duration {
+        self.connect_timeout.map(Duration::from_secs).unwrap_or(DEFAULT_CONNECT_TIMEOUT)
+    }
+
+    pub fn command_timeout(&self) -> Option<Duration> {
+        self.command_timeout.map(Duration::from_secs)
+    }
+
+    pub fn keepalive_interval(&self) -> Option<u32> {
+        self.keepalive_interval.filter(|interval| *interval > 0)
+    }
+
+    pub fn host_key_fingerprint(&self) -> Option<&str> {
+        self.host_key_fingerprint.as_deref().map(str::trim).filter(|fingerprint| !fingerprint.is_empty())
+    }
+
+    pub fn host_key_check(&self) -> HostKeyPolicy {
+        self.host_key_check.unwrap_or_default()
+    }
+
+    pub fn jump_host(&self) -> Option<&str> {
+        self.jump_host.as_deref().map(str::trim).filter(|name| !name.is_empty())
+    }
+
+    /// Conexão do bastião quando há `jump_host`; o erro explica por que a cadeia não pôde ser resolvida.
+    pub fn jump(&self) -> Option<Result<&ServerConnect,&str>> {
+        self.jump_host()?;
+
+        Some(match &self.jump {
+            Some(Ok(jump)) => Ok(jump),
+            Some(Err(e)) => Err(e),
+            None => Err("jump_host não foi resolvido a partir da configuração")
+        })
+    }
+
+    /// `known_hosts` configurado, senão `~/.ssh/known_hosts`; `None` quando não há diretório home.
+    pub fn known_hosts(&self) -> Option<PathBuf> {
+        match self.known_hosts.as_deref().map(str::trim).filter(|path| !path.is_empty()) {
+            Some(path) => Some(PathBuf::from(path)),
+            None => env::var_os("HOME").map(|home| Path::new(&home).join(".ssh").join("known_hosts"))
+        }
+    }
+
+}
+
+/// Bastiões de `server`, do que dá acesso direto ao servidor até o primeiro da cadeia.
+fn jump_chain<'a>(servers: &'a [ServerDetails], server: &ServerDetails) -> Result<Vec<&'a ServerDetails>,String> {
+    let mut chain: Vec<&ServerDetails> = vec![];
+    let mut current = server.connect.jump_host();
+
+    while let Some(name) = current {
+        let bastion = servers.iter()
+                             .rev()
+                             .find(|item| item.name == name)
+                             .ok_or_else(|| format!("jump_host \"{}\" não está definido em servers", name))?;
+
+        if bastion.name == server.name || chain.iter().any(|item| item.name == bastion.name) {
+            let path: Vec<&str> = std::iter::once(server.name.as_str())
+                                      .chain(chain.iter().map(|item| item.name.as_str()))
+                                      .chain(std::iter::once(bastion.name.as_str()))
+                                      .collect();
+            return Err(format!("jump_host em ciclo: {}", path.join(" -> ")));
+        }
+
+        chain.push(bastion);
+        current = bastion.connect.jump_host();
+    }
+
+    Ok(chain)
+}
+
+/// Separa `host:porta`, `[ipv6]:porta` ou `[ipv6]`. Um IPv6 sem colchetes é devolvido inteiro.
+fn split_host_port(address: &str) -> (String, Option<u16>) {
+    if let Some(rest) = address.strip_prefix('[') {
+        if let Some((host, after)) = rest.split_once(']') {
+            return (host.to_string(), after.strip_prefix(':').and_then(|port| port.parse().ok()));
+        }
+    }
+
+    match address.split_once(':') {
+        Some((host, port)) if !port.contains(':') => match port.parse() {
+            Ok(port) => (host.to_string(), Some(port)),
+            Err(_) => (address.to_string(), None)
+        },
+        _ => (address.to_string(), None)
+    }
 }
 
 #[test]
 fn test_parsing_yaml_file() {
     let path = "config.yaml";
 
-    let config = ConfigYaml::new(&path);
+    let config = ConfigYaml::new(path);
 
     match config {
         Ok(config) => {
@@ -180,7 +1130,7 @@ fn test_parsing_yaml_file() {
 fn test_quantity_servers() {
     let path = "config.yaml";
 
-    let config = ConfigYaml::new(&path);
+    let config = ConfigYaml::new(path);
 
     match config {
         Ok(config) => {
@@ -195,7 +1145,7 @@ fn test_quantity_servers() {
 fn test_info_server() {
     let path = "config.yaml";
 
-    let config = ConfigYaml::new(&path).unwrap();
+    let config = ConfigYaml::new(path).unwrap();
 
     let expected_config = ServerConfig{
         os: String::from("Ubuntu"),
@@ -208,36 +1158,43 @@ fn test_info_server() {
         user: String::from(""),
         ip_address: String::from("123456"),
         location: None,
-        password: Some(String::from(""))
+        password: Some(Secret::new("")),
+        ..ServerConnect::default()
     };
 
     let expected_commands = vec![
       ServerCommands{
         name: String::from("Atualizar Servidor"),
         exec: vec![
-            String::from("mkdir {nome_pasta}"),
-            String::from("git clone {url}"),
-            String::from("touch {nome_arquivo}")
-        ]
+            CommandStep::from("mkdir {nome_pasta}"),
+            CommandStep::from("git clone {url}"),
+            CommandStep::from("touch {nome_arquivo}")
+        ],
+        vars: vec![],
+        sudo: None,
+        run_as: None
       },
       ServerCommands{
         name: String::from("Criar cliente"),
         exec: vec![
-            String::from("git clone {url}"),
-            String::from("cd {nome_pasta}"),
-            String::from("composer install"),
-            String::from("chmod 777 -R {nome_pasta}"),
-            String::from("php index.php migrate"),
-            String::from("chown -R gitlab-runner:gitlab-runner ."),
-            String::from("git checkout .")
-        ]
+            CommandStep::from("git clone {url}"),
+            CommandStep::from("cd {nome_pasta}"),
+            CommandStep::from("composer install"),
+            CommandStep::from("chmod 777 -R {nome_pasta}"),
+            CommandStep::from("php index.php migrate"),
+            CommandStep::from("chown -R gitlab-runner:gitlab-runner ."),
+            CommandStep::from("git checkout .")
+        ],
+        vars: vec![],
+        sudo: None,
+        run_as: None
       }
     ];
 
     let configs = config.get_info_server("Servidor 1");
     let expected_tuple = Some((expected_config,expected_connect,expected_commands));
 
-    if let Some((config,connect,commands)) = &configs {
+    if let Some((_config,_connect,commands)) = &configs {
 
         let command = &commands[0];
 
@@ -253,7 +1210,7 @@ fn test_info_server() {
 fn test_info_server_configs() {
     let path = "config.yaml";
 
-    let config = ConfigYaml::new(&path).unwrap();
+    let config = ConfigYaml::new(path).unwrap();
 
     let result = config.get_info_server("Servidor 2").unwrap();
 
@@ -266,5 +1223,262 @@ fn test_info_server_configs() {
     assert_eq!(result.1.location, Some(String::from("")));
     assert_eq!(result.1.password, None);
 
+    let steps = result.2[0].steps();
+
+    assert_eq!(steps.len(),2);
+    assert_eq!(steps[0].run(),"apachectl configtest");
+    assert!(steps[0].continue_on_error());
+    assert!(!steps[1].continue_on_error());
+
+    assert_eq!(result.2[0].become_user(),Some("root"));
+    assert_eq!(result.2[1].become_user(),Some("deploy"));
+
+    let shared = &result.2[2];
+    assert_eq!(shared.name(),"Limpar cache");
+    assert_eq!(shared.variable("cache_dir").and_then(|var| var.default().clone()),Some(String::from("/var/cache/httpd")));
+    assert_eq!(shared.become_user(),Some("root"));
+    assert_eq!(config.commands()[0].variable("cache_dir").and_then(|var| var.default().clone()),Some(String::from("/tmp/cache")));
+
+
+}
+
+#[test]
+fn test_connect_host_and_port() {
+    let mut connect = ServerConnect {
+        ip_address: String::from("10.0.0.5:2222"),
+        ..ServerConnect::default()
+    };
+    assert_eq!((connect.host(), connect.port()), (String::from("10.0.0.5"), 2222));
+
+    connect.port = Some(22022);
+    assert_eq!(connect.port(), 22022);
+
+    connect.hostname = Some(String::from("app.example.com"));
+    connect.port = None;
+    assert_eq!((connect.host(), connect.port()), (String::from("app.example.com"), 22));
+
+    connect.hostname = Some(String::from("[2001:db8::1]:2200"));
+    assert_eq!((connect.host(), connect.port()), (String::from("2001:db8::1"), 2200));
+
+    assert_eq!(split_host_port("2001:db8::1"), (String::from("2001:db8::1"), None));
+    assert_eq!(connect.connect_timeout(), DEFAULT_CONNECT_TIMEOUT);
+    assert_eq!(connect.command_timeout(), None);
+}
+
+#[test]
+fn test_placeholders_commands() {
+    let path = "config.yaml";
+
+    let config = ConfigYaml::new(path).unwrap();
+
+    let (_,_,commands) = config.get_info_server("Servidor 1").unwrap();
+
+    assert_eq!(commands[0].placeholders(),vec!["nome_pasta","url","nome_arquivo"]);
+    assert_eq!(commands[1].placeholders(),vec!["url","nome_pasta"]);
+
+    let shell_expansion = ServerCommands{
+        name: String::from("Shell"),
+        exec: vec![CommandStep::from("echo ${HOME} {a,b} && awk '{print $1}' {arquivo}")],
+        vars: vec![],
+        sudo: None,
+        run_as: None
+    };
+
+    assert_eq!(shell_expansion.placeholders(),vec!["arquivo"]);
+}
+
+#[test]
+fn test_fill_placeholders() {
+    let command = ServerCommands{
+        name: String::from("Atualizar Servidor"),
+        exec: vec![
+            CommandStep::from("mkdir {nome_pasta}"),
+            CommandStep::from("git clone {url} {nome_pasta}")
+        ],
+        vars: vec![
+            CommandVariable{
+                name: String::from("nome_pasta"),
+                default: Some(String::from("projeto")),
+                description: None
+            }
+        ],
+        sudo: None,
+        run_as: None
+    };
+
+    let mut values = HashMap::new();
+    values.insert(String::from("url"),String::from("https://exemplo.com/repo.git"));
+
+    let filled = command.fill_placeholders(&values).unwrap();
+
+    assert_eq!(filled.commands(),vec![
+        String::from("mkdir projeto"),
+        String::from("git clone https://exemplo.com/repo.git projeto")
+    ]);
+
+    values.insert(String::from("nome_pasta"),String::from("a b'; rm -rf /"));
+
+    let filled = command.fill_placeholders(&values).unwrap();
+
+    assert_eq!(filled.commands()[0],"mkdir 'a b'\\''; rm -rf /'");
+
+    assert!(command.fill_placeholders(&HashMap::new()).is_err());
+}
+
+#[test]
+fn test_servers_by_tag_and_group() {
+    let config = ConfigYaml::new("config.yaml").unwrap();
+
+    let names = |servers: Vec<&ServerDetails>| servers.iter().map(|server| server.name.clone()).collect::<Vec<_>>();
+
+    assert_eq!(names(config.servers_with_tag("web")), vec!["Servidor 1", "Servidor 2"]);
+    assert_eq!(names(config.servers_with_tag("production")), vec!["Servidor 2"]);
+    assert_eq!(names(config.servers_in_group("Produção")), vec!["Servidor 2"]);
+    assert_eq!(names(config.servers_in_group("Apache")), vec!["Servidor 2"]);
+    assert_eq!(names(config.ungrouped_servers()), vec!["Servidor 1"]);
+    assert_eq!(config.group_names(), vec!["Produção", "Apache"]);
+    assert_eq!(config.groups_of("Servidor 2"), vec!["Produção", "Apache"]);
+}
+
+#[test]
+fn test_load_merges_config_files() {
+    let team_file = env::temp_dir().join(format!("server_manager_team_{}.yaml", std::process::id()));
+
+    std::fs::write(&team_file, r#"
+version: "2.0.0"
+application: "Time B"
+servers:
+  - name: "Servidor 2"
+    config: { os: "Debian", memory: "8GB", disk: "100GB" }
+    connect: { type_connection: SSH, user: "deploy", ip_address: "10.0.0.2:22" }
+    commands: []
+  - name: "Servidor 3"
+    config: { os: "Alpine", memory: "2GB", disk: "20GB" }
+    connect: { type_connection: SSH, user: "deploy", ip_address: "10.0.0.3:22" }
+    commands: []
+"#).unwrap();
+
+    let config = ConfigYaml::load(&[PathBuf::from("config.yaml"), team_file.clone()], None);
+    std::fs::remove_file(&team_file).unwrap();
+
+    let config = config.unwrap();
+
+    assert_eq!(config.version,"1.0.0");
+    assert_eq!(config.get_quantity_servers(),3);
+    assert_eq!(config.get_info_server("Servidor 2").unwrap().0.os(),"Debian");
+    assert!(config.get_info_server("Servidor 3").is_some());
+
+    assert!(ConfigYaml::load(&[], None).is_err());
+    let paths = vec![team_file];
+    assert_eq!(resolve_config_paths(&paths).unwrap(),paths);
+}
 
-}
\ No newline at end of file
+#[test]
+fn test_jump_host_chain() {
+    let config: ConfigYaml = serde_yaml_ng::from_str(r#"
+version: "1.0.0"
+application: "Teste"
+servers:
+  - name: "Borda"
+    config: { os: "Debian", memory: "1GB", disk: "10GB" }
+    connect: { type_connection: SSH, user: "ops", hostname: "borda.example.com" }
+  - name: "Bastião"
+    config: { os: "Debian", memory: "1GB", disk: "10GB" }
+    connect: { type_connection: SSH, user: "ops", ip_address: "10.0.0.2", jump_host: "Borda" }
+  - name: "App"
+    config: { os: "Ubuntu", memory: "4GB", disk: "40GB" }
+    connect: { type_connection: SSH, user: "deploy", ip_address: "10.0.1.5", jump_host: "Bastião" }
+  - name: "Ciclo"
+    config: { os: "Ubuntu", memory: "4GB", disk: "40GB" }
+    connect: { type_connection: SSH, user: "deploy", ip_address: "10.0.1.6", jump_host: "Ciclo" }
+"#).unwrap();
+
+    let (_, connect, _) = config.get_info_server("App").unwrap();
+    let bastion = connect.jump().unwrap().unwrap();
+    assert_eq!(bastion.host(), "10.0.0.2");
+    assert_eq!(bastion.jump().unwrap().unwrap().host(), "borda.example.com");
+    assert!(bastion.jump().unwrap().unwrap().jump().is_none());
+
+    let (_, connect, _) = config.get_info_server("Ciclo").unwrap();
+    assert_eq!(connect.jump().unwrap().unwrap_err(), "jump_host em ciclo: Ciclo -> Ciclo");
+}
+
+#[test]
+fn test_transfer_steps() {
+    let command: ServerCommands = serde_yaml_ng::from_str(r#"
+name: "Publicar configuração"
+exec:
+  - upload: { from: "./{arquivo}", to: "/etc/app/{arquivo}", mode: "0640" }
+  - "systemctl reload app"
+  - download: { from: "/var/log/app.log", to: "./logs/app.log" }
+    continue_on_error: true
+"#).unwrap();
+
+    let (direction, upload) = command.steps()[0].transfer().unwrap();
+    assert_eq!(direction, TransferDirection::Upload);
+    assert_eq!(upload.mode(), Ok(Some(0o640)));
+    assert_eq!(command.steps()[2].transfer().map(|(direction, _)| direction), Some(TransferDirection::Download));
+    assert!(command.steps()[2].continue_on_error());
+
+    assert_eq!(command.placeholders(), vec!["arquivo"]);
+    assert_eq!(command.commands(), vec!["systemctl reload app"]);
+
+    let values = HashMap::from([(String::from("arquivo"), String::from("app config.yaml"))]);
+    let filled = command.fill_placeholders(&values).unwrap();
+    assert_eq!(filled.steps()[0].label(), "upload ./app config.yaml → /etc/app/app config.yaml");
+
+    let invalid = FileTransfer { mode: Some(String::from("rw-r--r--")), ..Default::default() };
+    assert!(invalid.mode().is_err());
+}
+
+#[test]
+fn test_port_forwards() {
+    let forwards: Vec<PortForward> = serde_yaml_ng::from_str(r#"
+- name: "Postgres"
+  bind_port: 15432
+  host: "db.interno"
+  port: 5432
+- name: "Webhook"
+  direction: remote
+  bind_address: "0.0.0.0"
+  bind_port: 8080
+  host: "localhost"
+  port: 3000
+"#).unwrap();
+
+    assert_eq!(forwards[0].direction(), ForwardDirection::Local);
+    assert_eq!(forwards[0].label(), "-L 127.0.0.1:15432:db.interno:5432");
+    assert_eq!(forwards[1].label(), "-R 0.0.0.0:8080:localhost:3000");
+
+    let diagnostics = validate_str(Path::new("teste.yaml"), r#"version: "1.0.0"
+application: "Teste"
+servers:
+  - name: "Web"
+    config: { os: "Ubuntu", memory: "4GB", disk: "40GB" }
+    connect: { type_connection: SSH, user: "deploy", ip_address: "10.0.0.1:22" }
+    forwards:
+      - { name: "Postgres", bind_port: 15432, host: "db", port: 0 }
+      - { name: "Postgres", direction: remote, bind_port: 80, host: "localhost", port: 3000 }
+"#);
+    let messages: Vec<&str> = diagnostics.iter().map(|item| item.message.as_str()).collect();
+    assert_eq!(messages, vec![
+        "Túnel \"Postgres\" do servidor \"Web\" com port 0",
+        "Túnel \"Postgres\" declarado mais de uma vez no servidor \"Web\"",
+        "Túnel \"Postgres\": o servidor só abre portas abaixo de 1024 para o root",
+    ]);
+}
+
+#[test]
+fn test_parse_size() {
+    assert_eq!(parse_size("512"), Some(512));
+    assert_eq!(parse_size("512B"), Some(512));
+    assert_eq!(parse_size("4k"), Some(4 * 1024));
+    assert_eq!(parse_size("512M"), Some(512 * 1024 * 1024));
+    assert_eq!(parse_size(" 32GB "), Some(32 * 1024 * 1024 * 1024));
+    assert_eq!(parse_size("1.5 TiB"), Some(3 * 512 * 1024 * 1024 * 1024));
+    assert_eq!(parse_size("0,5G"), Some(512 * 1024 * 1024));
+
+    for garbage in ["", "GB", "muito", "1.2.3G", "10 XB", "10 iB", "10 GBB", "-1G"] {
+        assert_eq!(parse_size(garbage), None, "{:?}", garbage);
+    }
+}
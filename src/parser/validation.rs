@@ -0,0 +1,596 @@
+use std::{fmt, path::{Path, PathBuf}};
+
+use crate::secrets::SecretString;
+
+use super::{expand_config_paths, jump_chain, read_config_file, ConfigYaml, ConnectionType, ForwardDirection, ServerCommands, TransferDirection};
+
+#[derive(Debug,PartialEq, Eq,Clone,Copy)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+/// Problema encontrado em um arquivo de configuração, com linha e coluna (a partir de 1).
+#[derive(Debug,PartialEq, Eq,Clone)]
+pub struct Diagnostic {
+    pub file: PathBuf,
+    pub line: usize,
+    pub column: usize,
+    pub severity: Severity,
+    pub message: String,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Error => "erro",
+            Severity::Warning => "aviso",
+        };
+
+        write!(f, "{}:{}:{}: {}: {}", self.file.display(), self.line, self.column, severity, self.message)
+    }
+}
+
+pub fn has_errors(diagnostics: &[Diagnostic]) -> bool {
+    diagnostics.iter().any(|diagnostic| diagnostic.severity == Severity::Error)
+}
+
+/// Valida todos os arquivos (diretórios são expandidos como em `ConfigYaml::load`); os cifrados
+/// são decifrados com `passphrase`, e as linhas reportadas são as do conteúdo decifrado.
+pub fn validate_files(paths: &[PathBuf], passphrase: Option<&SecretString>) -> Vec<Diagnostic> {
+    match expand_config_paths(paths) {
+        Ok(files) => {
+            let shared = files.iter()
+                              .filter_map(|file| ConfigYaml::open(file, passphrase).ok())
+                              .reduce(|mut merged, config| {
+                                  merged.merge(config);
+                                  merged
+                              });
+
+            files.iter()
+                 .flat_map(|file| match read_config_file(file, passphrase) {
+                     Ok(content) => validate_with_shared(file, &content, shared.as_ref()),
+                     Err(message) => vec![Diagnostic {
+                         file: file.clone(),
+                         line: 1,
+                         column: 1,
+                         severity: Severity::Error,
+                         message,
+                     }]
+                 })
+                 .collect()
+        },
+        Err(e) => vec![Diagnostic {
+            file: paths.first().cloned().unwrap_or_default(),
+            line: 1,
+            column: 1,
+            severity: Severity::Error,
+            message: e.to_string(),
+        }]
+    }
+}
+
+pub fn validate_file(path: &Path) -> Vec<Diagnostic> {
+    match std::fs::read_to_string(path) {
+        Ok(content) => validate_str(path, &content),
+        Err(e) => vec![Diagnostic {
+            file: path.to_path_buf(),
+            line: 1,
+            column: 1,
+            severity: Severity::Error,
+            message: format!("Erro ao ler o arquivo: {}", e),
+        }]
+    }
+}
+
+/// Valida o conteúdo de um arquivo. Erros de sintaxe ou de estrutura interrompem a validação;
+/// os problemas de conteúdo são reportados todos de uma vez.
+pub fn validate_str(file: &Path, content: &str) -> Vec<Diagnostic> {
+    validate_with_shared(file, content, None)
+}
+
+/// Como `validate_str`, mas as referências em `uses` e `jump_host` também podem apontar para
+/// `shared` (a mesclagem de todos os arquivos carregados juntos).
+fn validate_with_shared(file: &Path, content: &str, shared: Option<&ConfigYaml>) -> Vec<Diagnostic> {
+    let mut report = Report { file, diagnostics: vec![] };
+
+    let config: ConfigYaml = match serde_yaml_ng::from_str(content) {
+        Ok(config) => config,
+        Err(e) => {
+            let (line, column) = e.location()
+                                  .map(|location| (location.line(), location.column()))
+                                  .unwrap_or((1, 1));
+            report.push((line - 1, column - 1), Severity::Error, e.to_string());
+            return report.diagnostics;
+        }
+    };
+
+    let catalog: Vec<&ServerCommands> = config.commands.iter()
+                                              .chain(shared.into_iter().flat_map(|shared| shared.commands.iter()))
+                                              .collect();
+    let all_servers = shared.map(|shared| &shared.servers[..]).unwrap_or(&config.servers);
+    let source = Source::new(content);
+    let servers_key = source.find_key(0, source.lines.len(), 0, "servers");
+    let (server_nodes, _) = match servers_key {
+        Some((line, _)) => source.list_items(line, source.lines.len()),
+        None => (vec![], 0)
+    };
+
+    if config.servers.is_empty() {
+        report.push(servers_key.unwrap_or((0, 0)), Severity::Warning, String::from("Nenhum servidor configurado"));
+    }
+
+    for (i, server) in config.servers.iter().enumerate() {
+        let node = server_nodes.get(i).copied().unwrap_or(Node { line: 0, column: 0, end: source.lines.len() });
+        let name_position = source.find_key(node.line, node.end, node.column, "name").unwrap_or(node.position());
+
+        if let Some(first) = config.servers[..i].iter().position(|item| item.name == server.name) {
+            let first_line = server_nodes.get(first).map(|node| node.line + 1).unwrap_or(1);
+            report.push(name_position, Severity::Error, format!(
+                "Servidor \"{}\" duplicado (primeira definição na linha {})", server.name, first_line
+                ));
+        }
+
+        let connect = source.child(&node, "connect");
+        let connect_position = connect.map(|child| (child.line, node.column)).unwrap_or(name_position);
+
+        if server.connect.host().is_empty() {
+            let position = connect.and_then(|child| source.find_key(child.line, child.end, child.column, "ip_address"))
+                                  .unwrap_or(connect_position);
+            report.push(position, Severity::Error, format!("Servidor \"{}\" sem ip_address ou hostname", server.name));
+        }
+
+        if server.connect.type_connection == ConnectionType::SSH_KEY
+            && server.connect.location.as_deref().map(str::trim).unwrap_or("").is_empty() {
+            let position = connect.and_then(|child| source.find_key(child.line, child.end, child.column, "location"))
+                                  .unwrap_or(connect_position);
+            report.push(position, Severity::Error, format!(
+                "Servidor \"{}\" usa SSH_KEY mas não informa o caminho da chave em location", server.name
+                ));
+        }
+
+        if let Some(password) = server.connect.password() {
+            let position = connect.and_then(|child| source.find_key(child.line, child.end, child.column, "password"))
+                                  .unwrap_or(connect_position);
+
+            if let Err(message) = password.check() {
+                report.push(position, Severity::Error, format!("Servidor \"{}\": password inválido, {}", server.name, message));
+            } else if password.is_plaintext() {
+                report.push(position, Severity::Warning, format!(
+                    "Servidor \"{}\" guarda a senha em texto puro; use env:, file:, cmd: ou prompt", server.name
+                    ));
+            } else if let Some(reason) = password.ambiguous_reason() {
+                report.push(position, Severity::Warning, format!(
+                    "Servidor \"{}\": password usa {}; se for a própria senha, escreva plain: antes dela", server.name, reason
+                    ));
+            }
+        }
+
+        if let Some(fingerprint) = server.connect.host_key_fingerprint() {
+            if !is_sha256_fingerprint(fingerprint) {
+                let position = connect.and_then(|child| source.find_key(child.line, child.end, child.column, "host_key_fingerprint"))
+                                      .unwrap_or(connect_position);
+                report.push(position, Severity::Error, format!(
+                    "Servidor \"{}\" informa host_key_fingerprint fora do formato SHA256:<base64>", server.name
+                    ));
+            }
+        }
+
+        if server.connect.jump_host().is_some() {
+            if let Err(message) = jump_chain(all_servers, server) {
+                let position = connect.and_then(|child| source.find_key(child.line, child.end, child.column, "jump_host"))
+                                      .unwrap_or(connect_position);
+                report.push(position, Severity::Error, format!("Servidor \"{}\": {}", server.name, message));
+            }
+        }
+
+        let forward_nodes = match source.find_key(node.line, node.end, node.column, "forwards") {
+            Some((line, _)) => source.list_items(line, node.end).0,
+            None => vec![]
+        };
+
+        for (j, forward) in server.forwards.iter().enumerate() {
+            let forward_node = forward_nodes.get(j).copied().unwrap_or(node);
+            let position = |key: &str| source.find_key(forward_node.line, forward_node.end, forward_node.column, key)
+                                             .unwrap_or(forward_node.position());
+
+            if forward.name().trim().is_empty() {
+                report.push(position("name"), Severity::Error, format!("Túnel sem name no servidor \"{}\"", server.name));
+            } else if server.forwards[..j].iter().any(|item| item.name() == forward.name()) {
+                report.push(position("name"), Severity::Error, format!(
+                    "Túnel \"{}\" declarado mais de uma vez no servidor \"{}\"", forward.name(), server.name
+                    ));
+            }
+
+            if forward.host().trim().is_empty() {
+                report.push(position("host"), Severity::Error, format!("Túnel \"{}\" do servidor \"{}\" sem host", forward.name(), server.name));
+            }
+
+            for (key, port) in [("bind_port", forward.bind_port()), ("port", forward.port())] {
+                if port == 0 {
+                    report.push(position(key), Severity::Error, format!(
+                        "Túnel \"{}\" do servidor \"{}\" com {} 0", forward.name(), server.name, key
+                        ));
+                }
+            }
+
+            if forward.direction() == ForwardDirection::Remote && (1..1024).contains(&forward.bind_port()) && server.connect.user() != "root" {
+                report.push(position("bind_port"), Severity::Warning, format!(
+                    "Túnel \"{}\": o servidor só abre portas abaixo de 1024 para o root", forward.name()
+                    ));
+            }
+        }
+
+        let owner = format!("no servidor \"{}\"", server.name);
+        check_commands(&mut report, &source, &node, &server.commands, &owner);
+
+        let use_nodes = match source.find_key(node.line, node.end, node.column, "uses") {
+            Some((line, _)) => source.list_items(line, node.end).0,
+            None => vec![]
+        };
+
+        for (j, reference) in server.uses.iter().enumerate() {
+            let use_node = use_nodes.get(j).copied().unwrap_or(node);
+            let command_position = source.find_key(use_node.line, use_node.end, use_node.column, "command")
+                                         .unwrap_or(use_node.position());
+
+            if server.uses[..j].iter().any(|item| item.name() == reference.name()) {
+                report.push(command_position, Severity::Error, format!(
+                    "Comando \"{}\" usado mais de uma vez no servidor \"{}\"", reference.name(), server.name
+                    ));
+            }
+
+            let Some(command) = catalog.iter().find(|item| item.name == reference.command()) else {
+                report.push(command_position, Severity::Error, format!(
+                    "Servidor \"{}\" usa o comando \"{}\", que não existe no catálogo commands", server.name, reference.command()
+                    ));
+                continue;
+            };
+
+            let placeholders = command.placeholders();
+            let mut names: Vec<&String> = reference.vars().keys().collect();
+            names.sort();
+
+            for name in names {
+                if !placeholders.contains(name) {
+                    let position = source.find_text(use_node.line, use_node.end, name).unwrap_or(command_position);
+                    report.push(position, Severity::Warning, format!(
+                        "Variável \"{}\" não é usada pelo comando \"{}\" do catálogo", name, reference.command()
+                        ));
+                }
+            }
+        }
+    }
+
+    let root = Node { line: 0, column: 0, end: source.lines.len() };
+    check_commands(&mut report, &source, &root, &config.commands, "no catálogo commands");
+
+    let group_nodes = match source.find_key(0, source.lines.len(), 0, "groups") {
+        Some((line, _)) => source.list_items(line, source.lines.len()).0,
+        None => vec![]
+    };
+
+    for (i, group) in config.groups.iter().enumerate() {
+        let node = group_nodes.get(i).copied().unwrap_or(Node { line: 0, column: 0, end: source.lines.len() });
+
+        for member in group.servers() {
+            if !all_servers.iter().any(|server| server.name == *member) {
+                let position = source.find_text(node.line, node.end, member).unwrap_or(node.position());
+                report.push(position, Severity::Warning, format!(
+                    "Grupo \"{}\" cita o servidor \"{}\", que não existe nos arquivos validados", group.name(), member
+                    ));
+            }
+        }
+    }
+
+    report.diagnostics
+}
+
+/// Verifica os comandos listados em `commands:` dentro de `parent`.
+fn check_commands(report: &mut Report, source: &Source, parent: &Node, commands: &[ServerCommands], owner: &str) {
+    let command_nodes = match source.find_key(parent.line, parent.end, parent.column, "commands") {
+        Some((line, _)) => source.list_items(line, parent.end).0,
+        None => vec![]
+    };
+
+    for (j, command) in commands.iter().enumerate() {
+        let command_node = command_nodes.get(j).copied().unwrap_or(*parent);
+        let command_name = source.find_key(command_node.line, command_node.end, command_node.column, "name")
+                                 .unwrap_or(command_node.position());
+
+        if commands[..j].iter().any(|item| item.name == command.name) {
+            report.push(command_name, Severity::Error, format!("Comando \"{}\" duplicado {}", command.name, owner));
+        }
+
+        let exec_position = source.find_key(command_node.line, command_node.end, command_node.column, "exec")
+                                  .unwrap_or(command_name);
+
+        if command.exec.is_empty() {
+            report.push(exec_position, Severity::Error, format!("Comando \"{}\" sem etapas em exec", command.name));
+        }
+
+        for step in &command.exec {
+            let Some((direction, transfer)) = step.transfer() else { continue };
+            let key = match direction {
+                TransferDirection::Upload => "upload",
+                TransferDirection::Download => "download",
+            };
+            let position = source.find_text(command_node.line, command_node.end, &format!("{}:", key))
+                                 .unwrap_or(exec_position);
+
+            if transfer.from().trim().is_empty() || transfer.to().trim().is_empty() {
+                report.push(position, Severity::Error, format!("Comando \"{}\": {} exige from e to", command.name, key));
+            }
+
+            match (direction, transfer.mode()) {
+                (_, Err(message)) => report.push(position, Severity::Error, format!("Comando \"{}\": {}", command.name, message)),
+                (TransferDirection::Download, Ok(Some(_))) => report.push(position, Severity::Warning, format!(
+                    "Comando \"{}\": mode só é aplicado em upload", command.name
+                    )),
+                _ => {}
+            }
+
+            if command.become_user().is_some() {
+                report.push(position, Severity::Warning, format!(
+                    "Comando \"{}\": {} usa o usuário da conexão; become/run_as não se aplica a transferências", command.name, key
+                    ));
+            }
+        }
+
+        for placeholder in command.placeholders() {
+            if command.variable(&placeholder).is_none() {
+                let position = source.find_text(command_node.line, command_node.end, &format!("{{{}}}", placeholder))
+                                     .unwrap_or(exec_position);
+                report.push(position, Severity::Warning, format!(
+                    "Placeholder {{{}}} do comando \"{}\" não está declarado em vars", placeholder, command.name
+                    ));
+            }
+        }
+
+        let placeholders = command.placeholders();
+        for variable in &command.vars {
+            if !placeholders.contains(&variable.name) {
+                let position = source.find_text(command_node.line, command_node.end, &variable.name)
+                                     .unwrap_or(command_name);
+                report.push(position, Severity::Warning, format!(
+                    "Variável \"{}\" declarada em vars não é usada no comando \"{}\"", variable.name, command.name
+                    ));
+            }
+        }
+    }
+}
+
+struct Report<'a> {
+    file: &'a Path,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Report<'_> {
+    /// `position` é (linha, coluna) a partir de 0.
+    fn push(&mut self, position: (usize, usize), severity: Severity, message: String) {
+        self.diagnostics.push(Diagnostic {
+            file: self.file.to_path_buf(),
+            line: position.0 + 1,
+            column: position.1 + 1,
+            severity,
+            message,
+        });
+    }
+}
+
+/// Mapeamento em bloco: linha de início, coluna das suas chaves e linha final (exclusiva).
+#[derive(Debug,Clone,Copy)]
+struct Node {
+    line: usize,
+    column: usize,
+    end: usize,
+}
+
+impl Node {
+    fn position(&self) -> (usize, usize) {
+        (self.line, self.column)
+    }
+}
+
+/// Localiza chaves e itens de lista no texto YAML em estilo de bloco, que é o formato usado em
+/// `config.yaml`. Quando algo não é encontrado (ex.: estilo `{...}`), a posição do item é usada.
+struct Source<'a> {
+    lines: Vec<&'a str>,
+}
+
+impl<'a> Source<'a> {
+    fn new(content: &'a str) -> Self {
+        Self {
+            lines: content.lines().collect(),
+        }
+    }
+
+    fn is_blank(line: &str) -> bool {
+        let trimmed = line.trim();
+        trimmed.is_empty() || trimmed.starts_with('#')
+    }
+
+    fn indent(line: &str) -> usize {
+        line.len() - line.trim_start_matches(' ').len()
+    }
+
+    /// Coluna onde começa o conteúdo da linha, pulando o marcador `- ` de item de lista.
+    fn content_column(line: &str) -> usize {
+        let indent = Source::indent(line);
+        let rest = &line[indent..];
+
+        match rest.strip_prefix('-') {
+            Some(after) if after.is_empty() || after.starts_with(' ') => {
+                indent + 1 + (after.len() - after.trim_start_matches(' ').len())
+            },
+            _ => indent
+        }
+    }
+
+    fn find_key(&self, start: usize, end: usize, column: usize, key: &str) -> Option<(usize, usize)> {
+        let pattern = format!("{}:", key);
+
+        (start..end.min(self.lines.len())).find(|&l| {
+            let line = self.lines[l];
+            let content = Source::content_column(line);
+            content == column && line[content..].starts_with(&pattern)
+        }).map(|l| (l, column))
+    }
+
+    fn find_text(&self, start: usize, end: usize, text: &str) -> Option<(usize, usize)> {
+        (start..end.min(self.lines.len())).find_map(|l| self.lines[l].find(text).map(|column| (l, column)))
+    }
+
+    /// Mapeamento aninhado na chave `key` do nó informado.
+    fn child(&self, node: &Node, key: &str) -> Option<Node> {
+        let (line, column) = self.find_key(node.line, node.end, node.column, key)?;
+
+        let end = (line + 1..node.end).find(|&l| {
+            !Source::is_blank(self.lines[l]) && Source::indent(self.lines[l]) <= column
+        }).unwrap_or(node.end);
+
+        let child_column = (line + 1..end).find(|&l| !Source::is_blank(self.lines[l]))
+                                          .map(|l| Source::indent(self.lines[l]))
+                                          .unwrap_or(column);
+
+        Some(Node { line, column: child_column, end })
+    }
+
+    /// Itens da lista declarada na linha `key_line` e a linha onde a lista termina.
+    fn list_items(&self, key_line: usize, end: usize) -> (Vec<Node>, usize) {
+        let key_indent = Source::indent(self.lines[key_line]);
+        let mut items: Vec<Node> = vec![];
+        let mut item_indent: Option<usize> = None;
+        let mut list_end = end.min(self.lines.len());
+
+        for l in key_line + 1..list_end {
+            let line = self.lines[l];
+            if Source::is_blank(line) {
+                continue;
+            }
+
+            let indent = Source::indent(line);
+            let is_item = Source::content_column(line) != indent;
+
+            if indent < key_indent || (indent == key_indent && !is_item) {
+                list_end = l;
+                break;
+            }
+
+            if is_item && *item_indent.get_or_insert(indent) == indent {
+                items.push(Node { line: l, column: Source::content_column(line), end: list_end });
+            }
+        }
+
+        for i in 0..items.len() {
+            items[i].end = items.get(i + 1).map(|next| next.line).unwrap_or(list_end);
+        }
+
+        (items, list_end)
+    }
+}
+
+/// Mesma regra de `fingerprint_matches`: o prefixo `SHA256:` e o `=` final são opcionais, mas o
+/// resto precisa ser o base64 de 32 bytes que o `ssh-keygen -l` mostra.
+fn is_sha256_fingerprint(fingerprint: &str) -> bool {
+    let encoded = fingerprint.trim().trim_start_matches("SHA256:").trim_end_matches('=');
+    encoded.len() == 43 && encoded.chars().all(|c| c.is_ascii_alphanumeric() || c == '+' || c == '/')
+}
+
+#[test]
+fn test_host_key_fingerprint_format() {
+    let fingerprint = "SHA256:uNiVztksCsDhcc0u9e8BujQXVUpKZIDTMczCvj3tD2s";
+    assert!(is_sha256_fingerprint(fingerprint));
+    assert!(is_sha256_fingerprint(fingerprint.trim_start_matches("SHA256:")));
+    assert!(is_sha256_fingerprint(&format!("{}=", fingerprint)));
+    assert!(crate::connection::fingerprint_matches(fingerprint.trim_start_matches("SHA256:"), fingerprint));
+
+    assert!(!is_sha256_fingerprint("MD5:16:27:ac:a5:76:28:2d:36:63:1b:56:4d:eb:df:a6:48"));
+    assert!(!is_sha256_fingerprint("SHA256:curto"));
+}
+
+#[test]
+fn test_validate_reports_all_problems() {
+    let content = r#"version: "1.0.0"
+application: "Teste"
+servers:
+  - name: "Web"
+    config:
+      os: "Ubuntu"
+      memory: "4GB"
+      disk: "40GB"
+    connect:
+      type_connection: SSH_KEY
+      user: "deploy"
+      ip_address: ""
+    commands:
+      - name: "Deploy"
+        exec:
+          - "git clone {url}"
+      - name: "Deploy"
+        exec: []
+  - name: "Web"
+    config: { os: "Ubuntu", memory: "4GB", disk: "40GB" }
+    connect: { type_connection: SSH, user: "deploy", ip_address: "10.0.0.1:22" }
+    commands: []
+"#;
+
+    let diagnostics = validate_str(Path::new("teste.yaml"), content);
+
+    let summary: Vec<(usize, usize, Severity)> = diagnostics.iter()
+                                                            .map(|item| (item.line, item.column, item.severity))
+                                                            .collect();
+
+    assert_eq!(summary, vec![
+        (12, 7, Severity::Error),
+        (9, 5, Severity::Error),
+        (16, 24, Severity::Warning),
+        (17, 9, Severity::Error),
+        (18, 9, Severity::Error),
+        (19, 5, Severity::Error),
+    ]);
+    assert!(has_errors(&diagnostics));
+    assert_eq!(diagnostics[5].to_string(), "teste.yaml:19:5: erro: Servidor \"Web\" duplicado (primeira definição na linha 4)");
+
+    let shared = r#"version: "1.0.0"
+application: "Teste"
+commands:
+  - name: "Limpar cache"
+    exec: ["rm -rf {dir}/*"]
+    vars: [{ name: dir }]
+servers:
+  - name: "Web"
+    config: { os: "Ubuntu", memory: "4GB", disk: "40GB" }
+    connect: { type_connection: SSH, user: "deploy", ip_address: "10.0.0.1:22" }
+    uses:
+      - command: "Limpar cache"
+        vars: { dri: "/tmp" }
+      - command: "Reiniciar"
+"#;
+
+    let summary: Vec<(usize, usize, Severity)> = validate_str(Path::new("teste.yaml"), shared).iter()
+                                                                                           .map(|item| (item.line, item.column, item.severity))
+                                                                                           .collect();
+    assert_eq!(summary, vec![(13, 17, Severity::Warning), (14, 9, Severity::Error)]);
+
+    // Membros de grupo são procurados em todos os arquivos carregados juntos.
+    let groups = r#"version: "1.0.0"
+application: "Teste"
+groups:
+  - name: "Produção"
+    servers: ["Web", "Fantasma"]
+servers: []
+"#;
+    let merged: ConfigYaml = serde_yaml_ng::from_str(shared).unwrap();
+    let messages: Vec<String> = validate_with_shared(Path::new("grupos.yaml"), groups, Some(&merged)).iter()
+                                                                                                   .map(|item| item.message.clone())
+                                                                                                   .collect();
+    assert_eq!(messages, vec![
+        String::from("Nenhum servidor configurado"),
+        String::from("Grupo \"Produção\" cita o servidor \"Fantasma\", que não existe nos arquivos validados"),
+    ]);
+
+    let syntax = validate_str(Path::new("teste.yaml"), "version: \"1.0.0\"\nservers: [\n");
+    assert_eq!(syntax.len(), 1);
+    assert_eq!(syntax[0].severity, Severity::Error);
+}
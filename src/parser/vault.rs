@@ -0,0 +1,185 @@
+use std::{env, fmt, path::{Path, PathBuf}};
+
+use openssl::{base64, error::ErrorStack, hash::MessageDigest, pkcs5, rand, symm::{self, Cipher}};
+
+use crate::secrets::SecretString;
+
+use super::expand_config_paths;
+
+/// Variável de ambiente com a senha dos arquivos de configuração cifrados.
+pub const VAULT_PASSWORD_ENV: &str = "SERVER_MANAGER_VAULT_PASSWORD";
+
+/// Extensão acrescentada por `encrypt` (`config.yaml` vira `config.yaml.enc`).
+pub const VAULT_EXTENSION: &str = "enc";
+
+/// Primeira linha do arquivo cifrado: formato, versão, algoritmos e iterações do PBKDF2.
+const MAGIC: &str = "$SERVER_MANAGER_VAULT";
+const VERSION: &str = "1";
+const CIPHER: &str = "AES256-GCM";
+const KDF: &str = "PBKDF2-SHA256";
+const ITERATIONS: u32 = 600_000;
+/// Limite das iterações aceitas no cabeçalho: um arquivo alterado ou corrompido não pode fazer o
+/// PBKDF2 rodar por minutos antes de a verificação do conteúdo falhar.
+const MAX_ITERATIONS: u32 = ITERATIONS * 10;
+
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+const TAG_LEN: usize = 16;
+const LINE_WIDTH: usize = 76;
+
+#[derive(Debug)]
+pub enum VaultError {
+    /// Cabeçalho ou conteúdo fora do formato esperado.
+    Format(String),
+    /// Senha errada ou arquivo alterado: a verificação do GCM falhou.
+    Decrypt,
+    Crypto(ErrorStack),
+}
+
+impl fmt::Display for VaultError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VaultError::Format(message) => write!(f, "Arquivo cifrado inválido: {}", message),
+            VaultError::Decrypt => write!(f, "Não foi possivel decifrar: senha incorreta ou arquivo alterado"),
+            VaultError::Crypto(source) => write!(f, "Erro de criptografia: {}", source),
+        }
+    }
+}
+
+impl std::error::Error for VaultError {}
+
+impl From<ErrorStack> for VaultError {
+    fn from(error: ErrorStack) -> Self {
+        VaultError::Crypto(error)
+    }
+}
+
+pub fn is_vault(content: &str) -> bool {
+    content.starts_with(MAGIC)
+}
+
+/// Senha do cofre definida em `SERVER_MANAGER_VAULT_PASSWORD`.
+pub fn passphrase_from_env() -> Option<SecretString> {
+    env::var(VAULT_PASSWORD_ENV).ok()
+                                .filter(|value| !value.is_empty())
+                                .map(|value| SecretString::new(&value))
+}
+
+/// Indica se algum dos arquivos (diretórios expandidos como em `ConfigYaml::load`) está cifrado.
+pub fn contains_vault(paths: &[PathBuf]) -> bool {
+    expand_config_paths(paths).map(|files| files.iter().any(|file| {
+                                  std::fs::read_to_string(file).map(|content| is_vault(&content)).unwrap_or(false)
+                              }))
+                              .unwrap_or(false)
+}
+
+/// Conteúdo de um arquivo de configuração, decifrado com `passphrase` (ou a de
+/// `SERVER_MANAGER_VAULT_PASSWORD`) quando está no formato do cofre.
+pub fn read_config_file(path: &Path, passphrase: Option<&SecretString>) -> Result<String,String> {
+    let content = std::fs::read_to_string(path).map_err(|e| format!("Erro ao ler o arquivo: {}", e))?;
+
+    if !is_vault(&content) {
+        return Ok(content);
+    }
+
+    let from_env = passphrase_from_env();
+    let passphrase = passphrase.or(from_env.as_ref()).ok_or_else(|| format!(
+        "{} está cifrado; defina {} ou execute em um terminal para digitar a senha", path.display(), VAULT_PASSWORD_ENV
+        ))?;
+
+    decrypt(&content, passphrase).map_err(|e| e.to_string())
+}
+
+pub fn encrypt(plaintext: &str, passphrase: &SecretString) -> Result<String,VaultError> {
+    encrypt_with_iterations(plaintext, passphrase, ITERATIONS)
+}
+
+fn encrypt_with_iterations(plaintext: &str, passphrase: &SecretString, iterations: u32) -> Result<String,VaultError> {
+    let header = format!("{};{};{};{};{}", MAGIC, VERSION, CIPHER, KDF, iterations);
+
+    let mut salt = [0u8; SALT_LEN];
+    let mut nonce = [0u8; NONCE_LEN];
+    rand::rand_bytes(&mut salt)?;
+    rand::rand_bytes(&mut nonce)?;
+
+    let key = derive_key(passphrase, &salt, iterations)?;
+    let mut tag = [0u8; TAG_LEN];
+    let ciphertext = symm::encrypt_aead(Cipher::aes_256_gcm(), &key, Some(&nonce), header.as_bytes(), plaintext.as_bytes(), &mut tag)?;
+
+    let payload: Vec<u8> = salt.iter().chain(&nonce).chain(&tag).chain(&ciphertext).copied().collect();
+    let encoded = base64::encode_block(&payload);
+
+    let mut content = header;
+    content.push('\n');
+    for line in encoded.as_bytes().chunks(LINE_WIDTH) {
+        content.push_str(&String::from_utf8_lossy(line));
+        content.push('\n');
+    }
+    Ok(content)
+}
+
+pub fn decrypt(content: &str, passphrase: &SecretString) -> Result<String,VaultError> {
+    let (header, body) = content.split_once('\n').unwrap_or((content, ""));
+    let header = header.trim_end_matches('\r');
+
+    let fields: Vec<&str> = header.split(';').collect();
+    let iterations = match fields.as_slice() {
+        [MAGIC, VERSION, CIPHER, KDF, iterations] => iterations.parse::<u32>()
+                                                               .map_err(|_| VaultError::Format(format!("iterações inválidas: {}", iterations)))?,
+        [MAGIC, version, ..] => return Err(VaultError::Format(format!("versão {} não suportada", version))),
+        _ => return Err(VaultError::Format(String::from("cabeçalho não reconhecido")))
+    };
+
+    if iterations == 0 || iterations > MAX_ITERATIONS {
+        return Err(VaultError::Format(format!("iterações fora do limite (1 a {}): {}", MAX_ITERATIONS, iterations)));
+    }
+
+    let encoded: String = body.chars().filter(|c| !c.is_whitespace()).collect();
+    let payload = base64::decode_block(&encoded).map_err(|_| VaultError::Format(String::from("conteúdo não está em base64")))?;
+
+    if payload.len() < SALT_LEN + NONCE_LEN + TAG_LEN {
+        return Err(VaultError::Format(String::from("conteúdo truncado")));
+    }
+
+    let (salt, rest) = payload.split_at(SALT_LEN);
+    let (nonce, rest) = rest.split_at(NONCE_LEN);
+    let (tag, ciphertext) = rest.split_at(TAG_LEN);
+
+    let key = derive_key(passphrase, salt, iterations)?;
+    let plaintext = symm::decrypt_aead(Cipher::aes_256_gcm(), &key, Some(nonce), header.as_bytes(), ciphertext, tag)
+                        .map_err(|_| VaultError::Decrypt)?;
+
+    String::from_utf8(plaintext).map_err(|_| VaultError::Format(String::from("conteúdo decifrado não é UTF-8")))
+}
+
+fn derive_key(passphrase: &SecretString, salt: &[u8], iterations: u32) -> Result<[u8; 32],VaultError> {
+    let mut key = [0u8; 32];
+    pkcs5::pbkdf2_hmac(passphrase.expose().as_bytes(), salt, iterations as usize, MessageDigest::sha256(), &mut key)?;
+    Ok(key)
+}
+
+#[test]
+fn test_vault_round_trip() {
+    let plaintext = std::fs::read_to_string("config.yaml").unwrap();
+    let passphrase = SecretString::new("correta");
+
+    let content = encrypt_with_iterations(&plaintext, &passphrase, 1_000).unwrap();
+    assert!(is_vault(&content));
+    assert!(content.starts_with("$SERVER_MANAGER_VAULT;1;AES256-GCM;PBKDF2-SHA256;1000\n"));
+    assert!(content.lines().all(|line| line.len() <= LINE_WIDTH));
+    assert_eq!(decrypt(&content, &passphrase).unwrap(), plaintext);
+
+    assert!(matches!(decrypt(&content, &SecretString::new("errada")), Err(VaultError::Decrypt)));
+
+    let tampered = content.replacen(";1000\n", ";1001\n", 1);
+    assert!(matches!(decrypt(&tampered, &passphrase), Err(VaultError::Decrypt)));
+
+    let tampered = content.replacen(";1000\n", ";4000000000\n", 1);
+    assert!(matches!(decrypt(&tampered, &passphrase), Err(VaultError::Format(_))));
+
+    let path = env::temp_dir().join(format!("server_manager_vault_{}.yaml.enc", std::process::id()));
+    std::fs::write(&path, &content).unwrap();
+    let config = super::ConfigYaml::open(&path, Some(&passphrase));
+    let _ = std::fs::remove_file(&path);
+    assert_eq!(config.unwrap().get_quantity_servers(), 2);
+}
@@ -0,0 +1,305 @@
+use std::{collections::HashMap, env, fmt, fs, io::{self, Write}, path::PathBuf, process::{Command, Stdio}};
+
+use crossterm::{
+    event::{self, Event, KeyCode, KeyEventKind, KeyModifiers},
+    terminal::{disable_raw_mode, enable_raw_mode},
+};
+use serde::{Deserialize, Serialize};
+
+/// Referência a um segredo como escrita na configuração. O valor só é obtido na hora de conectar:
+///
+/// - `env:NOME`: variável de ambiente;
+/// - `file:caminho`: conteúdo do arquivo (`~/` é o diretório home), sem a quebra de linha final;
+/// - `cmd:comando`: primeira linha da saída do comando, executado com `sh -c`;
+/// - `prompt` ou `prompt:rótulo`: digitado pelo usuário, sem eco;
+/// - qualquer outro texto (ou `plain:texto`): o próprio valor, mantido por compatibilidade.
+#[derive(Clone,PartialEq, Eq,Serialize, Deserialize,Default)]
+#[serde(transparent)]
+pub struct Secret(String);
+
+/// De onde vem o valor de um `Secret`.
+#[derive(Debug,PartialEq, Eq,Clone,Copy)]
+pub enum SecretSource<'a> {
+    Plain(&'a str),
+    Env(&'a str),
+    File(&'a str),
+    Command(&'a str),
+    Prompt(Option<&'a str>),
+}
+
+impl Secret {
+    pub fn new(reference: &str) -> Self {
+        Secret(reference.to_string())
+    }
+
+    pub fn source(&self) -> SecretSource<'_> {
+        let reference = self.0.as_str();
+
+        if reference.trim() == "prompt" {
+            return SecretSource::Prompt(None);
+        }
+
+        match reference.split_once(':') {
+            Some(("env", name)) => SecretSource::Env(name.trim()),
+            Some(("file", path)) => SecretSource::File(path.trim()),
+            Some(("cmd", command)) => SecretSource::Command(command.trim()),
+            Some(("prompt", label)) => SecretSource::Prompt(Some(label.trim()).filter(|label| !label.is_empty())),
+            Some(("plain", value)) => SecretSource::Plain(value),
+            _ => SecretSource::Plain(reference)
+        }
+    }
+
+    /// Senha em texto puro não vazia, que deveria sair do arquivo.
+    pub fn is_plaintext(&self) -> bool {
+        matches!(self.source(), SecretSource::Plain(value) if !value.is_empty())
+    }
+
+    /// Problema na própria referência (nome, caminho ou comando vazio).
+    pub fn check(&self) -> Result<(),String> {
+        match self.source() {
+            SecretSource::Env("") => Err(String::from("env: sem o nome da variável")),
+            SecretSource::File("") => Err(String::from("file: sem o caminho do arquivo")),
+            SecretSource::Command("") => Err(String::from("cmd: sem o comando")),
+            _ => Ok(())
+        }
+    }
+
+    /// Rótulo exibido ao pedir o valor, quando o segredo é `prompt`; `default_label` vale para `prompt` sem rótulo.
+    pub fn prompt_label(&self, default_label: &str) -> Option<String> {
+        match self.source() {
+            SecretSource::Prompt(label) => Some(label.unwrap_or(default_label).to_string()),
+            _ => None
+        }
+    }
+
+    /// Referência que provavelmente é uma senha em texto puro começando por um dos prefixos:
+    /// variável com nome impossível, arquivo inexistente ou programa que não existe. O motivo não
+    /// inclui o valor, para poder ser exibido.
+    pub fn ambiguous_reason(&self) -> Option<&'static str> {
+        match self.source() {
+            SecretSource::Env(name) => {
+                let valid = name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+                            && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+                (!valid).then_some("env: com nome de variável inválido")
+            },
+            SecretSource::File(path) => (!expand_home(path).exists()).then_some("file: aponta para um arquivo que não existe"),
+            SecretSource::Command(command) => {
+                let program = command.split_whitespace().next().unwrap_or_default();
+                (!program_exists(program)).then_some("cmd: com um programa que não foi encontrado")
+            },
+            _ => None
+        }
+    }
+
+    pub fn resolve(&self, answers: &PromptAnswers, default_label: &str) -> Result<SecretString,SecretError> {
+        self.resolve_with(answers, default_label, |name| env::var(name).ok())
+    }
+
+    /// Como `resolve`, com a leitura das variáveis de ambiente injetada.
+    fn resolve_with(&self, answers: &PromptAnswers, default_label: &str, env_var: impl Fn(&str) -> Option<String>) -> Result<SecretString,SecretError> {
+        match self.source() {
+            SecretSource::Plain(value) => Ok(SecretString::new(value)),
+            SecretSource::Env(name) => env_var(name).map(SecretString)
+                                                    .ok_or_else(|| SecretError::MissingEnv(name.to_string())),
+            SecretSource::File(path) => {
+                let path = expand_home(path);
+                fs::read_to_string(&path)
+                    .map(|content| SecretString::new(content.trim_end_matches(['\n', '\r'])))
+                    .map_err(|source| SecretError::File { path, source })
+            },
+            SecretSource::Command(command) => run_secret_command(command),
+            SecretSource::Prompt(label) => {
+                let label = label.unwrap_or(default_label);
+                answers.get(label).cloned().ok_or_else(|| SecretError::PromptRequired(label.to_string()))
+            }
+        }
+    }
+}
+
+/// Mostra apenas a origem; valores em texto puro aparecem como `***`.
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.source() {
+            SecretSource::Plain(_) => write!(f, "Secret(***)"),
+            _ => write!(f, "Secret({})", self.0)
+        }
+    }
+}
+
+/// Valor de um segredo já resolvido. Não aparece em `Debug` nem em `Display`.
+#[derive(Clone,PartialEq, Eq)]
+pub struct SecretString(String);
+
+impl SecretString {
+    pub fn new(value: &str) -> Self {
+        SecretString(value.to_string())
+    }
+
+    /// Acesso explícito ao valor, para entregá-lo à autenticação.
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Debug for SecretString {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "SecretString(***)")
+    }
+}
+
+/// Valores digitados para os segredos `prompt`, pelo rótulo. Ficam só na memória do processo.
+#[derive(Debug,Clone,PartialEq, Eq,Default)]
+pub struct PromptAnswers(HashMap<String,SecretString>);
+
+impl PromptAnswers {
+    pub fn insert(&mut self, label: &str, value: SecretString) {
+        self.0.insert(label.to_string(), value);
+    }
+
+    pub fn get(&self, label: &str) -> Option<&SecretString> {
+        self.0.get(label)
+    }
+
+    pub fn contains(&self, label: &str) -> bool {
+        self.0.contains_key(label)
+    }
+
+    pub fn remove(&mut self, label: &str) {
+        self.0.remove(label);
+    }
+}
+
+#[derive(Debug)]
+pub enum SecretError {
+    MissingEnv(String),
+    File { path: PathBuf, source: io::Error },
+    Command { command: String, message: String },
+    /// O segredo precisa ser digitado; o rótulo identifica a pergunta.
+    PromptRequired(String),
+}
+
+impl fmt::Display for SecretError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SecretError::MissingEnv(name) => write!(f, "Variável de ambiente {} não definida", name),
+            SecretError::File { path, source } => write!(f, "Não foi possivel ler o segredo em {}: {}", path.display(), source),
+            SecretError::Command { command, message } => write!(f, "O comando \"{}\" não devolveu o segredo: {}", command, message),
+            SecretError::PromptRequired(label) => write!(f, "Informe: {}", label),
+        }
+    }
+}
+
+impl std::error::Error for SecretError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SecretError::File { source, .. } => Some(source),
+            _ => None
+        }
+    }
+}
+
+/// `~/` no início do caminho é o diretório home.
+pub(crate) fn expand_home(path: &str) -> PathBuf {
+    match (path.strip_prefix("~/"), env::var_os("HOME")) {
+        (Some(rest), Some(home)) => PathBuf::from(home).join(rest),
+        _ => PathBuf::from(path)
+    }
+}
+
+/// Programa encontrado pelo `sh`, como executável no `PATH`, caminho ou comando embutido.
+fn program_exists(program: &str) -> bool {
+    Command::new("sh").arg("-c")
+                      .arg("command -v \"$1\"")
+                      .arg("sh")
+                      .arg(program)
+                      .stdin(Stdio::null())
+                      .stdout(Stdio::null())
+                      .stderr(Stdio::null())
+                      .status()
+                      .is_ok_and(|status| status.success())
+}
+
+/// Executa o comando sem terminal (a TUI está em modo raw) e usa a primeira linha da saída.
+fn run_secret_command(command: &str) -> Result<SecretString,SecretError> {
+    let error = |message: String| SecretError::Command { command: command.to_string(), message };
+
+    let output = Command::new("sh").arg("-c")
+                                   .arg(command)
+                                   .stdin(Stdio::null())
+                                   .output()
+                                   .map_err(|e| error(e.to_string()))?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(error(format!("{} {}", output.status, stderr.trim())));
+    }
+
+    let stdout = String::from_utf8(output.stdout).map_err(|_| error(String::from("saída não é UTF-8")))?;
+    Ok(SecretString::new(stdout.lines().next().unwrap_or_default()))
+}
+
+/// Lê uma senha no terminal sem eco. Esc ou Ctrl+C cancelam.
+pub fn read_masked(label: &str) -> io::Result<String> {
+    eprint!("{}: ",label);
+    io::stderr().flush()?;
+
+    enable_raw_mode()?;
+    let mut value = String::new();
+    let result = loop {
+        match event::read() {
+            Ok(Event::Key(key)) if key.kind != KeyEventKind::Release => match key.code {
+                KeyCode::Enter => break Ok(()),
+                KeyCode::Esc => break Err(io::Error::new(io::ErrorKind::Interrupted, "cancelado")),
+                KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                    break Err(io::Error::new(io::ErrorKind::Interrupted, "cancelado"))
+                },
+                KeyCode::Backspace => { value.pop(); },
+                KeyCode::Char(c) => value.push(c),
+                _ => {}
+            },
+            Ok(_) => {},
+            Err(e) => break Err(e)
+        }
+    };
+    disable_raw_mode()?;
+    eprintln!();
+
+    result.map(|_| value)
+}
+
+#[test]
+fn test_secret_sources() {
+    let answers = {
+        let mut answers = PromptAnswers::default();
+        answers.insert("Senha LDAP", SecretString::new("digitada"));
+        answers
+    };
+
+    let env_var = |name: &str| (name == "SENHA_DEPLOY").then(|| String::from("do-ambiente"));
+    assert_eq!(Secret::new("env:SENHA_DEPLOY").resolve_with(&answers, "x", env_var).unwrap().expose(), "do-ambiente");
+    assert!(matches!(Secret::new("env:OUTRA").resolve_with(&answers, "x", env_var), Err(SecretError::MissingEnv(_))));
+
+    assert_eq!(Secret::new("cmd:printf 'linha1\\nlinha2'").resolve(&answers, "x").unwrap().expose(), "linha1");
+    assert!(matches!(Secret::new("cmd:exit 3").resolve(&answers, "x"), Err(SecretError::Command { .. })));
+
+    assert_eq!(Secret::new("prompt:Senha LDAP").resolve(&answers, "x").unwrap().expose(), "digitada");
+    assert_eq!(Secret::new("prompt").prompt_label("Senha de deploy@web"), Some(String::from("Senha de deploy@web")));
+    assert!(matches!(Secret::new("prompt").resolve(&answers, "Senha de deploy@web"), Err(SecretError::PromptRequired(_))));
+
+    assert_eq!(Secret::new("plain:env:literal").resolve(&answers, "x").unwrap().expose(), "env:literal");
+    assert_eq!(format!("{:?}", Secret::new("hunter2")), "Secret(***)");
+    assert_eq!(format!("{:?}", SecretString::new("hunter2")), "SecretString(***)");
+    assert_eq!(Secret::new("file:").check(), Err(String::from("file: sem o caminho do arquivo")));
+}
+
+#[test]
+fn test_ambiguous_secret_references() {
+    assert_eq!(Secret::new("env:SENHA_DEPLOY").ambiguous_reason(), None);
+    assert_eq!(Secret::new("file:Cargo.toml").ambiguous_reason(), None);
+    assert_eq!(Secret::new("cmd:printf segredo").ambiguous_reason(), None);
+
+    // Senhas literais que por acaso começam com um prefixo.
+    assert!(Secret::new("env:s3nh@!").ambiguous_reason().is_some());
+    assert!(Secret::new("file:minha-senha-2024").ambiguous_reason().is_some());
+    assert!(Secret::new("cmd:x9#Kq2").ambiguous_reason().is_some());
+}
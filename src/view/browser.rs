@@ -0,0 +1,160 @@
+use std::time::{Duration, Instant};
+
+use crate::{connection::RemoteEntry, parser::{FileTransfer, TransferDirection}};
+
+/// Intervalo entre as leituras do que foi acrescentado ao arquivo acompanhado com `t`.
+pub const TAIL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Operação do navegador de arquivos que precisa da thread de conexões.
+#[derive(Debug,PartialEq, Eq,Clone)]
+pub enum BrowserRequest {
+    List(String),
+    /// Lê o começo do arquivo ou, com `tail`, o final.
+    Preview { path: String, tail: bool },
+    /// Lê o que foi acrescentado ao arquivo acompanhado a partir de `offset`.
+    Follow { path: String, offset: u64 },
+    Transfer(TransferDirection,FileTransfer),
+}
+
+/// Estado do painel que navega pelo sistema de arquivos do servidor selecionado via SFTP.
+pub struct FileBrowser {
+    server: String,
+    path: String,
+    entries: Vec<RemoteEntry>,
+    selected: usize,
+    /// Arquivo exibido com `tail`, conferido a cada `TAIL_INTERVAL`, e até onde ele já foi exibido.
+    following: Option<String>,
+    offset: Option<u64>,
+    last_tail: Option<Instant>,
+    refresh: bool,
+}
+
+impl FileBrowser {
+    /// Abre no diretório inicial da sessão SFTP (normalmente o home do usuário).
+    pub fn new(server: &str) -> Self {
+        FileBrowser {
+            server: server.to_string(),
+            path: String::from("."),
+            entries: vec![],
+            selected: 0,
+            following: None,
+            offset: None,
+            last_tail: None,
+            refresh: true,
+        }
+    }
+
+    pub fn server(&self) -> &str {
+        &self.server
+    }
+
+    /// Diretório listado, já absoluto depois da primeira listagem.
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    pub fn entries(&self) -> &Vec<RemoteEntry> {
+        &self.entries
+    }
+
+    pub fn selected(&self) -> usize {
+        self.selected
+    }
+
+    pub fn selected_entry(&self) -> Option<&RemoteEntry> {
+        self.entries.get(self.selected)
+    }
+
+    pub fn set_listing(&mut self, path: String, entries: Vec<RemoteEntry>) {
+        self.path = path;
+        self.entries = entries;
+        self.selected = 0;
+    }
+
+    pub fn select_next(&mut self) {
+        if self.selected + 1 < self.entries.len() {
+            self.selected += 1;
+        }
+    }
+
+    pub fn select_previous(&mut self) {
+        self.selected = self.selected.saturating_sub(1);
+    }
+
+    /// Diretório acima do atual; o servidor resolve o `..`.
+    pub fn parent(&self) -> String {
+        format!("{}/..", self.path.trim_end_matches('/'))
+    }
+
+    pub fn following(&self) -> Option<&str> {
+        self.following.as_deref()
+    }
+
+    /// Passa a acompanhar o final de `path`; a primeira leitura sai no próximo `pending_request`.
+    pub fn follow(&mut self, path: &str) {
+        self.following = Some(path.to_string());
+        self.offset = None;
+        self.last_tail = None;
+    }
+
+    /// Registra até onde o arquivo acompanhado já foi exibido; as próximas leituras pedem só o resto.
+    pub fn set_offset(&mut self, path: &str, offset: u64) {
+        if self.following.as_deref() == Some(path) {
+            self.offset = Some(offset);
+        }
+    }
+
+    pub fn stop_following(&mut self) {
+        self.following = None;
+    }
+
+    /// Pede uma nova listagem do diretório atual no próximo `pending_request`.
+    pub fn request_refresh(&mut self) {
+        self.refresh = true;
+    }
+
+    /// Próxima operação automática: a listagem pendente ou a releitura do arquivo acompanhado.
+    /// Deve ser chamado só quando a thread de conexões estiver livre.
+    pub fn pending_request(&mut self) -> Option<BrowserRequest> {
+        if self.refresh {
+            self.refresh = false;
+            return Some(BrowserRequest::List(self.path.clone()));
+        }
+
+        let path = self.following.as_ref()?;
+        if self.last_tail.is_some_and(|last| last.elapsed() < TAIL_INTERVAL) {
+            return None;
+        }
+
+        self.last_tail = Some(Instant::now());
+        Some(match self.offset {
+            Some(offset) => BrowserRequest::Follow { path: path.clone(), offset },
+            None => BrowserRequest::Preview { path: path.clone(), tail: true }
+        })
+    }
+}
+
+#[test]
+fn test_file_browser_requests() {
+    let mut browser = FileBrowser::new("Servidor 1");
+
+    assert_eq!(browser.pending_request(), Some(BrowserRequest::List(String::from("."))));
+    assert_eq!(browser.pending_request(), None);
+
+    browser.set_listing(String::from("/var/log/"), vec![]);
+    browser.select_next();
+    assert_eq!(browser.selected(), 0);
+    assert_eq!(browser.parent(), "/var/log/..");
+
+    browser.follow("/var/log/syslog");
+    assert_eq!(browser.pending_request(), Some(BrowserRequest::Preview { path: String::from("/var/log/syslog"), tail: true }));
+    assert_eq!(browser.pending_request(), None);
+
+    browser.set_offset("/var/log/syslog", 4096);
+    browser.last_tail = None;
+    assert_eq!(browser.pending_request(), Some(BrowserRequest::Follow { path: String::from("/var/log/syslog"), offset: 4096 }));
+
+    browser.stop_following();
+    browser.request_refresh();
+    assert_eq!(browser.pending_request(), Some(BrowserRequest::List(String::from("/var/log/"))));
+}
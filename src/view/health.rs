@@ -0,0 +1,98 @@
+use tui::{
+    style::{Color, Modifier, Style},
+    text::{Span, Spans},
+};
+
+use crate::{connection::{ConfigField, HealthReport}, parser::ServerConfig};
+
+use super::format_size;
+
+fn mark(report: &HealthReport, config: &ServerConfig, field: ConfigField) -> (&'static str, Color) {
+    match report.matches(config, field) {
+        Some(true) => ("✔", Color::Green),
+        Some(false) => ("✖", Color::Red),
+        None => ("?", Color::DarkGray),
+    }
+}
+
+fn uptime(report: &HealthReport) -> String {
+    match report.uptime().map(|uptime| uptime.as_secs()) {
+        Some(seconds) => format!("{}d {}h {}min", seconds / 86400, seconds % 86400 / 3600, seconds % 3600 / 60),
+        None => String::from("-")
+    }
+}
+
+/// Linha do painel de informações: valores medidos ao lado dos declarados e as divergências.
+pub fn health_summary(report: &HealthReport, config: &ServerConfig) -> String {
+    let mismatches = report.mismatches(config);
+    let status = match mismatches.len() {
+        0 => String::from("configuração confere"),
+        count => format!("{} divergência(s) com a configuração", count)
+    };
+
+    format!(
+        "So: {} (declarado {}), Memória: {} (declarado {}), Disco: {} (declarado {}) - {}",
+        report.os().unwrap_or("-"),
+        config.os(),
+        report.memory_total().map(format_size).unwrap_or_else(|| String::from("-")),
+        config.memory(),
+        report.disk_total().map(format_size).unwrap_or_else(|| String::from("-")),
+        config.disk(),
+        status
+    )
+}
+
+/// Conteúdo do painel de saúde: campos declarados × medidos, carga e uso de cada montagem.
+pub(super) fn health_lines<'a>(report: &HealthReport, config: &ServerConfig) -> Vec<Spans<'a>> {
+    let label = |text: &str| Span::styled(format!("{:<9}", text), Style::default().fg(Color::Green).add_modifier(Modifier::BOLD));
+
+    let mut lines = vec![];
+    let compared = [
+        (ConfigField::Os, "SO", config.os().to_string(), report.os().unwrap_or("-").to_string()),
+        (ConfigField::Memory, "Memória", config.memory().to_string(), format!(
+            "{} ({} livre)",
+            report.memory_total().map(format_size).unwrap_or_else(|| String::from("-")),
+            report.memory_available().map(format_size).unwrap_or_else(|| String::from("-"))
+            )),
+        (ConfigField::Disk, "Disco", config.disk().to_string(), report.disk_total().map(format_size).unwrap_or_else(|| String::from("-"))),
+    ];
+
+    for (field, name, declared, actual) in compared {
+        let (symbol, color) = mark(report, config, field);
+        lines.push(Spans::from(vec![
+            label(name),
+            Span::styled(format!("{} ", symbol), Style::default().fg(color).add_modifier(Modifier::BOLD)),
+            Span::raw(actual),
+            Span::styled(format!("  (declarado {})", declared), Style::default().fg(Color::DarkGray)),
+        ]));
+    }
+
+    lines.push(Spans::from(vec![label("Kernel"), Span::raw(report.kernel().unwrap_or("-").to_string())]));
+    lines.push(Spans::from(vec![label("Uptime"), Span::raw(uptime(report))]));
+    lines.push(Spans::from(vec![
+        label("CPU"),
+        Span::raw(format!(
+            "{} núcleo(s), carga {}",
+            report.cpus().map(|cpus| cpus.to_string()).unwrap_or_else(|| String::from("-")),
+            report.load().map(|[one, five, fifteen]| format!("{:.2} {:.2} {:.2}", one, five, fifteen)).unwrap_or_else(|| String::from("-"))
+            ))
+    ]));
+
+    for mount in report.mounts() {
+        let percent = mount.used_percent();
+        let color = match percent {
+            90.. => Color::Red,
+            75..=89 => Color::Yellow,
+            _ => Color::White,
+        };
+        lines.push(Spans::from(vec![
+            label("Montagem"),
+            Span::styled(
+                format!("{} {} de {} ({}%)", mount.mount, format_size(mount.used), format_size(mount.size), percent),
+                Style::default().fg(color)
+                ),
+        ]));
+    }
+
+    lines
+}
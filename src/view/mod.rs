@@ -1,4 +1,4 @@
-use std::{fmt::Result, io::{self, Stdout}, sync::{Arc, Mutex}};
+use std::{collections::HashMap, io::Stdout};
 
 use crossterm::{event::EnableMouseCapture, execute, terminal::{enable_raw_mode, EnterAlternateScreen}};
 
@@ -10,13 +10,297 @@ use tui::
     }, style::
     {
         Color, Modifier, Style
-    }, terminal::CompletedFrame, text::{Span, Spans}, widgets::
+    }, text::{Span, Spans}, widgets::
     {
-        Block, Borders, List, ListItem, ListState, Paragraph
+        Block, Borders, List, ListItem, Paragraph
     }, Terminal
 };
 
-use crate::parser::{ServerCommands, ServerDetails};
+use crate::{connection::{HealthReport, HostKey, OutputStream}, parser::{ConfigYaml, ServerCommands, ServerConfig, ServerDetails}};
+
+mod browser;
+mod health;
+mod terminal;
+mod tunnels;
+pub use browser::{BrowserRequest, FileBrowser, TAIL_INTERVAL};
+pub use health::health_summary;
+pub use terminal::{key_bytes, suspend_tui};
+pub use tunnels::{TunnelPanel, TunnelRow, TunnelState};
+
+/// Cabeçalho usado para os servidores sem grupo quando o arquivo declara grupos.
+pub const UNGROUPED: &str = "Sem grupo";
+
+/// Campo do formulário de placeholders.
+pub struct InputField {
+    pub name: String,
+    pub description: Option<String>,
+    pub value: String,
+    /// Campo de senha: o valor aparece como `*`.
+    pub masked: bool,
+}
+
+/// Formulário exibido antes da execução para coletar os valores dos placeholders `{nome}` de um comando.
+pub struct InputForm {
+    pub title: String,
+    pub fields: Vec<InputField>,
+    pub active: usize,
+    /// Os campos são placeholders `{nome}` e aparecem entre chaves.
+    pub placeholders: bool,
+}
+
+impl InputForm {
+    pub fn from_command(server_commands: &ServerCommands) -> Self {
+        let fields = server_commands.placeholders()
+                                    .into_iter()
+                                    .map(|name| {
+                                        let variable = server_commands.variable(&name);
+                                        InputField {
+                                            description: variable.and_then(|var| var.description().clone()),
+                                            value: variable.and_then(|var| var.default().clone()).unwrap_or_default(),
+                                            name,
+                                            masked: false,
+                                        }
+                                    })
+                                    .collect();
+
+        Self {
+            title: format!("Parâmetros: {}",server_commands.name()),
+            fields,
+            active: 0,
+            placeholders: true,
+        }
+    }
+
+    /// Formulário para digitar as senhas `prompt`, um campo mascarado por rótulo.
+    pub fn from_prompts(labels: &[String]) -> Self {
+        Self {
+            title: String::from("Senhas"),
+            fields: labels.iter()
+                          .map(|label| InputField {
+                              name: label.clone(),
+                              description: None,
+                              value: String::new(),
+                              masked: true,
+                          })
+                          .collect(),
+            active: 0,
+            placeholders: false,
+        }
+    }
+
+    /// Formulário com campos de texto livres, já preenchidos com os valores iniciais.
+    pub fn from_fields(title: &str, fields: &[(&str,String)]) -> Self {
+        Self {
+            title: title.to_string(),
+            fields: fields.iter()
+                          .map(|(name, value)| InputField {
+                              name: name.to_string(),
+                              description: None,
+                              value: value.clone(),
+                              masked: false,
+                          })
+                          .collect(),
+            active: 0,
+            placeholders: false,
+        }
+    }
+
+    pub fn next_field(&mut self) {
+        if !self.fields.is_empty() {
+            self.active = (self.active + 1) % self.fields.len();
+        }
+    }
+
+    pub fn previous_field(&mut self) {
+        if !self.fields.is_empty() {
+            self.active = (self.active + self.fields.len() - 1) % self.fields.len();
+        }
+    }
+
+    pub fn push_char(&mut self, c: char) {
+        if let Some(field) = self.fields.get_mut(self.active) {
+            field.value.push(c);
+        }
+    }
+
+    pub fn pop_char(&mut self) {
+        if let Some(field) = self.fields.get_mut(self.active) {
+            field.value.pop();
+        }
+    }
+
+    pub fn values(&self) -> HashMap<String,String> {
+        self.fields.iter()
+                   .map(|field| (field.name.clone(), field.value.clone()))
+                   .collect()
+    }
+}
+
+/// Saída acumulada do comando em execução, exibida no painel rolável da TUI.
+#[derive(Default)]
+pub struct OutputPane {
+    lines: Vec<(OutputStream,String)>,
+    line_open: bool,
+    scroll: Option<usize>,
+}
+
+impl OutputPane {
+    pub fn clear(&mut self) {
+        self.lines.clear();
+        self.line_open = false;
+        self.scroll = None;
+    }
+
+    /// Acrescenta um trecho recebido do servidor, continuando a última linha se ela ainda não terminou.
+    pub fn push(&mut self, stream: OutputStream, text: &str) {
+        if text.is_empty() {
+            return;
+        }
+
+        for (i,line) in text.split('\n').enumerate() {
+            let open = i == 0 && self.line_open;
+            let continues = open && self.lines.last().map(|(last,_)| *last == stream).unwrap_or(false);
+
+            match self.lines.last_mut() {
+                Some((_,last)) if continues => last.push_str(line),
+                // A quebra vinda do outro fluxo só encerra a linha aberta, sem criar uma linha vazia.
+                _ if open && line.is_empty() => {},
+                _ => self.lines.push((stream, line.to_string()))
+            }
+        }
+
+        self.line_open = !text.ends_with('\n');
+        if !self.line_open {
+            self.lines.pop();
+        }
+    }
+
+    pub fn lines(&self) -> &Vec<(OutputStream,String)> {
+        &self.lines
+    }
+
+    pub fn scroll_up(&mut self, amount: usize, height: usize) {
+        let top = self.top_line(height);
+        self.scroll = Some(top.saturating_sub(amount));
+    }
+
+    pub fn scroll_down(&mut self, amount: usize, height: usize) {
+        let top = self.top_line(height) + amount;
+
+        if top >= self.lines.len().saturating_sub(height) {
+            self.scroll = None;
+        } else {
+            self.scroll = Some(top);
+        }
+    }
+
+    /// Primeira linha visível; sem rolagem manual o painel acompanha o final da saída.
+    pub fn top_line(&self, height: usize) -> usize {
+        match self.scroll {
+            Some(top) => top,
+            None => self.lines.len().saturating_sub(height)
+        }
+    }
+}
+
+/// Linha do menu lateral: cabeçalho de grupo ou servidor.
+#[derive(Debug,PartialEq, Eq,Clone)]
+pub enum SidebarRow {
+    Group { name: String, servers: Vec<String>, collapsed: bool },
+    Server { name: String },
+}
+
+impl SidebarRow {
+    /// Texto exibido no menu; servidores ficam recuados quando há grupos.
+    pub fn label(&self, grouped: bool, marked: &[String]) -> String {
+        match self {
+            SidebarRow::Group { name, servers, collapsed } => {
+                format!("{} {} ({})", if *collapsed { "▸" } else { "▾" }, name, servers.len())
+            },
+            SidebarRow::Server { name } => {
+                let mark = if marked.contains(name) { "[x] " } else { "" };
+                format!("{}{}{}", if grouped { "  " } else { "" }, mark, name)
+            }
+        }
+    }
+}
+
+/// Monta o menu lateral com os servidores sob seus grupos. Um servidor em vários grupos aparece
+/// em cada um; sem nenhum grupo no arquivo, a lista continua plana.
+pub fn sidebar_rows(config: &ConfigYaml, collapsed: &[String]) -> Vec<SidebarRow> {
+    let group_names = config.group_names();
+
+    if group_names.is_empty() {
+        return config.list_servers()
+                     .iter()
+                     .map(|server| SidebarRow::Server { name: server.name.clone() })
+                     .collect();
+    }
+
+    let mut groups: Vec<(String, Vec<String>)> = group_names.into_iter()
+                                                            .map(|group| {
+                                                                let servers = config.servers_in_group(&group)
+                                                                                    .iter()
+                                                                                    .map(|server| server.name.clone())
+                                                                                    .collect();
+                                                                (group, servers)
+                                                            })
+                                                            .collect();
+
+    let ungrouped: Vec<String> = config.ungrouped_servers().iter().map(|server| server.name.clone()).collect();
+    if !ungrouped.is_empty() {
+        groups.push((UNGROUPED.to_string(), ungrouped));
+    }
+
+    let mut rows = vec![];
+    for (name, servers) in groups {
+        let is_collapsed = collapsed.contains(&name);
+        if !is_collapsed {
+            rows.push(SidebarRow::Group { name, servers: servers.clone(), collapsed: false });
+            rows.extend(servers.into_iter().map(|name| SidebarRow::Server { name }));
+        } else {
+            rows.push(SidebarRow::Group { name, servers, collapsed: true });
+        }
+    }
+    rows
+}
+
+/// Situação de uma etapa de `exec` exibida na lista de etapas.
+#[derive(Debug,PartialEq, Eq,Clone,Copy)]
+pub enum StepStatus {
+    Pending,
+    Running,
+    Succeeded,
+    Failed,
+    Ignored,
+    Skipped,
+    Cancelled,
+}
+
+impl StepStatus {
+    fn symbol(&self) -> &'static str {
+        match self {
+            StepStatus::Pending => "·",
+            StepStatus::Running => "▶",
+            StepStatus::Succeeded => "✔",
+            StepStatus::Failed => "✖",
+            StepStatus::Ignored => "!",
+            StepStatus::Skipped => "-",
+            StepStatus::Cancelled => "■",
+        }
+    }
+
+    fn color(&self) -> Color {
+        match self {
+            StepStatus::Pending | StepStatus::Skipped => Color::DarkGray,
+            StepStatus::Running => Color::Yellow,
+            StepStatus::Succeeded => Color::Green,
+            StepStatus::Failed => Color::Red,
+            StepStatus::Ignored => Color::Magenta,
+            StepStatus::Cancelled => Color::LightRed,
+        }
+    }
+}
 
 pub trait ManagerItems<'a> {
     fn sidebar_items(server_details: Vec<ServerDetails>) -> Vec<ListItem<'a>>;
@@ -29,8 +313,16 @@ pub trait RenderComponent<'a> {
     fn main_component(list_commands: Vec<ListItem<'a>>) -> List<'a>;
     fn bottom_component() -> Paragraph<'a>;
     fn info_paragraph_component(input_info: & mut String) -> Paragraph<'a>;
+    fn input_form_component(input_form: &InputForm) -> Paragraph<'a>;
+    fn host_key_component(host_key: &HostKey) -> Paragraph<'a>;
+    fn output_component(output_pane: &OutputPane, height: usize) -> Paragraph<'a>;
+    fn steps_component(title: &'a str, steps: &[(String,StepStatus)]) -> List<'a>;
+    fn browser_component(browser: &FileBrowser, focused: bool) -> List<'a>;
+    fn tunnels_component(panel: &TunnelPanel, focused: bool) -> List<'a>;
+    fn health_component(report: &HealthReport, config: &ServerConfig) -> Paragraph<'a>;
 }
 
+#[allow(dead_code)]
 pub struct MainView<'a>{
     selected_index: &'a mut usize,
     input_info: &'a mut String,
@@ -46,23 +338,6 @@ impl<'a> MainView<'a> {
             focused_block,
         }
     }
-
-    pub async fn update_info_paragraph(output_message: Arc<Mutex<String>>,area: tui::layout::Rect) {
-        let mut terminal = Terminal::new(CrosstermBackend::new(std::io::stdout())).unwrap();
-        let mut out = output_message.lock().unwrap();
-
-        let info_paragraph = Paragraph::new(out.clone())
-        .block(
-            Block::default()
-                             .title("Saída das informações")
-                             .borders(Borders::ALL)
-                             .style(Style::default().fg(Color::Yellow).add_modifier(Modifier::BOLD))
-            ).style(Style::default().fg(Color::White));
-
-        terminal.draw(|f| {
-            f.render_widget(info_paragraph, area);
-        }).unwrap();
-    }
 }
 
 impl<'a> ManagerItems<'a> for MainView<'a> {
@@ -80,6 +355,7 @@ impl<'a> ManagerItems<'a> for MainView<'a> {
      }
 }
 
+#[allow(dead_code)]
 pub struct RenderizeComponents {
     stdout: Stdout,
     backend: CrosstermBackend<Stdout>,
@@ -163,7 +439,198 @@ impl<'a> RenderComponent<'a> for RenderizeComponents {
         .style(Style::default().fg(Color::White))
     }
 
+    fn input_form_component(input_form: &InputForm) -> Paragraph<'a> {
+        let mut lines = vec![];
+
+        for (i,field) in input_form.fields.iter().enumerate() {
+            let style = if i == input_form.active {
+                Style::default().fg(Color::Black).bg(Color::White).add_modifier(Modifier::BOLD)
+            } else {
+                Style::default().fg(Color::White)
+            };
+
+            let (label, value) = if field.masked {
+                (format!("{}: ",field.name), "*".repeat(field.value.chars().count()))
+            } else if input_form.placeholders {
+                (format!("{{{}}}: ",field.name), field.value.clone())
+            } else {
+                (format!("{}: ",field.name), field.value.clone())
+            };
+
+            lines.push(Spans::from(vec![
+                Span::styled(label, Style::default().fg(Color::Green).add_modifier(Modifier::BOLD)),
+                Span::styled(value, style)
+            ]));
+
+            if let Some(description) = &field.description {
+                lines.push(Spans::from(Span::raw(format!("  {}",description))));
+            }
+        }
+
+        lines.push(Spans::from(vec![]));
+        lines.push(Spans::from(vec![
+            Span::styled("Tab/Up/Down", Style::default().fg(Color::Green).add_modifier(Modifier::BOLD)),
+            Span::raw(" alterna campos, "),
+            Span::styled("Enter", Style::default().fg(Color::Green).add_modifier(Modifier::BOLD)),
+            Span::raw(" executa, "),
+            Span::styled("Esc", Style::default().fg(Color::Red).add_modifier(Modifier::BOLD)),
+            Span::raw(" cancela")
+        ]));
+
+        Paragraph::new(lines)
+    .block(
+           Block::default()
+                 .title(input_form.title.clone())
+                 .borders(Borders::ALL)
+                 .style(Style::default().fg(Color::Yellow).add_modifier(Modifier::BOLD))
+        )
+    }
+
+    fn host_key_component(host_key: &HostKey) -> Paragraph<'a> {
+        Paragraph::new(vec![
+            Spans::from(Span::raw(format!("O servidor {}:{} não está no known_hosts.",host_key.host(),host_key.port()))),
+            Spans::from(vec![]),
+            Spans::from(vec![
+                Span::raw(format!("{} ",host_key.key_type())),
+                Span::styled(host_key.fingerprint().to_string(), Style::default().fg(Color::Green).add_modifier(Modifier::BOLD))
+            ]),
+            Spans::from(vec![]),
+            Spans::from(Span::raw("Confira o fingerprint com o administrador antes de confiar na chave.")),
+            Spans::from(vec![
+                Span::styled("s", Style::default().fg(Color::Green).add_modifier(Modifier::BOLD)),
+                Span::raw(" registra a chave e conecta, "),
+                Span::styled("n/Esc", Style::default().fg(Color::Red).add_modifier(Modifier::BOLD)),
+                Span::raw(" cancela")
+            ])
+        ])
+    .block(
+           Block::default()
+                 .title("Chave de host desconhecida")
+                 .borders(Borders::ALL)
+                 .style(Style::default().fg(Color::Yellow).add_modifier(Modifier::BOLD))
+        )
+        .style(Style::default().fg(Color::White))
+    }
+
+    fn output_component(output_pane: &OutputPane, height: usize) -> Paragraph<'a> {
+        let lines: Vec<Spans> = output_pane.lines()
+                                           .iter()
+                                           .map(|(stream,line)| {
+                                               match stream {
+                                                   OutputStream::Stdout => Spans::from(Span::raw(line.clone())),
+                                                   OutputStream::Stderr => Spans::from(Span::styled(line.clone(), Style::default().fg(Color::Red)))
+                                               }
+                                           })
+                                           .collect();
 
+        Paragraph::new(lines)
+    .block(
+           Block::default()
+                 .title("Saída do comando (PageUp/PageDown)")
+                 .borders(Borders::ALL)
+                 .style(Style::default().fg(Color::Yellow).add_modifier(Modifier::BOLD))
+        )
+        .style(Style::default().fg(Color::White))
+        .scroll((output_pane.top_line(height) as u16, 0))
+    }
+
+    fn steps_component(title: &'a str, steps: &[(String,StepStatus)]) -> List<'a> {
+        let items: Vec<ListItem> = steps.iter()
+                                        .enumerate()
+                                        .map(|(i,(command,status))| {
+                                            ListItem::new(format!("{} {}. {}",status.symbol(),i + 1,command))
+                                                .style(Style::default().fg(status.color()))
+                                        })
+                                        .collect();
+
+        List::new(items)
+    .block(
+           Block::default()
+                 .title(title)
+                 .borders(Borders::ALL)
+                 .style(Style::default().fg(Color::Green).add_modifier(Modifier::ITALIC))
+        )
+    }
+
+    fn browser_component(browser: &FileBrowser, focused: bool) -> List<'a> {
+        let items: Vec<ListItem> = browser.entries()
+                                          .iter()
+                                          .map(|entry| {
+                                              if entry.is_dir() {
+                                                  ListItem::new(format!("▸ {}/",entry.name()))
+                                                      .style(Style::default().fg(Color::Cyan))
+                                              } else {
+                                                  let tail = if browser.following() == Some(entry.path()) { " [tail]" } else { "" };
+                                                  let size = entry.size().map(format_size).unwrap_or_default();
+                                                  ListItem::new(format!("  {} {}{}",entry.name(),size,tail))
+                                              }
+                                          })
+                                          .collect();
+
+        let highlight = if focused {
+            Style::default().fg(Color::Black).bg(Color::White).add_modifier(Modifier::BOLD)
+        } else {
+            Style::default().add_modifier(Modifier::BOLD)
+        };
+
+        List::new(items)
+    .block(
+           Block::default()
+                 .title(format!("Arquivos: {}",browser.path()))
+                 .borders(Borders::ALL)
+                 .style(Style::default().fg(Color::Green).add_modifier(Modifier::ITALIC))
+        )
+        .style(Style::default().fg(Color::White))
+        .highlight_style(highlight)
+    }
+
+    fn tunnels_component(panel: &TunnelPanel, focused: bool) -> List<'a> {
+        let items: Vec<ListItem> = panel.rows()
+                                        .iter()
+                                        .map(|row| {
+                                            ListItem::new(vec![
+                                                Spans::from(format!("{} / {}  {}",row.server,row.forward.name(),row.forward.label())),
+                                                Spans::from(Span::styled(format!("  {}",row.state.label()), Style::default().fg(row.state.color())))
+                                            ])
+                                        })
+                                        .collect();
+
+        let highlight = if focused {
+            Style::default().fg(Color::Black).bg(Color::White).add_modifier(Modifier::BOLD)
+        } else {
+            Style::default().add_modifier(Modifier::BOLD)
+        };
+
+        List::new(items)
+    .block(
+           Block::default()
+                 .title(format!("Túneis ({} ativos) - Enter abre/fecha",panel.active_count()))
+                 .borders(Borders::ALL)
+                 .style(Style::default().fg(Color::Green).add_modifier(Modifier::ITALIC))
+        )
+        .style(Style::default().fg(Color::White))
+        .highlight_style(highlight)
+    }
+
+    fn health_component(report: &HealthReport, config: &ServerConfig) -> Paragraph<'a> {
+        Paragraph::new(health::health_lines(report, config))
+    .block(
+           Block::default()
+                 .title("Saúde do servidor (h fecha)")
+                 .borders(Borders::ALL)
+                 .style(Style::default().fg(Color::Green).add_modifier(Modifier::ITALIC))
+        )
+        .style(Style::default().fg(Color::White))
+    }
+}
+
+/// Tamanho abreviado, usado na lista do navegador e no painel de saúde.
+fn format_size(bytes: u64) -> String {
+    match bytes {
+        0..=1023 => format!("{} B",bytes),
+        1024..=1048575 => format!("{:.1} KiB",bytes as f64 / 1024.0),
+        _ => format!("{:.1} MiB",bytes as f64 / 1048576.0)
+    }
 }
 
 impl RenderizeComponents {
@@ -182,7 +649,7 @@ impl RenderizeComponents {
         enable_raw_mode().unwrap();
 
        loop {
-           self.terminal.draw(|f| {
+           self.terminal.draw(|_f| {
            }).unwrap();
        }
     }
@@ -196,3 +663,51 @@ impl RenderizeComponents {
 //    let mut terminal = Terminal::new(backend).unwrap();
 //}
 
+#[test]
+fn test_output_pane_push_chunks() {
+    let mut pane = OutputPane::default();
+
+    pane.push(OutputStream::Stdout, "Cloning into 'repo'");
+    pane.push(OutputStream::Stdout, "...\n\ndone\n");
+    pane.push(OutputStream::Stderr, "fatal: erro\n");
+
+    assert_eq!(pane.lines(),&vec![
+        (OutputStream::Stdout,String::from("Cloning into 'repo'...")),
+        (OutputStream::Stdout,String::new()),
+        (OutputStream::Stdout,String::from("done")),
+        (OutputStream::Stderr,String::from("fatal: erro"))
+    ]);
+    assert_eq!(pane.top_line(2),2);
+
+    pane.clear();
+    pane.push(OutputStream::Stdout, "progresso 50%");
+    pane.push(OutputStream::Stderr, "\n");
+    pane.push(OutputStream::Stderr, "aviso\n");
+    assert_eq!(pane.lines(),&vec![
+        (OutputStream::Stdout,String::from("progresso 50%")),
+        (OutputStream::Stderr,String::from("aviso"))
+    ]);
+}
+
+#[test]
+fn test_sidebar_rows_groups_and_collapse() {
+    let config = ConfigYaml::new("config.yaml").unwrap();
+
+    let labels = |rows: Vec<SidebarRow>| rows.iter().map(|row| row.label(true, &[String::from("Servidor 1")])).collect::<Vec<_>>();
+
+    assert_eq!(labels(sidebar_rows(&config, &[])), vec![
+        "▾ Produção (1)",
+        "  Servidor 2",
+        "▾ Apache (1)",
+        "  Servidor 2",
+        "▾ Sem grupo (1)",
+        "  [x] Servidor 1",
+    ]);
+
+    assert_eq!(labels(sidebar_rows(&config, &[String::from("Produção"), String::from("Sem grupo")])), vec![
+        "▸ Produção (1)",
+        "▾ Apache (1)",
+        "  Servidor 2",
+        "▸ Sem grupo (1)",
+    ]);
+}
@@ -0,0 +1,95 @@
+use std::io::{self, Write};
+
+use crossterm::{
+    cursor::Show,
+    event::{DisableMouseCapture, EnableMouseCapture, KeyCode, KeyEvent, KeyEventKind, KeyModifiers},
+    execute,
+    terminal::{EnterAlternateScreen, LeaveAlternateScreen},
+};
+
+/// Bytes que um terminal xterm enviaria para a tecla, repassados ao shell remoto.
+/// Teclas sem equivalente (mídia, modificadores sozinhos) não geram nada.
+pub fn key_bytes(key: &KeyEvent) -> Vec<u8> {
+    if key.kind == KeyEventKind::Release {
+        return vec![];
+    }
+
+    let mut bytes: Vec<u8> = match key.code {
+        KeyCode::Char(c) if key.modifiers.contains(KeyModifiers::CONTROL) => match c.to_ascii_lowercase() {
+            c @ 'a'..='z' => vec![c as u8 - b'a' + 1],
+            '@' | ' ' => vec![0],
+            '[' => vec![0x1b],
+            '\\' => vec![0x1c],
+            ']' => vec![0x1d],
+            '^' => vec![0x1e],
+            '_' => vec![0x1f],
+            c => c.to_string().into_bytes()
+        },
+        KeyCode::Char(c) => c.to_string().into_bytes(),
+        KeyCode::Enter => vec![b'\r'],
+        KeyCode::Backspace => vec![0x7f],
+        KeyCode::Tab => vec![b'\t'],
+        KeyCode::BackTab => b"\x1b[Z".to_vec(),
+        KeyCode::Esc => vec![0x1b],
+        KeyCode::Up => b"\x1b[A".to_vec(),
+        KeyCode::Down => b"\x1b[B".to_vec(),
+        KeyCode::Right => b"\x1b[C".to_vec(),
+        KeyCode::Left => b"\x1b[D".to_vec(),
+        KeyCode::Home => b"\x1b[H".to_vec(),
+        KeyCode::End => b"\x1b[F".to_vec(),
+        KeyCode::Insert => b"\x1b[2~".to_vec(),
+        KeyCode::Delete => b"\x1b[3~".to_vec(),
+        KeyCode::PageUp => b"\x1b[5~".to_vec(),
+        KeyCode::PageDown => b"\x1b[6~".to_vec(),
+        KeyCode::F(n) => match n {
+            1 => b"\x1bOP".to_vec(),
+            2 => b"\x1bOQ".to_vec(),
+            3 => b"\x1bOR".to_vec(),
+            4 => b"\x1bOS".to_vec(),
+            5 => b"\x1b[15~".to_vec(),
+            6..=10 => format!("\x1b[{}~", n + 11).into_bytes(),
+            11..=12 => format!("\x1b[{}~", n + 12).into_bytes(),
+            _ => vec![]
+        },
+        KeyCode::Null => vec![0],
+        _ => vec![]
+    };
+
+    // Alt prefixa a tecla com ESC, como no xterm com `metaSendsEscape`.
+    if key.modifiers.contains(KeyModifiers::ALT) && !bytes.is_empty() {
+        bytes.insert(0, 0x1b);
+    }
+    bytes
+}
+
+/// Sai da tela alternativa da TUI, executa `run` na tela normal e volta à TUI mesmo que `run`
+/// falhe. O modo raw continua ligado, como o shell remoto precisa.
+pub fn suspend_tui<W: Write, T>(out: &mut W, run: impl FnOnce() -> T) -> io::Result<T> {
+    execute!(out, LeaveAlternateScreen, DisableMouseCapture, Show)?;
+    let result = run();
+    execute!(out, EnterAlternateScreen, EnableMouseCapture)?;
+    Ok(result)
+}
+
+#[test]
+fn test_suspend_tui_restores_screen() {
+    let mut out: Vec<u8> = vec![];
+    let result: io::Result<()> = suspend_tui(&mut out, || Err(io::Error::other("shell interrompido"))).unwrap();
+    assert!(result.is_err());
+
+    let out = String::from_utf8(out).unwrap();
+    let (left, entered) = (out.find("\x1b[?1049l").unwrap(), out.find("\x1b[?1049h").unwrap());
+    assert!(left < entered);
+}
+
+#[test]
+fn test_key_bytes() {
+    let key = |code, modifiers| key_bytes(&KeyEvent::new(code, modifiers));
+
+    assert_eq!(key(KeyCode::Char('ç'), KeyModifiers::NONE), "ç".as_bytes());
+    assert_eq!(key(KeyCode::Char('c'), KeyModifiers::CONTROL), vec![3]);
+    assert_eq!(key(KeyCode::Char('b'), KeyModifiers::ALT), b"\x1bb");
+    assert_eq!(key(KeyCode::Up, KeyModifiers::NONE), b"\x1b[A");
+    assert_eq!(key(KeyCode::F(10), KeyModifiers::NONE), b"\x1b[21~");
+    assert_eq!(key(KeyCode::F(12), KeyModifiers::NONE), b"\x1b[24~");
+}
@@ -0,0 +1,122 @@
+use tui::style::Color;
+
+use crate::parser::PortForward;
+
+/// Situação de um túnel no painel de túneis.
+#[derive(Debug,PartialEq, Eq,Clone)]
+pub enum TunnelState {
+    Stopped,
+    Starting,
+    Active { connections: usize },
+    Failed(String),
+}
+
+impl TunnelState {
+    /// Aberto ou abrindo; `Enter` nesse estado para o túnel.
+    pub fn is_running(&self) -> bool {
+        matches!(self, TunnelState::Starting | TunnelState::Active { .. })
+    }
+
+    pub(super) fn label(&self) -> String {
+        match self {
+            TunnelState::Stopped => String::from("○ parado"),
+            TunnelState::Starting => String::from("▶ abrindo..."),
+            TunnelState::Active { connections } => format!("● ativo ({} conexões)", connections),
+            TunnelState::Failed(error) => format!("✖ {}", error),
+        }
+    }
+
+    pub(super) fn color(&self) -> Color {
+        match self {
+            TunnelState::Stopped => Color::DarkGray,
+            TunnelState::Starting => Color::Yellow,
+            TunnelState::Active { .. } => Color::Green,
+            TunnelState::Failed(_) => Color::Red,
+        }
+    }
+}
+
+/// Túnel exibido no painel, com o servidor a que pertence.
+#[derive(Debug,PartialEq, Eq,Clone)]
+pub struct TunnelRow {
+    pub server: String,
+    pub forward: PortForward,
+    pub state: TunnelState,
+}
+
+/// Painel com os túneis do servidor selecionado e os que continuam ativos em outros servidores.
+#[derive(Default)]
+pub struct TunnelPanel {
+    rows: Vec<TunnelRow>,
+    selected: usize,
+}
+
+impl TunnelPanel {
+    /// Mostra os túneis declarados por `server`, mantendo a situação dos que já estavam na lista.
+    /// Túneis de outros servidores só continuam enquanto estiverem abertos.
+    pub fn show_server(&mut self, server: &str, forwards: Vec<PortForward>) {
+        let mut rows: Vec<TunnelRow> = forwards.into_iter()
+                                               .map(|forward| {
+                                                   let state = self.rows.iter()
+                                                                        .find(|row| row.server == server && row.forward.name() == forward.name())
+                                                                        .map(|row| row.state.clone())
+                                                                        .unwrap_or(TunnelState::Stopped);
+                                                   TunnelRow { server: server.to_string(), forward, state }
+                                               })
+                                               .collect();
+
+        rows.extend(self.rows.drain(..).filter(|row| row.server != server && row.state.is_running()));
+
+        self.rows = rows;
+        self.selected = self.selected.min(self.rows.len().saturating_sub(1));
+    }
+
+    pub fn rows(&self) -> &Vec<TunnelRow> {
+        &self.rows
+    }
+
+    pub fn selected(&self) -> usize {
+        self.selected
+    }
+
+    pub fn selected_row(&self) -> Option<&TunnelRow> {
+        self.rows.get(self.selected)
+    }
+
+    pub fn select_next(&mut self) {
+        if self.selected + 1 < self.rows.len() {
+            self.selected += 1;
+        }
+    }
+
+    pub fn select_previous(&mut self) {
+        self.selected = self.selected.saturating_sub(1);
+    }
+
+    pub fn set_state(&mut self, server: &str, name: &str, state: TunnelState) {
+        if let Some(row) = self.rows.iter_mut().find(|row| row.server == server && row.forward.name() == name) {
+            row.state = state;
+        }
+    }
+
+    pub fn active_count(&self) -> usize {
+        self.rows.iter().filter(|row| matches!(row.state, TunnelState::Active { .. })).count()
+    }
+}
+
+#[test]
+fn test_tunnel_panel_keeps_running_tunnels() {
+    let forward = |name: &str| serde_yaml_ng::from_str::<PortForward>(&format!("name: {}\nbind_port: 15432\nhost: db\nport: 5432\n", name)).unwrap();
+
+    let mut panel = TunnelPanel::default();
+    panel.show_server("Servidor 1", vec![forward("Postgres"), forward("Redis")]);
+    panel.set_state("Servidor 1", "Postgres", TunnelState::Active { connections: 0 });
+
+    panel.show_server("Servidor 2", vec![forward("MySQL")]);
+    let names: Vec<(&str, &str)> = panel.rows().iter().map(|row| (row.server.as_str(), row.forward.name())).collect();
+    assert_eq!(names, vec![("Servidor 2", "MySQL"), ("Servidor 1", "Postgres")]);
+
+    panel.show_server("Servidor 1", vec![forward("Postgres"), forward("Redis")]);
+    assert_eq!(panel.rows()[0].state, TunnelState::Active { connections: 0 });
+    assert_eq!(panel.active_count(), 1);
+}